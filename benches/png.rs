@@ -0,0 +1,87 @@
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use encodeme::chunk::{chunk_crc, Chunk};
+use encodeme::chunk_type::ChunkType;
+use encodeme::png::Png;
+use std::str::FromStr;
+
+// Build a png of `size` bytes of IDAT split into 64 KiB chunks, roughly the
+// layout encoders produce for large images.
+fn large_png(size: usize) -> Vec<u8> {
+    let idat = ChunkType::from_str("IDAT").unwrap();
+    let mut chunks = vec![Chunk::new(
+        ChunkType::from_str("IHDR").unwrap(),
+        vec![0; 13],
+    )];
+    let mut remaining = size;
+    while remaining > 0 {
+        let len = remaining.min(64 * 1024);
+        chunks.push(Chunk::new(idat, vec![0xa5; len]));
+        remaining -= len;
+    }
+    chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()));
+    Png::from_chunks(chunks).as_bytes()
+}
+
+// The parser as it was before chunks were backed by `Bytes`: every chunk's
+// data is copied into its own `Vec`.
+fn copying_parse(value: &[u8]) -> Vec<(u32, [u8; 4], Vec<u8>, u32)> {
+    let mut chunks = Vec::new();
+    let mut offset = 8;
+    while offset + 12 <= value.len() {
+        let len = u32::from_be_bytes(value[offset..offset + 4].try_into().unwrap()) as usize;
+        let chunk_type: [u8; 4] = value[offset + 4..offset + 8].try_into().unwrap();
+        let data = value[offset + 8..offset + 8 + len].to_vec();
+        let end = offset + 12 + len;
+        let crc = u32::from_be_bytes(value[end - 4..end].try_into().unwrap());
+        chunks.push((len as u32, chunk_type, data, crc));
+        offset = end;
+    }
+    chunks
+}
+
+// `Chunk::new` as it was: concatenate type and data, then hash.
+fn concatenating_crc(chunk_type: &ChunkType, data: &[u8]) -> u32 {
+    let crccalc: Vec<u8> = chunk_type
+        .bytes()
+        .iter()
+        .chain(data.iter())
+        .copied()
+        .collect();
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(crccalc.as_slice());
+    hasher.finalize()
+}
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for size in [1 << 20, 16 << 20] {
+        let bytes = large_png(size);
+        let shared = Bytes::from(bytes.clone());
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_with_input(BenchmarkId::new("copying", size), &bytes, |b, bytes| {
+            b.iter(|| copying_parse(bytes))
+        });
+        group.bench_with_input(
+            BenchmarkId::new("from_bytes", size),
+            &shared,
+            |b, shared| b.iter(|| Png::from_bytes(shared.clone()).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+fn crc(c: &mut Criterion) {
+    let mut group = c.benchmark_group("crc");
+    let chunk_type = ChunkType::from_str("IDAT").unwrap();
+    let data = vec![0xa5; 4 << 20];
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("concatenating", |b| {
+        b.iter(|| concatenating_crc(&chunk_type, &data))
+    });
+    group.bench_function("incremental", |b| b.iter(|| chunk_crc(&chunk_type, &data)));
+    group.finish();
+}
+
+criterion_group!(benches, parse, crc);
+criterion_main!(benches);
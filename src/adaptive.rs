@@ -0,0 +1,156 @@
+use crate::lsb::keyed_rng;
+use crate::pixels::Image;
+use rand::Rng;
+
+// Content-adaptive ordering of the samples used for LSB embedding. The cost
+// of changing a sample is low where its neighbourhood is textured and high in
+// flat areas, where changes stand out. Texture is the variance of the 3x3
+// neighbourhood in the same channel, measured with the embedded low bits
+// masked off, so the decoder computes the same values from the stego image.
+//
+// Samples are ordered by weighted random sampling without replacement
+// (Efraimidis-Spirakis): every sample draws u from the keyed generator and is
+// ranked by u^(1/w) with w = (1 + variance)^strength. A strength of 0 gives a
+// plain random order; the higher it is, the more the busiest areas fill first.
+
+// Variance of the 3x3 neighbourhood of every sample, ignoring the low `bits`.
+pub fn texture(image: &Image, bits: u8) -> Vec<f64> {
+    let channels = image.channels();
+    let width = image.header.width as usize;
+    let height = image.header.height as usize;
+    let value = |x: usize, y: usize, c: usize| {
+        (image.samples[(y * width + x) * channels + c] >> bits) as f64
+    };
+    let mut texture = vec![0.0; image.samples.len()];
+    for y in 0..height {
+        for x in 0..width {
+            for c in 0..channels {
+                let (mut sum, mut sum_sq, mut count) = (0.0, 0.0, 0.0);
+                for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                    for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                        let v = value(nx, ny, c);
+                        sum += v;
+                        sum_sq += v * v;
+                        count += 1.0;
+                    }
+                }
+                let mean = sum / count;
+                texture[(y * width + x) * channels + c] = sum_sq / count - mean * mean;
+            }
+        }
+    }
+    texture
+}
+
+// Reorder `positions` so that textured samples come first, as described above.
+pub fn order(image: &Image, positions: &mut Vec<usize>, bits: u8, strength: f64, key: &[u8]) {
+    let texture = texture(image, bits);
+    let mut rng = keyed_rng(key);
+    let mut ranked: Vec<(f64, usize)> = positions
+        .iter()
+        .map(|&position| {
+            let weight = (1.0 + texture[position]).powf(strength);
+            let u: f64 = rng.gen_range(f64::MIN_POSITIVE..1.0);
+            // ln(u^(1/w)) keeps the ranking without underflowing to 0.
+            (u.ln() / weight, position)
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+    positions.clear();
+    positions.extend(ranked.into_iter().map(|(_, position)| position));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsb::{self, LsbOptions};
+    use crate::pixels::ImageHeader;
+
+    // The left half is flat, the right half noisy.
+    fn testing_image() -> Image {
+        let header = ImageHeader {
+            width: 32,
+            height: 32,
+            bit_depth: 8,
+            color_type: 0,
+            interlace: 0,
+        };
+        let samples = (0..32 * 32)
+            .map(|i| {
+                if i % 32 < 16 {
+                    128
+                } else {
+                    ((i * 7919) % 251) as u16
+                }
+            })
+            .collect();
+        Image { header, samples }
+    }
+
+    fn adaptive(strength: f64) -> LsbOptions {
+        LsbOptions {
+            adaptive: Some(strength),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_texture() {
+        let texture = texture(&testing_image(), 1);
+        assert_eq!(texture[5 * 32 + 4], 0.0);
+        assert!(texture[5 * 32 + 24] > 100.0);
+    }
+
+    #[test]
+    fn test_prefers_textured_samples() {
+        let image = testing_image();
+        let positions = lsb::positions(&image, &adaptive(4.0));
+        let textured = positions[..256].iter().filter(|&&i| i % 32 >= 17).count();
+        assert!(textured > 250, "{} of 256 in the textured half", textured);
+    }
+
+    #[test]
+    fn test_zero_strength_has_no_preference() {
+        let image = testing_image();
+        let positions = lsb::positions(&image, &adaptive(0.0));
+        let textured = positions[..256].iter().filter(|&&i| i % 32 >= 16).count();
+        assert!((80..176).contains(&textured));
+    }
+
+    #[test]
+    fn test_reproducible_after_embedding() {
+        let original = testing_image();
+        let mut image = original.clone();
+        let options = LsbOptions {
+            key: Some(b"hunter2".to_vec()),
+            ..adaptive(2.0)
+        };
+        lsb::embed(&mut image, b"My secret message", &options).unwrap();
+        assert_eq!(
+            lsb::positions(&image, &options),
+            lsb::positions(&original, &options)
+        );
+        assert_eq!(
+            lsb::extract(&image, &options).unwrap(),
+            b"My secret message"
+        );
+        let flat_changes = original
+            .samples
+            .iter()
+            .zip(&image.samples)
+            .enumerate()
+            .filter(|(i, (a, b))| i % 32 < 15 && a != b)
+            .count();
+        assert_eq!(flat_changes, 0);
+    }
+
+    #[test]
+    fn test_matching_is_rejected() {
+        let mut image = testing_image();
+        let options = LsbOptions {
+            matching: true,
+            ..adaptive(1.0)
+        };
+        assert!(lsb::embed(&mut image, b"My secret message", &options).is_err());
+    }
+}
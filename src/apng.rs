@@ -0,0 +1,457 @@
+use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png};
+use anyhow::Result;
+use bytes::Bytes;
+use std::fmt;
+use std::str::FromStr;
+
+// Typed view of an animated png: the acTL animation control chunk and the
+// frames described by fcTL, with their image data gathered from IDAT and fdAT.
+// Sequence numbers are checked on parse and regenerated on write.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnimationControl {
+    pub num_frames: u32,
+    pub num_plays: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisposeOp {
+    None,
+    Background,
+    Previous,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendOp {
+    Source,
+    Over,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameControl {
+    pub sequence_number: u32,
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: u32,
+    pub y_offset: u32,
+    pub delay_num: u16,
+    pub delay_den: u16,
+    pub dispose_op: DisposeOp,
+    pub blend_op: BlendOp,
+}
+
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub control: FrameControl,
+    // Whether the frame's data is stored in IDAT, i.e. it is the default image.
+    pub is_default_image: bool,
+    // The frame's image data, one entry per IDAT or fdAT chunk, without the
+    // fdAT sequence number.
+    pub data: Vec<Bytes>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Animation {
+    pub control: AnimationControl,
+    pub frames: Vec<Frame>,
+}
+
+fn chunk_type(name: &str) -> ChunkType {
+    ChunkType::from_str(name).unwrap()
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+// The chunks that make up the animation and are rewritten by `Animation::apply`.
+pub fn is_animation_chunk(chunk_type: &ChunkType) -> bool {
+    ["acTL", "fcTL", "fdAT", "IDAT"]
+        .iter()
+        .any(|name| chunk_type.bytes() == name.as_bytes())
+}
+
+impl TryFrom<&[u8]> for AnimationControl {
+    type Error = anyhow::Error;
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        if value.len() != 8 {
+            return Err(anyhow::anyhow!("The acTL chunk must be 8 bytes long"));
+        }
+        Ok(AnimationControl {
+            num_frames: u32_at(value, 0),
+            num_plays: u32_at(value, 4),
+        })
+    }
+}
+
+impl AnimationControl {
+    pub fn as_bytes(&self) -> Vec<u8> {
+        [self.num_frames.to_be_bytes(), self.num_plays.to_be_bytes()].concat()
+    }
+}
+
+impl TryFrom<u8> for DisposeOp {
+    type Error = anyhow::Error;
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(DisposeOp::None),
+            1 => Ok(DisposeOp::Background),
+            2 => Ok(DisposeOp::Previous),
+            _ => Err(anyhow::anyhow!("Invalid dispose op {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for DisposeOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisposeOp::None => write!(f, "none"),
+            DisposeOp::Background => write!(f, "background"),
+            DisposeOp::Previous => write!(f, "previous"),
+        }
+    }
+}
+
+impl TryFrom<u8> for BlendOp {
+    type Error = anyhow::Error;
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(BlendOp::Source),
+            1 => Ok(BlendOp::Over),
+            _ => Err(anyhow::anyhow!("Invalid blend op {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for BlendOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlendOp::Source => write!(f, "source"),
+            BlendOp::Over => write!(f, "over"),
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for FrameControl {
+    type Error = anyhow::Error;
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        if value.len() != 26 {
+            return Err(anyhow::anyhow!("The fcTL chunk must be 26 bytes long"));
+        }
+        let control = FrameControl {
+            sequence_number: u32_at(value, 0),
+            width: u32_at(value, 4),
+            height: u32_at(value, 8),
+            x_offset: u32_at(value, 12),
+            y_offset: u32_at(value, 16),
+            delay_num: u16_at(value, 20),
+            delay_den: u16_at(value, 22),
+            dispose_op: DisposeOp::try_from(value[24])?,
+            blend_op: BlendOp::try_from(value[25])?,
+        };
+        if control.width == 0 || control.height == 0 {
+            return Err(anyhow::anyhow!(
+                "Frame {} has an empty region",
+                control.sequence_number
+            ));
+        }
+        Ok(control)
+    }
+}
+
+impl FrameControl {
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(26);
+        bytes.extend_from_slice(&self.sequence_number.to_be_bytes());
+        bytes.extend_from_slice(&self.width.to_be_bytes());
+        bytes.extend_from_slice(&self.height.to_be_bytes());
+        bytes.extend_from_slice(&self.x_offset.to_be_bytes());
+        bytes.extend_from_slice(&self.y_offset.to_be_bytes());
+        bytes.extend_from_slice(&self.delay_num.to_be_bytes());
+        bytes.extend_from_slice(&self.delay_den.to_be_bytes());
+        bytes.push(self.dispose_op as u8);
+        bytes.push(self.blend_op as u8);
+        bytes
+    }
+
+    // The delay in seconds. A denominator of 0 means 1/100 s per the spec.
+    pub fn delay(&self) -> f64 {
+        let den = if self.delay_den == 0 {
+            100
+        } else {
+            self.delay_den
+        };
+        self.delay_num as f64 / den as f64
+    }
+}
+
+impl Animation {
+    // Returns None for a png without an acTL chunk.
+    pub fn from_chunks(chunks: &[Chunk]) -> Result<Option<Animation>> {
+        let (actl, fctl, fdat, idat) = (
+            chunk_type("acTL"),
+            chunk_type("fcTL"),
+            chunk_type("fdAT"),
+            chunk_type("IDAT"),
+        );
+        let control = match chunks.iter().find(|chunk| chunk.chunk_type == actl) {
+            Some(chunk) => AnimationControl::try_from(chunk.data())?,
+            None => return Ok(None),
+        };
+
+        let mut frames: Vec<Frame> = Vec::new();
+        let mut next_sequence = 0;
+        let mut seen_idat = false;
+        let mut check_sequence = |sequence_number: u32| {
+            if sequence_number != next_sequence {
+                return Err(anyhow::anyhow!(
+                    "Expected sequence number {}, found {}",
+                    next_sequence,
+                    sequence_number
+                ));
+            }
+            next_sequence += 1;
+            Ok(())
+        };
+        for chunk in chunks {
+            if chunk.chunk_type == fctl {
+                let control = FrameControl::try_from(chunk.data())?;
+                check_sequence(control.sequence_number)?;
+                frames.push(Frame {
+                    control,
+                    is_default_image: false,
+                    data: Vec::new(),
+                });
+            } else if chunk.chunk_type == idat {
+                // IDAT belongs to the first frame only when its fcTL came first;
+                // otherwise the default image is not part of the animation.
+                if frames.len() == 1 && (frames[0].is_default_image || !seen_idat) {
+                    frames[0].is_default_image = true;
+                    frames[0].data.push(chunk.data.clone());
+                }
+                seen_idat = true;
+            } else if chunk.chunk_type == fdat {
+                if chunk.data().len() < 4 {
+                    return Err(anyhow::anyhow!("The fdAT chunk is too short"));
+                }
+                check_sequence(u32_at(chunk.data(), 0))?;
+                let frame = match frames.last_mut() {
+                    Some(frame) if seen_idat && !frame.is_default_image => frame,
+                    _ => return Err(anyhow::anyhow!("fdAT chunk without a preceding fcTL")),
+                };
+                frame.data.push(chunk.data.slice(4..));
+            }
+        }
+        if frames.len() != control.num_frames as usize {
+            return Err(anyhow::anyhow!(
+                "acTL declares {} frames but {} were found",
+                control.num_frames,
+                frames.len()
+            ));
+        }
+        if let Some(frame) = frames.iter().find(|frame| frame.data.is_empty()) {
+            return Err(anyhow::anyhow!(
+                "Frame {} has no image data",
+                frame.control.sequence_number
+            ));
+        }
+        Ok(Some(Animation { control, frames }))
+    }
+
+    pub fn from_png(png: &Png) -> Result<Option<Animation>> {
+        Animation::from_chunks(png.chunks())
+    }
+
+    // The chunks encoding the animation, from acTL to the last fdAT, with
+    // sequence numbers assigned in order. When the default image is not the
+    // first frame, `default_image` holds the IDAT chunks that precede it.
+    pub fn to_chunks(&self, default_image: &[Chunk]) -> Vec<Chunk> {
+        let control = AnimationControl {
+            num_frames: self.frames.len() as u32,
+            ..self.control
+        };
+        let mut chunks = vec![Chunk::new(chunk_type("acTL"), control.as_bytes())];
+        if !self
+            .frames
+            .first()
+            .is_some_and(|frame| frame.is_default_image)
+        {
+            chunks.extend_from_slice(default_image);
+        }
+        let mut sequence_number = 0;
+        for frame in &self.frames {
+            let control = FrameControl {
+                sequence_number,
+                ..frame.control
+            };
+            chunks.push(Chunk::new(chunk_type("fcTL"), control.as_bytes()));
+            sequence_number += 1;
+            for data in &frame.data {
+                if frame.is_default_image {
+                    chunks.push(Chunk::from_data(chunk_type("IDAT"), data.clone()));
+                } else {
+                    let mut fdat = Vec::with_capacity(data.len() + 4);
+                    fdat.extend_from_slice(&sequence_number.to_be_bytes());
+                    fdat.extend_from_slice(data);
+                    chunks.push(Chunk::new(chunk_type("fdAT"), fdat));
+                    sequence_number += 1;
+                }
+            }
+        }
+        chunks
+    }
+
+    // Replace the animation chunks of `png` with this animation. Every other
+    // chunk stays where it was, so PLTE, tRNS and the like that follow an
+    // early acTL still come before the image data. acTL and the frames go
+    // where the first fcTL or IDAT was.
+    pub fn apply(&self, png: &mut Png) {
+        let (idat, fctl) = (chunk_type("IDAT"), chunk_type("fcTL"));
+        let chunks = png.chunks();
+        let start = chunks
+            .iter()
+            .position(|chunk| chunk.chunk_type == fctl || chunk.chunk_type == idat)
+            .unwrap_or(chunks.len());
+        let default_image: Vec<Chunk> = chunks
+            .iter()
+            .filter(|chunk| chunk.chunk_type == idat)
+            .cloned()
+            .collect();
+        let mut rewritten = Vec::with_capacity(chunks.len());
+        for (i, chunk) in chunks.iter().enumerate() {
+            if i == start {
+                rewritten.extend(self.to_chunks(&default_image));
+            }
+            if !is_animation_chunk(&chunk.chunk_type) {
+                rewritten.push(chunk.clone());
+            }
+        }
+        if start == chunks.len() {
+            rewritten.extend(self.to_chunks(&default_image));
+        }
+        png.set_chunks(rewritten);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame_control(sequence_number: u32) -> FrameControl {
+        FrameControl {
+            sequence_number,
+            width: 4,
+            height: 4,
+            x_offset: 0,
+            y_offset: 0,
+            delay_num: 1,
+            delay_den: 10,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+        }
+    }
+
+    fn fdat(sequence_number: u32, data: &[u8]) -> Chunk {
+        let data = [&sequence_number.to_be_bytes()[..], data].concat();
+        Chunk::new(chunk_type("fdAT"), data)
+    }
+
+    fn testing_chunks() -> Vec<Chunk> {
+        let control = AnimationControl {
+            num_frames: 2,
+            num_plays: 0,
+        };
+        vec![
+            Chunk::new(chunk_type("IHDR"), vec![0; 13]),
+            Chunk::new(chunk_type("acTL"), control.as_bytes()),
+            Chunk::new(chunk_type("fcTL"), frame_control(0).as_bytes()),
+            Chunk::new(chunk_type("IDAT"), b"first frame".to_vec()),
+            Chunk::new(chunk_type("fcTL"), frame_control(1).as_bytes()),
+            fdat(2, b"second "),
+            fdat(3, b"frame"),
+            Chunk::new(chunk_type("IEND"), Vec::new()),
+        ]
+    }
+
+    #[test]
+    fn test_parse_frames() {
+        let animation = Animation::from_chunks(&testing_chunks()).unwrap().unwrap();
+        assert_eq!(animation.control.num_frames, 2);
+        assert_eq!(animation.frames.len(), 2);
+        assert!(animation.frames[0].is_default_image);
+        assert_eq!(&animation.frames[0].data[0][..], b"first frame");
+        assert_eq!(animation.frames[1].data.len(), 2);
+        assert_eq!(&animation.frames[1].data[1][..], b"frame");
+        assert_eq!(animation.frames[1].control.delay(), 0.1);
+    }
+
+    #[test]
+    fn test_not_animated() {
+        let chunks = vec![Chunk::new(chunk_type("IHDR"), vec![0; 13])];
+        assert!(Animation::from_chunks(&chunks).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_apply_round_trip() {
+        let mut png = Png::from_chunks(testing_chunks());
+        let expected = png.as_bytes();
+        Animation::from_png(&png).unwrap().unwrap().apply(&mut png);
+        assert_eq!(png.as_bytes(), expected);
+    }
+
+    #[test]
+    fn test_apply_keeps_chunks_before_image_data() {
+        let mut chunks = testing_chunks();
+        chunks.insert(2, Chunk::new(chunk_type("PLTE"), vec![0; 6]));
+        chunks.insert(3, Chunk::new(chunk_type("tRNS"), vec![0]));
+        let mut png = Png::from_chunks(chunks);
+        Animation::from_png(&png).unwrap().unwrap().apply(&mut png);
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+        assert_eq!(
+            types,
+            ["IHDR", "PLTE", "tRNS", "acTL", "fcTL", "IDAT", "fcTL", "fdAT", "fdAT", "IEND"]
+        );
+        assert_eq!(Animation::from_png(&png).unwrap().unwrap().frames.len(), 2);
+    }
+
+    #[test]
+    fn test_apply_renumbers_frames() {
+        let mut png = Png::from_chunks(testing_chunks());
+        let mut animation = Animation::from_png(&png).unwrap().unwrap();
+        animation.frames.swap(0, 1);
+        animation.frames[0].is_default_image = false;
+        animation.frames[1].is_default_image = false;
+        animation.apply(&mut png);
+        let animation = Animation::from_png(&png).unwrap().unwrap();
+        assert!(!animation.frames[0].is_default_image);
+        assert_eq!(animation.frames[0].control.sequence_number, 0);
+        assert_eq!(&animation.frames[0].data[0][..], b"second ");
+        assert_eq!(animation.frames[1].control.sequence_number, 3);
+    }
+
+    #[test]
+    fn test_bad_sequence_number() {
+        let mut chunks = testing_chunks();
+        chunks[6] = fdat(5, b"frame");
+        assert!(Animation::from_chunks(&chunks).is_err());
+    }
+
+    #[test]
+    fn test_frame_count_mismatch() {
+        let mut chunks = testing_chunks();
+        let control = AnimationControl {
+            num_frames: 3,
+            num_plays: 0,
+        };
+        chunks[1] = Chunk::new(chunk_type("acTL"), control.as_bytes());
+        assert!(Animation::from_chunks(&chunks).is_err());
+    }
+}
@@ -0,0 +1,404 @@
+use crate::{
+    apng::{is_animation_chunk, Animation},
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    lsb::{self, LsbOptions},
+    pixels::{Image, ImageHeader},
+    png::Png,
+};
+use anyhow::Result;
+use bytes::Bytes;
+
+// Methods that spread a message over the frames of an animated png.
+//
+// With chunks, every frame is followed by a chunk of the chosen type holding
+// the sequence number of the frame's fcTL and its share of the message. With
+// LSB, every frame stores its share in the low bits of its own pixels. Either
+// way the shares are put back together in sequence number order.
+
+fn animation(png: &Png) -> Result<Animation> {
+    Animation::from_png(png)?.ok_or_else(|| anyhow::anyhow!("The PNG is not animated"))
+}
+
+// Split `payload` into one share per weight, proportionally to the weights.
+fn split<'a>(payload: &'a [u8], weights: &[usize]) -> Vec<&'a [u8]> {
+    let total: usize = weights.iter().sum::<usize>().max(1);
+    let mut shares = Vec::with_capacity(weights.len());
+    let (mut start, mut acc) = (0, 0);
+    for weight in weights {
+        acc += weight;
+        let end = payload.len() * acc / total;
+        shares.push(&payload[start..end]);
+        start = end;
+    }
+    shares
+}
+
+pub fn embed_chunks(png: &mut Png, chunk_type: ChunkType, payload: &[u8]) -> Result<()> {
+    let animation = animation(png)?;
+    // Shares of two messages can't be told apart once they are interleaved.
+    if png
+        .chunks()
+        .iter()
+        .any(|chunk| chunk.chunk_type == chunk_type)
+    {
+        return Err(anyhow::anyhow!(
+            "The image already holds {} chunks",
+            chunk_type
+        ));
+    }
+    let weights = vec![1; animation.frames.len()];
+    let mut shares = split(payload, &weights).into_iter();
+    let fctl: ChunkType = "fcTL".parse()?;
+
+    // A share goes right before the next frame's fcTL, or after the last
+    // animation chunk for the final frame.
+    let chunks = png.chunks();
+    let last = chunks
+        .iter()
+        .rposition(|chunk| is_animation_chunk(&chunk.chunk_type))
+        .unwrap_or(0);
+    let mut rewritten = Vec::with_capacity(chunks.len() + animation.frames.len());
+    let mut sequence_number = None;
+    let mut insert = |rewritten: &mut Vec<Chunk>, sequence_number: u32| {
+        let share = shares.next().unwrap_or_default();
+        let data = [&sequence_number.to_be_bytes()[..], share].concat();
+        rewritten.push(Chunk::new(chunk_type, data));
+    };
+    for (i, chunk) in chunks.iter().enumerate() {
+        if chunk.chunk_type == fctl {
+            if let Some(sequence_number) = sequence_number {
+                insert(&mut rewritten, sequence_number);
+            }
+            sequence_number = Some(u32::from_be_bytes(chunk.data()[..4].try_into()?));
+        }
+        rewritten.push(chunk.clone());
+        if i == last {
+            if let Some(sequence_number) = sequence_number {
+                insert(&mut rewritten, sequence_number);
+            }
+        }
+    }
+    png.set_chunks(rewritten);
+    Ok(())
+}
+
+fn shares(png: &Png, chunk_type: ChunkType) -> Result<Vec<(u32, Bytes)>> {
+    let mut shares = png
+        .chunks()
+        .iter()
+        .filter(|chunk| chunk.chunk_type == chunk_type)
+        .map(|chunk| {
+            if chunk.data().len() < 4 {
+                return Err(anyhow::anyhow!("The {} chunk is too short", chunk_type));
+            }
+            let sequence_number = u32::from_be_bytes(chunk.data()[..4].try_into()?);
+            Ok((sequence_number, chunk.data.slice(4..)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if shares.is_empty() {
+        return Err(anyhow::anyhow!("No chunk found with type {}", chunk_type));
+    }
+    shares.sort_by_key(|(sequence_number, _)| *sequence_number);
+    Ok(shares)
+}
+
+pub fn extract_chunks(png: &Png, chunk_type: ChunkType) -> Result<Vec<u8>> {
+    Ok(shares(png, chunk_type)?
+        .into_iter()
+        .flat_map(|(_, share)| share.to_vec())
+        .collect())
+}
+
+pub fn remove_chunks(png: &mut Png, chunk_type: ChunkType) -> Result<Vec<u8>> {
+    let payload = extract_chunks(png, chunk_type)?;
+    let mut chunks = png.chunks().to_vec();
+    chunks.retain(|chunk| chunk.chunk_type != chunk_type);
+    png.set_chunks(chunks);
+    Ok(payload)
+}
+
+// A key is extended with the frame's index so that frames of the same size
+// don't share a permutation. Sequence numbers can't be used since they change
+// when the frames are rewritten.
+fn frame_options(options: &LsbOptions, index: usize) -> LsbOptions {
+    let key = options
+        .key
+        .as_ref()
+        .map(|key| [&key[..], &(index as u32).to_be_bytes()].concat());
+    LsbOptions {
+        key,
+        ..options.clone()
+    }
+}
+
+// Decode every frame of the animation, in sequence number order.
+fn frame_images(png: &Png, animation: &mut Animation) -> Result<Vec<Image>> {
+    let header = ImageHeader::from_png(png)?;
+    animation
+        .frames
+        .sort_by_key(|frame| frame.control.sequence_number);
+    animation
+        .frames
+        .iter()
+        .map(|frame| {
+            let header = ImageHeader {
+                width: frame.control.width,
+                height: frame.control.height,
+                ..header
+            };
+            Image::decode(header, &frame.data.concat())
+        })
+        .collect()
+}
+
+pub fn embed_lsb(png: &mut Png, payload: &[u8], options: &LsbOptions) -> Result<()> {
+    let mut animation = animation(png)?;
+    let mut images = frame_images(png, &mut animation)?;
+    let capacities: Vec<usize> = images
+        .iter()
+        .map(|image| lsb::capacity(image, options))
+        .collect();
+    let capacity: usize = capacities.iter().sum();
+    if payload.len() > capacity {
+        return Err(anyhow::anyhow!(
+            "The message is {} bytes but only {} fit in the frames",
+            payload.len(),
+            capacity
+        ));
+    }
+    for (i, ((image, frame), share)) in images
+        .iter_mut()
+        .zip(animation.frames.iter_mut())
+        .zip(split(payload, &capacities))
+        .enumerate()
+    {
+        // A frame too small for the length prefix carries nothing.
+        if capacities[i] == 0 {
+            continue;
+        }
+        lsb::embed(image, share, &frame_options(options, i))?;
+        frame.data = vec![Bytes::from(image.encode()?)];
+    }
+    animation.apply(png);
+    Ok(())
+}
+
+pub fn capacity_lsb(png: &Png, options: &LsbOptions) -> Result<usize> {
+    let mut animation = animation(png)?;
+    let images = frame_images(png, &mut animation)?;
+    for image in &images {
+        lsb::check(image, options)?;
+    }
+    Ok(images
+        .iter()
+        .map(|image| lsb::capacity(image, options))
+        .sum())
+}
+
+pub fn extract_lsb(png: &Png, options: &LsbOptions) -> Result<Vec<u8>> {
+    let mut animation = animation(png)?;
+    let mut payload = Vec::new();
+    for (i, image) in frame_images(png, &mut animation)?.iter().enumerate() {
+        if lsb::capacity(image, options) == 0 {
+            continue;
+        }
+        payload.extend(lsb::extract(image, &frame_options(options, i))?);
+    }
+    Ok(payload)
+}
+
+// Pixels can't be restored, so removal leaves an empty message in every frame.
+pub fn remove_lsb(png: &mut Png, options: &LsbOptions) -> Result<Vec<u8>> {
+    let payload = extract_lsb(png, options)?;
+    embed_lsb(png, &[], options)?;
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apng::{AnimationControl, BlendOp, DisposeOp, FrameControl};
+    use std::str::FromStr;
+
+    fn chunk_type(name: &str) -> ChunkType {
+        ChunkType::from_str(name).unwrap()
+    }
+
+    fn frame_control(sequence_number: u32) -> Vec<u8> {
+        sized_frame_control(sequence_number, 8, 8)
+    }
+
+    fn sized_frame_control(sequence_number: u32, width: u32, height: u32) -> Vec<u8> {
+        FrameControl {
+            sequence_number,
+            width,
+            height,
+            x_offset: 0,
+            y_offset: 0,
+            delay_num: 1,
+            delay_den: 10,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+        }
+        .as_bytes()
+    }
+
+    fn frame_data(seed: u16) -> Vec<u8> {
+        sized_frame_data(8, 8, seed)
+    }
+
+    fn sized_frame_data(width: u32, height: u32, seed: u16) -> Vec<u8> {
+        let header = ImageHeader {
+            width,
+            height,
+            bit_depth: 8,
+            color_type: 2,
+            interlace: 0,
+        };
+        let samples = (0..(width * height * 3) as u16)
+            .map(|i| (i * seed) % 256)
+            .collect();
+        Image { header, samples }.encode().unwrap()
+    }
+
+    fn fdat(sequence_number: u32, data: Vec<u8>) -> Chunk {
+        Chunk::new(
+            chunk_type("fdAT"),
+            [sequence_number.to_be_bytes().to_vec(), data].concat(),
+        )
+    }
+
+    fn testing_png() -> Png {
+        let control = AnimationControl {
+            num_frames: 3,
+            num_plays: 0,
+        };
+        Png::from_chunks(vec![
+            Chunk::new(
+                chunk_type("IHDR"),
+                vec![0, 0, 0, 8, 0, 0, 0, 8, 8, 2, 0, 0, 0],
+            ),
+            Chunk::new(chunk_type("acTL"), control.as_bytes()),
+            Chunk::new(chunk_type("fcTL"), frame_control(0)),
+            Chunk::new(chunk_type("IDAT"), frame_data(3)),
+            Chunk::new(chunk_type("fcTL"), frame_control(1)),
+            fdat(2, frame_data(5)),
+            Chunk::new(chunk_type("fcTL"), frame_control(3)),
+            fdat(4, frame_data(7)),
+            Chunk::new(chunk_type("IEND"), Vec::new()),
+        ])
+    }
+
+    #[test]
+    fn test_chunks_between_frames() {
+        let mut png = testing_png();
+        embed_chunks(&mut png, chunk_type("ruSt"), b"My secret message").unwrap();
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|x| x.chunk_type().to_string())
+            .collect();
+        assert_eq!(
+            types,
+            [
+                "IHDR", "acTL", "fcTL", "IDAT", "ruSt", "fcTL", "fdAT", "ruSt", "fcTL", "fdAT",
+                "ruSt", "IEND"
+            ]
+        );
+        let png = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        assert_eq!(Animation::from_png(&png).unwrap().unwrap().frames.len(), 3);
+        assert_eq!(
+            extract_chunks(&png, chunk_type("ruSt")).unwrap(),
+            b"My secret message"
+        );
+    }
+
+    #[test]
+    fn test_second_embed_is_rejected() {
+        let mut png = testing_png();
+        embed_chunks(&mut png, chunk_type("ruSt"), b"My secret message").unwrap();
+        assert!(embed_chunks(&mut png, chunk_type("ruSt"), b"Another message").is_err());
+        assert_eq!(
+            extract_chunks(&png, chunk_type("ruSt")).unwrap(),
+            b"My secret message"
+        );
+    }
+
+    #[test]
+    fn test_chunks_reassembled_in_sequence_order() {
+        let mut png = testing_png();
+        embed_chunks(&mut png, chunk_type("ruSt"), b"My secret message").unwrap();
+        let mut chunks = png.chunks().to_vec();
+        chunks.swap(4, 10);
+        png.set_chunks(chunks);
+        assert_eq!(
+            extract_chunks(&png, chunk_type("ruSt")).unwrap(),
+            b"My secret message"
+        );
+        assert_eq!(
+            remove_chunks(&mut png, chunk_type("ruSt")).unwrap(),
+            b"My secret message"
+        );
+        assert_eq!(png.as_bytes(), testing_png().as_bytes());
+    }
+
+    #[test]
+    fn test_lsb_across_frames() {
+        let mut png = testing_png();
+        let options = LsbOptions::default();
+        embed_lsb(&mut png, b"My secret message, spread over frames", &options).unwrap();
+        let png = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        let animation = Animation::from_png(&png).unwrap().unwrap();
+        assert_eq!(animation.frames.len(), 3);
+        assert_eq!(
+            extract_lsb(&png, &options).unwrap(),
+            b"My secret message, spread over frames"
+        );
+    }
+
+    #[test]
+    fn test_keyed_lsb_across_frames() {
+        let mut png = testing_png();
+        let options = LsbOptions {
+            key: Some(b"hunter2".to_vec()),
+            ..Default::default()
+        };
+        embed_lsb(&mut png, b"My secret message", &options).unwrap();
+        assert_eq!(extract_lsb(&png, &options).unwrap(), b"My secret message");
+        assert!(
+            extract_lsb(&png, &LsbOptions::default()).map_or(true, |x| x != b"My secret message")
+        );
+    }
+
+    #[test]
+    fn test_lsb_skips_small_frames() {
+        let mut png = testing_png();
+        let mut chunks = png.chunks().to_vec();
+        chunks[4] = Chunk::new(chunk_type("fcTL"), sized_frame_control(1, 2, 1));
+        chunks[5] = fdat(2, sized_frame_data(2, 1, 5));
+        png.set_chunks(chunks);
+        let options = LsbOptions::default();
+        // Two 8x8 frames of 20 bytes each; the 2x1 frame can't hold a prefix.
+        assert_eq!(capacity_lsb(&png, &options).unwrap(), 40);
+        embed_lsb(&mut png, b"My secret message", &options).unwrap();
+        assert_eq!(extract_lsb(&png, &options).unwrap(), b"My secret message");
+        assert_eq!(
+            remove_lsb(&mut png, &options).unwrap(),
+            b"My secret message"
+        );
+    }
+
+    #[test]
+    fn test_lsb_too_large() {
+        let mut png = testing_png();
+        let payload = vec![0; 100];
+        assert!(embed_lsb(&mut png, &payload, &LsbOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_not_animated() {
+        let mut png = Png::from_chunks(vec![Chunk::new(chunk_type("IEND"), Vec::new())]);
+        assert!(embed_chunks(&mut png, chunk_type("ruSt"), b"message").is_err());
+    }
+}
@@ -0,0 +1,347 @@
+use crate::{chunk::chunk_crc, chunk_type::ChunkType, pixels::ImageHeader, png::Png};
+use anyhow::Result;
+use std::fmt::Write;
+
+// A text listing of the structure of a file, and the way back. The listing
+// is line based:
+//
+//   signature 89504e470d0a1a0a
+//   chunk IHDR
+//     # offset 8, 13 bytes, crc 2cc6456b: 16x16, bit depth 8, ...
+//     hex 0000001000000010 0806000000
+//   chunk tEXt
+//     text "Comment\0hello"
+//   trailer
+//     hex 0102
+//
+// `hex` and `text` lines append to the data of the current chunk or the
+// trailer. `length` and `crc` lines override the values `asm` would compute,
+// and `disasm` writes them only when the file disagrees with its own data, so
+// damaged files come back byte for byte. `#` starts a comment.
+
+const HEX_PER_LINE: usize = 32;
+
+// Only letters are written as they are, the way ChunkType displays them, so
+// a type such as "0x1A" can't be mistaken for hex when it is read back.
+fn type_name(chunk_type: [u8; 4]) -> String {
+    if chunk_type.iter().all(|byte| byte.is_ascii_alphabetic()) {
+        String::from_utf8_lossy(&chunk_type).into_owned()
+    } else {
+        format!("0x{:08x}", u32::from_be_bytes(chunk_type))
+    }
+}
+
+fn parse_type_name(name: &str) -> Result<[u8; 4]> {
+    if let Some(hex) = name.strip_prefix("0x") {
+        if hex.len() == 8 && hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Ok(u32::from_str_radix(hex, 16)?.to_be_bytes());
+        }
+    }
+    name.as_bytes()
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("A chunk type is 4 bytes, got {}", name))
+}
+
+// Text that reads back the same, for data that is mostly printable.
+fn is_text(data: &[u8]) -> bool {
+    !data.is_empty()
+        && data
+            .iter()
+            .all(|&byte| byte.is_ascii_graphic() || byte == b' ' || byte == 0 || byte == b'\n')
+}
+
+fn escape(data: &[u8]) -> String {
+    let mut text = String::from("\"");
+    for &byte in data {
+        match byte {
+            b'"' => text.push_str("\\\""),
+            b'\\' => text.push_str("\\\\"),
+            b'\n' => text.push_str("\\n"),
+            0 => text.push_str("\\0"),
+            byte if byte.is_ascii_graphic() || byte == b' ' => text.push(byte as char),
+            byte => write!(text, "\\x{:02x}", byte).unwrap(),
+        }
+    }
+    text.push('"');
+    text
+}
+
+fn unescape(text: &str) -> Result<Vec<u8>> {
+    let inner = text
+        .strip_prefix('"')
+        .and_then(|text| text.strip_suffix('"'))
+        .ok_or_else(|| anyhow::anyhow!("Text must be in double quotes"))?;
+    let mut data = Vec::new();
+    let mut bytes = inner.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte != b'\\' {
+            data.push(byte);
+            continue;
+        }
+        match bytes.next() {
+            Some(b'"') => data.push(b'"'),
+            Some(b'\\') => data.push(b'\\'),
+            Some(b'n') => data.push(b'\n'),
+            Some(b'0') => data.push(0),
+            Some(b'x') => {
+                let hex: Vec<u8> = bytes.by_ref().take(2).collect();
+                let hex = std::str::from_utf8(&hex)?;
+                data.push(u8::from_str_radix(hex, 16)?);
+            }
+            _ => return Err(anyhow::anyhow!("Invalid escape in {}", text)),
+        }
+    }
+    Ok(data)
+}
+
+fn parse_hex(text: &str) -> Result<Vec<u8>> {
+    let digits: Vec<u8> = text
+        .bytes()
+        .filter(|byte| !byte.is_ascii_whitespace())
+        .collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err(anyhow::anyhow!("Odd number of hex digits"));
+    }
+    digits
+        .chunks(2)
+        .map(|pair| Ok(u8::from_str_radix(std::str::from_utf8(pair)?, 16)?))
+        .collect()
+}
+
+fn write_data(listing: &mut String, data: &[u8]) {
+    if is_text(data) {
+        writeln!(listing, "  text {}", escape(data)).unwrap();
+        return;
+    }
+    for line in data.chunks(HEX_PER_LINE) {
+        let hex: Vec<String> = line
+            .chunks(8)
+            .map(|group| group.iter().map(|byte| format!("{:02x}", byte)).collect())
+            .collect();
+        writeln!(listing, "  hex {}", hex.join(" ")).unwrap();
+    }
+}
+
+// A short description of chunks whose contents are worth decoding.
+fn describe(chunk_type: [u8; 4], data: &[u8]) -> Option<String> {
+    match &chunk_type {
+        b"IHDR" => ImageHeader::try_from(data).ok().map(|header| {
+            format!(
+                "{}x{}, bit depth {}, color type {}, interlace {}",
+                header.width, header.height, header.bit_depth, header.color_type, header.interlace
+            )
+        }),
+        b"PLTE" if data.len().is_multiple_of(3) => Some(format!("{} entries", data.len() / 3)),
+        _ => None,
+    }
+}
+
+pub fn disassemble(buffer: &[u8]) -> String {
+    let mut listing = String::new();
+    let signature = &buffer[..buffer.len().min(8)];
+    let hex: String = signature
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    writeln!(listing, "signature {}", hex).unwrap();
+    let mut offset = signature.len();
+    while buffer.len() - offset >= 12 {
+        let length = u32::from_be_bytes(buffer[offset..offset + 4].try_into().unwrap());
+        let end = offset + 12 + length as usize;
+        let chunk_type: [u8; 4] = buffer[offset + 4..offset + 8].try_into().unwrap();
+        if end > buffer.len() {
+            break;
+        }
+        let data = &buffer[offset + 8..end - 4];
+        let crc = u32::from_be_bytes(buffer[end - 4..end].try_into().unwrap());
+        let computed = chunk_crc(&ChunkType::try_from(chunk_type).unwrap(), data);
+        writeln!(listing, "chunk {}", type_name(chunk_type)).unwrap();
+        let mut comment = format!("  # offset {}, {} bytes, crc {:08x}", offset, length, crc);
+        if let Some(description) = describe(chunk_type, data) {
+            write!(comment, ": {}", description).unwrap();
+        }
+        writeln!(listing, "{}", comment).unwrap();
+        if crc != computed {
+            writeln!(listing, "  crc {:08x}", crc).unwrap();
+        }
+        write_data(&mut listing, data);
+        offset = end;
+    }
+    if offset < buffer.len() {
+        writeln!(listing, "trailer").unwrap();
+        writeln!(
+            listing,
+            "  # offset {}, {} bytes",
+            offset,
+            buffer.len() - offset
+        )
+        .unwrap();
+        write_data(&mut listing, &buffer[offset..]);
+    }
+    listing
+}
+
+struct Block {
+    chunk_type: Option<[u8; 4]>,
+    length: Option<u32>,
+    crc: Option<u32>,
+    data: Vec<u8>,
+}
+
+impl Block {
+    fn write_to(&self, bytes: &mut Vec<u8>) {
+        match self.chunk_type {
+            Some(chunk_type) => {
+                let length = self.length.unwrap_or(self.data.len() as u32);
+                let crc = self.crc.unwrap_or_else(|| {
+                    chunk_crc(&ChunkType::try_from(chunk_type).unwrap(), &self.data)
+                });
+                bytes.extend_from_slice(&length.to_be_bytes());
+                bytes.extend_from_slice(&chunk_type);
+                bytes.extend_from_slice(&self.data);
+                bytes.extend_from_slice(&crc.to_be_bytes());
+            }
+            None => bytes.extend_from_slice(&self.data),
+        }
+    }
+}
+
+// The chunk or trailer that data and overrides apply to.
+fn current<'a>(blocks: &'a mut [Block], keyword: &str) -> Result<&'a mut Block> {
+    blocks
+        .last_mut()
+        .ok_or_else(|| anyhow::anyhow!("{} outside of a chunk or trailer", keyword))
+}
+
+fn assemble_line(line: &str, signature: &mut Vec<u8>, blocks: &mut Vec<Block>) -> Result<()> {
+    let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let rest = rest.trim();
+    match keyword {
+        "signature" => {
+            if !blocks.is_empty() {
+                return Err(anyhow::anyhow!("The signature must come first"));
+            }
+            *signature = parse_hex(rest)?;
+        }
+        "chunk" => blocks.push(Block {
+            chunk_type: Some(parse_type_name(rest)?),
+            length: None,
+            crc: None,
+            data: Vec::new(),
+        }),
+        "trailer" => blocks.push(Block {
+            chunk_type: None,
+            length: None,
+            crc: None,
+            data: Vec::new(),
+        }),
+        "length" => current(blocks, keyword)?.length = Some(rest.parse()?),
+        "crc" => current(blocks, keyword)?.crc = Some(u32::from_str_radix(rest, 16)?),
+        "hex" => current(blocks, keyword)?.data.extend(parse_hex(rest)?),
+        "text" => current(blocks, keyword)?.data.extend(unescape(rest)?),
+        _ => return Err(anyhow::anyhow!("Unknown keyword {}", keyword)),
+    }
+    Ok(())
+}
+
+pub fn assemble(listing: &str) -> Result<Vec<u8>> {
+    let mut signature = Png::STANDARD_HEADER.to_vec();
+    let mut blocks = Vec::new();
+    for (number, line) in listing.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        assemble_line(line, &mut signature, &mut blocks)
+            .map_err(|err| anyhow::anyhow!("Line {}: {}", number + 1, err))?;
+    }
+    let mut bytes = signature;
+    for block in &blocks {
+        block.write_to(&mut bytes);
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use std::str::FromStr;
+
+    fn testing_bytes() -> Vec<u8> {
+        let chunk = |chunk_type: &str, data: &[u8]| {
+            Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+        };
+        let mut png = Png::from_chunks(vec![
+            chunk("IHDR", &[0, 0, 0, 16, 0, 0, 0, 16, 8, 6, 0, 0, 0]),
+            chunk("tEXt", b"Comment\0\"quoted\"\\\n"),
+            chunk("IDAT", &(0..100).collect::<Vec<u8>>()),
+            chunk("IEND", &[]),
+        ]);
+        png.set_trailer(bytes::Bytes::from_static(b"\x01\x02tail"));
+        png.as_bytes()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = testing_bytes();
+        let listing = disassemble(&bytes);
+        assert!(listing.contains("text \"Comment\\0\\\"quoted\\\"\\\\\\n\""));
+        assert!(listing.contains("16x16, bit depth 8, color type 6"));
+        assert!(!listing.contains("  crc "));
+        assert_eq!(assemble(&listing).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_damaged_round_trip() {
+        let mut bytes = testing_bytes();
+        // A bad CRC on IHDR, a bad signature and a truncated last chunk.
+        bytes[32] ^= 1;
+        bytes[0] = 0;
+        bytes.truncate(bytes.len() - 3);
+        let listing = disassemble(&bytes);
+        assert!(listing.contains("  crc "));
+        assert_eq!(assemble(&listing).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_overrides() {
+        let listing = "
+            # a chunk claiming to be longer than it is
+            chunk IHDR
+              length 99
+              hex 00000001 00000001
+              hex 0800000000
+            chunk 0x20755374
+              crc deadbeef
+              text \"\\x41B\"
+        ";
+        let bytes = assemble(listing).unwrap();
+        assert_eq!(&bytes[..8], &Png::STANDARD_HEADER);
+        assert_eq!(&bytes[8..12], &99u32.to_be_bytes());
+        let second = 8 + 12 + 13;
+        assert_eq!(&bytes[second + 4..second + 8], b" uSt");
+        assert_eq!(&bytes[second + 8..second + 10], b"AB");
+        assert_eq!(&bytes[second + 10..], &0xdeadbeefu32.to_be_bytes());
+    }
+
+    #[test]
+    fn test_type_names() {
+        for chunk_type in [*b"IHDR", *b"0x1A", *b" uSt", [0xff, 0, 1, 2]] {
+            assert_eq!(parse_type_name(&type_name(chunk_type)).unwrap(), chunk_type);
+        }
+        assert_eq!(type_name(*b"0x1A"), "0x30783141");
+        assert_eq!(&parse_type_name("0x1A").unwrap(), b"0x1A");
+        assert!(parse_type_name("0x123").is_err());
+        assert!(parse_type_name("0x1234567g").is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(assemble("hex 00").is_err());
+        assert!(assemble("chunk IHDRX").is_err());
+        assert!(assemble("chunk IHDR\nhex 0").is_err());
+        let err = assemble("chunk IHDR\nfrob").unwrap_err();
+        assert_eq!(err.to_string(), "Line 2: Unknown keyword frob");
+    }
+}
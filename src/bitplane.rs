@@ -0,0 +1,194 @@
+use crate::pixels::{Image, ImageHeader};
+use anyhow::Result;
+
+// Bit planes of the decoded samples, rendered as black and white images so
+// embedding patterns can be inspected by eye. A set bit is white.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BitPlane {
+    pub width: u32,
+    pub height: u32,
+    pub bits: Vec<bool>,
+}
+
+// Resolve a channel given by index or by name (r, g, b, a, gray) for an image.
+pub fn channel_index(header: &ImageHeader, channel: &str) -> Result<usize> {
+    let index = match (channel, header.color_type) {
+        (index, _) if index.parse::<usize>().is_ok() => index.parse::<usize>()?,
+        ("r" | "red", 2 | 6) => 0,
+        ("g" | "green", 2 | 6) => 1,
+        ("b" | "blue", 2 | 6) => 2,
+        ("a" | "alpha", 6) => 3,
+        ("a" | "alpha", 4) => 1,
+        ("gray" | "grey" | "y", 0 | 4) => 0,
+        ("index" | "i", 3) => 0,
+        _ => {
+            return Err(anyhow::anyhow!(
+                "The image has no {} channel (color type {})",
+                channel,
+                header.color_type
+            ))
+        }
+    };
+    if index >= header.channels() {
+        return Err(anyhow::anyhow!(
+            "Channel {} is out of range, the image has {} channel(s)",
+            index,
+            header.channels()
+        ));
+    }
+    Ok(index)
+}
+
+// The name of a channel, for reports.
+pub fn channel_name(header: &ImageHeader, channel: usize) -> &'static str {
+    match (header.color_type, channel) {
+        (2 | 6, 0) => "red",
+        (2 | 6, 1) => "green",
+        (2 | 6, 2) => "blue",
+        (6, 3) | (4, 1) => "alpha",
+        (3, _) => "index",
+        _ => "gray",
+    }
+}
+
+impl BitPlane {
+    // Bit `bit` (0 is the least significant) of every sample of `channel`.
+    pub fn extract(image: &Image, channel: usize, bit: u8) -> Result<BitPlane> {
+        let channels = image.channels();
+        if channel >= channels {
+            return Err(anyhow::anyhow!(
+                "Channel {} is out of range, the image has {} channel(s)",
+                channel,
+                channels
+            ));
+        }
+        if bit >= image.header.bit_depth {
+            return Err(anyhow::anyhow!(
+                "Bit {} is out of range for a bit depth of {}",
+                bit,
+                image.header.bit_depth
+            ));
+        }
+        let bits = image
+            .samples
+            .iter()
+            .skip(channel)
+            .step_by(channels)
+            .map(|sample| sample >> bit & 1 == 1)
+            .collect();
+        Ok(BitPlane {
+            width: image.header.width,
+            height: image.header.height,
+            bits,
+        })
+    }
+
+    // Set where exactly one of the planes is set, i.e. where they differ.
+    pub fn xor(&self, other: &BitPlane) -> Result<BitPlane> {
+        if (self.width, self.height) != (other.width, other.height) {
+            return Err(anyhow::anyhow!(
+                "The images differ in size: {}x{} and {}x{}",
+                self.width,
+                self.height,
+                other.width,
+                other.height
+            ));
+        }
+        let bits = self
+            .bits
+            .iter()
+            .zip(&other.bits)
+            .map(|(a, b)| a ^ b)
+            .collect();
+        Ok(BitPlane {
+            width: self.width,
+            height: self.height,
+            bits,
+        })
+    }
+
+    pub fn count_ones(&self) -> usize {
+        self.bits.iter().filter(|&&bit| bit).count()
+    }
+
+    // A 1 bit grayscale image of the plane.
+    pub fn to_image(&self) -> Image {
+        Image {
+            header: ImageHeader {
+                width: self.width,
+                height: self.height,
+                bit_depth: 1,
+                color_type: 0,
+                interlace: 0,
+            },
+            samples: self.bits.iter().map(|&bit| bit as u16).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_image() -> Image {
+        Image {
+            header: ImageHeader {
+                width: 2,
+                height: 2,
+                bit_depth: 8,
+                color_type: 6,
+                interlace: 0,
+            },
+            samples: vec![
+                1, 2, 3, 255, //
+                0, 3, 4, 254, //
+                5, 6, 7, 253, //
+                8, 9, 10, 252,
+            ],
+        }
+    }
+
+    #[test]
+    fn test_channel_index() {
+        let header = testing_image().header;
+        assert_eq!(channel_index(&header, "g").unwrap(), 1);
+        assert_eq!(channel_index(&header, "alpha").unwrap(), 3);
+        assert_eq!(channel_index(&header, "2").unwrap(), 2);
+        assert!(channel_index(&header, "4").is_err());
+        assert!(channel_index(&header, "gray").is_err());
+        assert_eq!(channel_name(&header, 3), "alpha");
+    }
+
+    #[test]
+    fn test_extract() {
+        let image = testing_image();
+        let plane = BitPlane::extract(&image, 0, 0).unwrap();
+        assert_eq!(plane.bits, vec![true, false, true, false]);
+        let plane = BitPlane::extract(&image, 1, 1).unwrap();
+        assert_eq!(plane.bits, vec![true, true, true, false]);
+        assert!(BitPlane::extract(&image, 4, 0).is_err());
+        assert!(BitPlane::extract(&image, 0, 8).is_err());
+    }
+
+    #[test]
+    fn test_xor() {
+        let image = testing_image();
+        let mut other = image.clone();
+        other.samples[4] ^= 1;
+        let a = BitPlane::extract(&image, 0, 0).unwrap();
+        let b = BitPlane::extract(&other, 0, 0).unwrap();
+        let diff = a.xor(&b).unwrap();
+        assert_eq!(diff.bits, vec![false, true, false, false]);
+        assert_eq!(diff.count_ones(), 1);
+    }
+
+    #[test]
+    fn test_render() {
+        let plane = BitPlane::extract(&testing_image(), 2, 0).unwrap();
+        let png = plane.to_image().to_png().unwrap();
+        let image = Image::from_png(&png).unwrap();
+        assert_eq!(image.header.bit_depth, 1);
+        assert_eq!(image.samples, vec![1, 0, 1, 0]);
+    }
+}
@@ -0,0 +1,212 @@
+use crate::{
+    apng::Animation,
+    apng_carrier,
+    chunk::MAX_LENGTH,
+    lsb::{self, LsbOptions},
+    method::Method,
+    pixels::Image,
+    png::Png,
+    text, trailer,
+};
+use anyhow::Result;
+
+// How many message bytes each method can hold in a given image, after the
+// framing each method adds: nothing for a chunk (its length field already
+// says how long it is), the keyword and its NUL separator for a text chunk,
+// the footer for the trailer, the length prefix for LSB and, for frame
+// chunks, the sequence number in each chunk.
+
+#[derive(Debug, Clone)]
+pub struct Capacity {
+    pub method: Method,
+    // A short description of the variant, e.g. "2 bits, with alpha".
+    pub variant: String,
+    // Usable bytes, or why the method can't be used with this image.
+    pub bytes: Result<usize, String>,
+}
+
+pub const TRAILER_CAPACITY: usize = u32::MAX as usize;
+
+fn lsb_variant(options: &LsbOptions) -> String {
+    let mut variant = format!(
+        "{} bit{}",
+        options.bits,
+        if options.bits == 1 { "" } else { "s" }
+    );
+    if options.alpha {
+        variant.push_str(", with alpha");
+    }
+    if options.matrix {
+        variant.push_str(", matrix");
+    }
+    variant
+}
+
+fn lsb_capacity(image: &Image, options: &LsbOptions) -> Result<usize, String> {
+    lsb::check(image, options).map_err(|err| err.to_string())?;
+    Ok(lsb::capacity(image, options))
+}
+
+// The capacity of a method with the given options.
+pub fn capacity(png: &Png, method: Method, options: &LsbOptions) -> Result<usize, String> {
+    let animation = || match Animation::from_png(png) {
+        Ok(Some(animation)) => Ok(animation),
+        Ok(None) => Err("not animated".to_string()),
+        Err(err) => Err(err.to_string()),
+    };
+    match method {
+        Method::Chunk => Ok(MAX_LENGTH),
+        Method::Text => Ok(text::CAPACITY),
+        Method::Trailer => Ok(TRAILER_CAPACITY - trailer::FOOTER_LEN),
+        Method::Lsb => {
+            let image = Image::from_png(png).map_err(|err| err.to_string())?;
+            lsb_capacity(&image, options)
+        }
+        Method::FrameChunks => Ok(animation()?.frames.len() * (MAX_LENGTH - 4)),
+        Method::FrameLsb => {
+            animation()?;
+            apng_carrier::capacity_lsb(png, options).map_err(|err| err.to_string())
+        }
+    }
+}
+
+// Every method, with LSB at 1 to 4 bits per sample with and without alpha.
+pub fn capacities(png: &Png) -> Vec<Capacity> {
+    let mut capacities = Vec::new();
+    let mut add = |method: Method, variant: String, options: &LsbOptions| {
+        capacities.push(Capacity {
+            method,
+            variant,
+            bytes: capacity(png, method, options),
+        })
+    };
+    let default = LsbOptions::default();
+    add(Method::Chunk, String::new(), &default);
+    add(Method::Text, String::new(), &default);
+    add(Method::Trailer, String::new(), &default);
+    for alpha in [false, true] {
+        for bits in 1..=4 {
+            let options = LsbOptions {
+                bits,
+                alpha,
+                ..Default::default()
+            };
+            add(Method::Lsb, lsb_variant(&options), &options);
+        }
+    }
+    let matrix = LsbOptions {
+        matrix: true,
+        ..Default::default()
+    };
+    add(Method::Lsb, lsb_variant(&matrix), &matrix);
+    add(Method::FrameChunks, String::new(), &default);
+    add(Method::FrameLsb, lsb_variant(&default), &default);
+    capacities
+}
+
+// Refuse a message that doesn't fit before anything is written.
+pub fn check(png: &Png, method: Method, options: &LsbOptions, len: usize) -> Result<()> {
+    fits(method, capacity(png, method, options), len)
+}
+
+// The same check for LSB on an image that is already decoded.
+pub fn check_image(image: &Image, options: &LsbOptions, len: usize) -> Result<()> {
+    fits(Method::Lsb, lsb_capacity(image, options), len)
+}
+
+fn fits(method: Method, capacity: Result<usize, String>, len: usize) -> Result<()> {
+    match capacity {
+        Ok(bytes) if len <= bytes => Ok(()),
+        Ok(bytes) => Err(anyhow::anyhow!(
+            "The message is {} bytes but the {} method can hold at most {} bytes in this image",
+            len,
+            method,
+            bytes
+        )),
+        Err(reason) => Err(anyhow::anyhow!(
+            "The {} method can't be used with this image: {}",
+            method,
+            reason
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::pixels::ImageHeader;
+    use std::str::FromStr;
+
+    fn testing_png(color_type: u8) -> Png {
+        let header = ImageHeader {
+            width: 16,
+            height: 16,
+            bit_depth: 8,
+            color_type,
+            interlace: 0,
+        };
+        let samples = vec![100; 16 * 16 * header.channels()];
+        let ihdr = [0, 0, 0, 16, 0, 0, 0, 16, 8, color_type, 0, 0, 0];
+        let mut png = Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), ihdr.to_vec()),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), Vec::new()),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ]);
+        Image { header, samples }.write_to_png(&mut png).unwrap();
+        png
+    }
+
+    fn find<'a>(capacities: &'a [Capacity], method: Method, variant: &str) -> &'a Capacity {
+        capacities
+            .iter()
+            .find(|c| c.method == method && c.variant == variant)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_lsb_capacities() {
+        let capacities = capacities(&testing_png(6));
+        let pixels = 16 * 16;
+        assert_eq!(
+            find(&capacities, Method::Lsb, "1 bit").bytes,
+            Ok(pixels * 3 / 8 - 4)
+        );
+        assert_eq!(
+            find(&capacities, Method::Lsb, "4 bits").bytes,
+            Ok(pixels * 3 / 2 - 4)
+        );
+        assert_eq!(
+            find(&capacities, Method::Lsb, "2 bits, with alpha").bytes,
+            Ok(pixels * 4 / 4 - 4)
+        );
+        assert_eq!(
+            find(&capacities, Method::Lsb, "1 bit, matrix").bytes,
+            Ok((pixels * 3 - 8) / 8 - 4)
+        );
+        assert!(find(&capacities, Method::FrameLsb, "1 bit").bytes.is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let png = testing_png(2);
+        let options = LsbOptions::default();
+        assert!(check(&png, Method::Lsb, &options, 92).is_ok());
+        let err = check(&png, Method::Lsb, &options, 93).unwrap_err();
+        assert!(err.to_string().contains("at most 92 bytes"));
+        assert!(check(&png, Method::FrameChunks, &options, 1).is_err());
+        assert!(check(&png, Method::Trailer, &options, 1 << 20).is_ok());
+    }
+
+    #[test]
+    fn test_palette_has_no_lsb_capacity() {
+        let capacities = capacities(&testing_png(3));
+        assert!(find(&capacities, Method::Lsb, "1 bit").bytes.is_err());
+        assert_eq!(find(&capacities, Method::Chunk, "").bytes, Ok(MAX_LENGTH));
+        assert_eq!(
+            find(&capacities, Method::Text, "").bytes,
+            Ok(MAX_LENGTH - "encodeme".len() - 1)
+        );
+    }
+}
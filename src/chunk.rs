@@ -1,54 +1,58 @@
 use super::chunk_type::ChunkType;
 use anyhow::Result;
+use bytes::Bytes;
 use std::convert::TryFrom;
 use std::fmt;
-use std::io::BufReader;
-use std::io::Read;
 
-#[derive(Debug,Clone)]
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "ChunkRepr", from = "ChunkRepr")
+)]
 pub struct Chunk {
     pub length: u32,
     pub chunk_type: ChunkType,
-    pub data: Vec<u8>,
+    pub data: Bytes,
     pub crc: u32,
 }
 
-impl TryFrom<&[u8]> for Chunk {
-    type Error = anyhow::Error;
-    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        let mut len: [u8; 4] = [0; 4];
-        let mut buf = BufReader::new(value);
-        buf.read_exact(&mut len)?;
-        let len: u32 = u32::from_be_bytes(len);
-        let mut chunk_type: [u8; 4] = [0; 4];
-        buf.read_exact(&mut chunk_type)?;
-        let value_len = value.len() - 12;
-        if value_len != len as usize {
-            return Err(anyhow::anyhow!(
-                "The length of the chunk is not equal to the length of the data"
-            ));
-        }
-        let mut data = vec![0; value_len as usize];
-        buf.read_exact(&mut data)?;
-        let mut crc: [u8; 4] = [0; 4];
-        buf.read_exact(&mut crc)?;
-        let crc: u32 = u32::from_be_bytes(crc);
+// The largest chunk data the PNG specification allows.
+pub const MAX_LENGTH: usize = (1 << 31) - 1;
 
+// Incremental CRC over the chunk type and data, so neither has to be
+// concatenated into a temporary buffer first.
+#[derive(Default)]
+pub struct ChunkHasher {
+    hasher: crc32fast::Hasher,
+}
 
-        let buf2 = value[4..value.len()-4].to_vec();
+impl ChunkHasher {
+    pub fn new(chunk_type: &ChunkType) -> ChunkHasher {
         let mut hasher = crc32fast::Hasher::new();
-        hasher.update(buf2.as_slice());
-        let crc2 = hasher.finalize();
-        if crc != crc2 {
-            return Err(anyhow::anyhow!("The CRC of the chunk is not equal to the CRC of the data"));
-        }
+        hasher.update(&chunk_type.bytes());
+        ChunkHasher { hasher }
+    }
 
-        Ok(Chunk {
-            length: len,
-            chunk_type: ChunkType::try_from(chunk_type)?,
-            data,
-            crc,
-        })
+    pub fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+    }
+
+    pub fn finalize(self) -> u32 {
+        self.hasher.finalize()
+    }
+}
+
+pub fn chunk_crc(chunk_type: &ChunkType, data: &[u8]) -> u32 {
+    let mut hasher = ChunkHasher::new(chunk_type);
+    hasher.update(data);
+    hasher.finalize()
+}
+
+impl TryFrom<&[u8]> for Chunk {
+    type Error = anyhow::Error;
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        Chunk::from_bytes(Bytes::copy_from_slice(value))
     }
 }
 
@@ -66,20 +70,50 @@ impl fmt::Display for Chunk {
 
 impl Chunk {
     pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Chunk {
-        let length: usize = data.len();
-        // Calculate the crc32 of the data
-        let crccalc : Vec<u8>= chunk_type.bytes().iter().chain(data.iter()).copied().collect();
-        let mut hasher = crc32fast::Hasher::new();
-        hasher.update(crccalc.as_slice());
-        let crc = hasher.finalize();
+        Chunk::from_data(chunk_type, Bytes::from(data))
+    }
+
+    // Build a chunk around an existing buffer without copying it.
+    pub fn from_data(chunk_type: ChunkType, data: Bytes) -> Chunk {
+        let crc = chunk_crc(&chunk_type, &data);
         Chunk {
-            length: length as u32,
+            length: data.len() as u32,
             chunk_type,
             data,
             crc,
         }
     }
 
+    // Parse a single serialized chunk. The data is a slice of `value`, not a copy.
+    pub fn from_bytes(value: Bytes) -> Result<Chunk> {
+        if value.len() < 12 {
+            return Err(anyhow::anyhow!("The chunk is shorter than 12 bytes"));
+        }
+        let len = u32::from_be_bytes(value[0..4].try_into()?);
+        let chunk_type: [u8; 4] = value[4..8].try_into()?;
+        let value_len = value.len() - 12;
+        if value_len != len as usize {
+            return Err(anyhow::anyhow!(
+                "The length of the chunk is not equal to the length of the data"
+            ));
+        }
+        let chunk_type = ChunkType::try_from(chunk_type)?;
+        let data = value.slice(8..8 + value_len);
+        let crc = u32::from_be_bytes(value[8 + value_len..].try_into()?);
+        if crc != chunk_crc(&chunk_type, &data) {
+            return Err(anyhow::anyhow!(
+                "The CRC of the chunk is not equal to the CRC of the data"
+            ));
+        }
+
+        Ok(Chunk {
+            length: len,
+            chunk_type,
+            data,
+            crc,
+        })
+    }
+
     pub fn length(&self) -> u32 {
         self.length
     }
@@ -96,21 +130,56 @@ impl Chunk {
         self.crc
     }
     pub fn data_as_string(&self) -> Result<String> {
-        Ok(String::from_utf8(self.data.clone())?)
+        Ok(String::from_utf8(self.data.to_vec())?)
     }
     pub fn as_bytes(&self) -> Vec<u8> {
-        let bytes = self
-            .length
-            .to_be_bytes()
-            .iter()
-            .chain(self.chunk_type.bytes().iter())
-            .chain(self.data.iter())
-            .chain(self.crc.to_be_bytes().iter())
-            .copied()
-            .collect();
-
+        let mut bytes = Vec::with_capacity(self.data.len() + 12);
+        self.write_to(&mut bytes);
         bytes
     }
+    // Append the serialized chunk to `buf`.
+    pub fn write_to(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.length.to_be_bytes());
+        buf.extend_from_slice(&self.chunk_type.bytes());
+        buf.extend_from_slice(&self.data);
+        buf.extend_from_slice(&self.crc.to_be_bytes());
+    }
+}
+
+// The serialized form of a chunk. The length follows from the data, and the
+// CRC is kept as stored so a chunk with a bad CRC survives a round trip. When
+// it's missing, it is computed.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ChunkRepr {
+    #[serde(rename = "type")]
+    chunk_type: ChunkType,
+    #[serde(with = "crate::serde_base64")]
+    data: Bytes,
+    #[serde(default)]
+    crc: Option<u32>,
+}
+
+#[cfg(feature = "serde")]
+impl From<Chunk> for ChunkRepr {
+    fn from(chunk: Chunk) -> ChunkRepr {
+        ChunkRepr {
+            chunk_type: chunk.chunk_type,
+            data: chunk.data,
+            crc: Some(chunk.crc),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<ChunkRepr> for Chunk {
+    fn from(repr: ChunkRepr) -> Chunk {
+        let mut chunk = Chunk::from_data(repr.chunk_type, repr.data);
+        if let Some(crc) = repr.crc {
+            chunk.crc = crc;
+        }
+        chunk
+    }
 }
 
 #[cfg(test)]
@@ -223,6 +292,22 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    fn test_chunk_hasher_matches_crc() {
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        let mut hasher = ChunkHasher::new(&chunk_type);
+        hasher.update(b"This is where your secret ");
+        hasher.update(b"message will be!");
+        assert_eq!(hasher.finalize(), 2882656334);
+    }
+
+    #[test]
+    fn test_chunk_from_bytes_does_not_copy() {
+        let chunk_bytes = Bytes::from(testing_chunk().as_bytes());
+        let chunk = Chunk::from_bytes(chunk_bytes.clone()).unwrap();
+        assert_eq!(chunk.data().as_ptr(), chunk_bytes[8..].as_ptr());
+    }
+
     #[test]
     pub fn test_chunk_trait_impls() {
         let data_length: u32 = 42;
@@ -243,4 +328,28 @@ mod tests {
 
         let _chunk_string = format!("{}", chunk);
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_chunk_serde() {
+        let chunk = testing_chunk();
+        let json = serde_json::to_string(&chunk).unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"RuSt","data":"VGhpcyBpcyB3aGVyZSB5b3VyIHNlY3JldCBtZXNzYWdlIHdpbGwgYmUh","crc":2882656334}"#
+        );
+        let back: Chunk = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.as_bytes(), chunk.as_bytes());
+
+        let mut bad = testing_chunk();
+        bad.crc = 1;
+        let back: Chunk = serde_json::from_str(&serde_json::to_string(&bad).unwrap()).unwrap();
+        assert_eq!(back.crc(), 1);
+
+        let back: Chunk = serde_json::from_str(r#"{"type":"RuSt","data":"aGk="}"#).unwrap();
+        assert_eq!(
+            back.as_bytes(),
+            Chunk::new(chunk.chunk_type, b"hi".to_vec()).as_bytes()
+        );
+    }
 }
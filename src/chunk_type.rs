@@ -1,9 +1,9 @@
 use std::cmp::PartialEq;
 use std::convert::TryFrom;
+use std::fmt;
 use std::result::Result;
 use std::str::FromStr;
-use std::{fmt};
-#[derive(Debug, Clone,Copy)]
+#[derive(Debug, Clone, Copy)]
 pub struct ChunkType {
     pub datatype: [u8; 4],
 }
@@ -26,7 +26,7 @@ impl TryFrom<Vec<u8>> for ChunkType {
     }
 }
 pub fn is_valid_byte(byte: u8) -> bool {
-    (65..=90).contains(&byte) || ((97..=122).contains(&byte) )
+    (65..=90).contains(&byte) || ((97..=122).contains(&byte))
 }
 
 impl ChunkType {
@@ -105,9 +105,39 @@ impl FromStr for ChunkType {
     }
 }
 
+// A type read from an untrusted file can hold any bytes, so anything that
+// isn't four letters is shown as 0xHHHHHHHH.
 impl fmt::Display for ChunkType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", std::str::from_utf8(&self.datatype).unwrap())
+        if self.is_alpha() {
+            write!(f, "{}", std::str::from_utf8(&self.datatype).unwrap())
+        } else {
+            write!(f, "0x{:08x}", u32::from_be_bytes(self.datatype))
+        }
+    }
+}
+
+// Chunk types are written as they are displayed.
+#[cfg(feature = "serde")]
+impl serde::Serialize for ChunkType {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for ChunkType {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+        let name = String::deserialize(deserializer)?;
+        match name.strip_prefix("0x") {
+            Some(hex) if hex.len() == 8 => u32::from_str_radix(hex, 16)
+                .map(|value| ChunkType {
+                    datatype: value.to_be_bytes(),
+                })
+                .map_err(D::Error::custom),
+            _ => ChunkType::from_str(&name).map_err(D::Error::custom),
+        }
     }
 }
 
@@ -201,6 +231,12 @@ mod tests {
         assert_eq!(&chunk.to_string(), "RuSt");
     }
 
+    #[test]
+    pub fn test_chunk_type_string_not_letters() {
+        let chunk = ChunkType::try_from([0xff, 0xfe, 0xfd, 0xfc]).unwrap();
+        assert_eq!(&chunk.to_string(), "0xfffefdfc");
+    }
+
     #[test]
     pub fn test_chunk_type_trait_impls() {
         let chunk_type_1: ChunkType = TryFrom::try_from([82, 117, 83, 116]).unwrap();
@@ -208,4 +244,16 @@ mod tests {
         let _chunk_string = format!("{}", chunk_type_1);
         let _are_chunks_equal = chunk_type_1 == chunk_type_2;
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    pub fn test_chunk_type_serde() {
+        let chunk = ChunkType::from_str("RuSt").unwrap();
+        assert_eq!(serde_json::to_string(&chunk).unwrap(), "\"RuSt\"");
+        let odd = ChunkType::try_from([0, 1, b'S', b't']).unwrap();
+        let json = serde_json::to_string(&odd).unwrap();
+        assert_eq!(json, "\"0x00015374\"");
+        assert_eq!(serde_json::from_str::<ChunkType>(&json).unwrap(), odd);
+        assert!(serde_json::from_str::<ChunkType>("\"Ru1t\"").is_err());
+    }
 }
@@ -1,22 +1,252 @@
-use crate::chunk::Chunk;
-use crate::chunk_type;
-use crate::png;
 use anyhow::Context;
 use anyhow::Error;
 use clap::ArgMatches;
-use std::str::FromStr;
+use encodeme::apng::Animation;
+use encodeme::apng_carrier;
+use encodeme::asm;
+use encodeme::bitplane::{self, BitPlane};
+use encodeme::capacity;
+use encodeme::chunk::Chunk;
+use encodeme::chunk_type;
+use encodeme::diff::{self, Change};
+use encodeme::format::Format;
+use encodeme::gif::{self, Gif};
+use encodeme::gif_carrier;
+use encodeme::jpeg::{self, Jpeg};
+use encodeme::jpeg_carrier;
+use encodeme::lsb::{self, LsbOptions};
+use encodeme::mapped_png::MappedPng;
+use encodeme::method::Method;
+use encodeme::pixels::Image;
+use encodeme::png;
+use encodeme::polyglot;
+use encodeme::repair;
+use encodeme::riff::{self, Riff, RiffChunk};
+use encodeme::sanitize::{self, Policy};
+use encodeme::scan::{self, ScanOptions};
+use encodeme::steganalysis;
+use encodeme::text;
+use encodeme::trailer;
+use encodeme::transplant::{self, TransplantOptions};
 use std::result::Result::Ok;
+use std::str::FromStr;
+fn method(matches: &ArgMatches) -> Result<Method, Error> {
+    Method::from_str(matches.value_of("method").unwrap_or("chunk"))
+}
+
+// The chunk type is only needed when the message lives in a chunk.
+fn chunk_type_arg(matches: &ArgMatches) -> Result<&str, Error> {
+    matches
+        .value_of("chunk_type")
+        .ok_or_else(|| anyhow::anyhow!("A chunk type (-t) is required for the chunk methods"))
+}
+
+fn parse_chunk_type(matches: &ArgMatches) -> Result<chunk_type::ChunkType, Error> {
+    let name = chunk_type_arg(matches)?;
+    chunk_type::ChunkType::from_str(name).with_context(|| format!("Invalid chunk type: {}", name))
+}
+
+fn format(matches: &ArgMatches) -> Result<Option<Format>, Error> {
+    let path = matches.value_of("path").unwrap();
+    Format::of_path(path).with_context(|| format!("Could not open {}", path))
+}
+
+// JPEG, GIF and RIFF files keep the message in the segment, extension or
+// chunk named by -t.
+fn container_arg(matches: &ArgMatches) -> Result<&str, Error> {
+    if method(matches)? != Method::Chunk {
+        return Err(anyhow::anyhow!(
+            "Only the chunk method works with JPEG, GIF, WebP and WAV files"
+        ));
+    }
+    chunk_type_arg(matches)
+}
+
+fn jpeg_marker(matches: &ArgMatches) -> Result<u8, Error> {
+    jpeg::parse_marker(container_arg(matches)?)
+}
+
+fn gif_label(matches: &ArgMatches) -> Result<u8, Error> {
+    gif::parse_label(container_arg(matches)?)
+}
+
+// Standard chunks are refused, so remove can't delete the audio or image data.
+fn riff_fourcc(matches: &ArgMatches) -> Result<[u8; 4], Error> {
+    let fourcc = riff::parse_fourcc(container_arg(matches)?)?;
+    if riff::is_known(&fourcc) {
+        return Err(anyhow::anyhow!(
+            "{} is a standard RIFF chunk and can't hold a message",
+            riff::fourcc_name(&fourcc)
+        ));
+    }
+    Ok(fourcc)
+}
+
+fn lsb_options(matches: &ArgMatches) -> Result<LsbOptions, Error> {
+    let adaptive = match matches.value_of("adaptive") {
+        Some(strength) => Some(
+            strength
+                .parse::<f64>()
+                .with_context(|| format!("Invalid adaptive strength: {}", strength))?,
+        ),
+        None => None,
+    };
+    Ok(LsbOptions {
+        bits: matches.value_of("bits").unwrap_or("1").parse()?,
+        alpha: matches.is_present("alpha"),
+        key: matches.value_of("key").map(|key| key.as_bytes().to_vec()),
+        // decode has no use for --matching, so it doesn't define it
+        matching: matches.is_valid_arg("matching") && matches.is_present("matching"),
+        matrix: matches.is_present("matrix"),
+        adaptive,
+    })
+}
+
 pub fn encode(matches: &ArgMatches) -> Result<(), Error> {
+    match format(matches)? {
+        Some(Format::Jpeg) => return encode_jpeg(matches),
+        Some(Format::Gif) => return encode_gif(matches),
+        Some(Format::WebP | Format::Wav) => return encode_riff(matches),
+        _ => {}
+    }
     let mut img = png::Png::from_path(matches.value_of("path").unwrap())?;
-    let chunk_type = chunk_type::ChunkType::from_str(matches.value_of("chunk_type").unwrap());
-    let chunk_type = match chunk_type {
-        Ok(tp) => tp,
-        Err(err) => panic!("Invalid chunk type: {:?}", err),
+    let message = matches.value_of("message").unwrap().as_bytes();
+    if matches.is_present("strip_trailer") {
+        img.strip_trailer();
+    }
+    let method = method(matches)?;
+    let options = lsb_options(matches)?;
+    // LSB is checked once the image is decoded, so it is only decoded once.
+    if method != Method::Lsb {
+        capacity::check(&img, method, &options, message.len())?;
+    }
+    match method {
+        Method::Chunk => {
+            let chunk = Chunk::new(parse_chunk_type(matches)?, message.to_vec());
+            img.append_chunk(chunk);
+        }
+        Method::Text => text::embed(&mut img, message)?,
+        Method::Trailer => trailer::embed(&mut img, message)?,
+        Method::Lsb => {
+            let mut image = Image::from_png(&img)?;
+            capacity::check_image(&image, &options, message.len())?;
+            let report = lsb::embed(&mut image, message, &options)?;
+            image.write_to_png(&mut img)?;
+            if let Some(k) = report.matrix_k {
+                println!(
+                    "Matrix embedding with k = {}: changed {} of {} samples ({:.2}%, expected {:.2}%)",
+                    k,
+                    report.changed,
+                    report.samples,
+                    report.change_rate() * 100.0,
+                    report.expected_change_rate() * 100.0
+                );
+            }
+        }
+        Method::FrameChunks => {
+            apng_carrier::embed_chunks(&mut img, parse_chunk_type(matches)?, message)?
+        }
+        Method::FrameLsb => apng_carrier::embed_lsb(&mut img, message, &options)?,
+    }
+    let output_path = matches
+        .value_of("output")
+        .unwrap_or_else(|| matches.value_of("path").unwrap());
+    std::fs::write(output_path, img.as_bytes())
+        .with_context(|| format!("Could not write to {}", output_path))?;
+    Ok(())
+}
+
+pub fn decode(matches: &ArgMatches) -> Result<(), Error> {
+    match format(matches)? {
+        Some(Format::Jpeg) => return decode_jpeg(matches),
+        Some(Format::Gif) => return decode_gif(matches),
+        Some(Format::WebP | Format::Wav) => return decode_riff(matches),
+        _ => {}
+    }
+    // Only the chunk holding the message is read, so map the file instead of loading it.
+    let img = MappedPng::open(matches.value_of("path").unwrap())?;
+    let message = match method(matches)? {
+        Method::Chunk => {
+            let chunk_type = chunk_type_arg(matches)?;
+            // find chunk with given type
+            match img.chunk_by_type(chunk_type) {
+                Some(chunk) => chunk.data,
+                None => {
+                    println!("No chunk found with type {}", chunk_type);
+                    return Ok(());
+                }
+            }
+        }
+        Method::Text => match text::extract(img.to_png()?.chunks()) {
+            Some(payload) => payload,
+            None => {
+                println!("No {} tEXt chunk found", text::KEYWORD);
+                return Ok(());
+            }
+        },
+        Method::Trailer => match trailer::extract(&img.trailer()) {
+            Some(payload) => payload,
+            None => {
+                println!("No message found after IEND");
+                return Ok(());
+            }
+        },
+        Method::Lsb => lsb::extract_png(&img.to_png()?, &lsb_options(matches)?)?.into(),
+        Method::FrameChunks => {
+            apng_carrier::extract_chunks(&img.to_png()?, parse_chunk_type(matches)?)?.into()
+        }
+        Method::FrameLsb => {
+            apng_carrier::extract_lsb(&img.to_png()?, &lsb_options(matches)?)?.into()
+        }
     };
+    // print the message
+    println!("{}", String::from_utf8(message.to_vec())?);
+    Ok(())
+}
+
+pub fn remove(matches: &ArgMatches) -> Result<(), Error> {
+    match format(matches)? {
+        Some(Format::Jpeg) => return remove_jpeg(matches),
+        Some(Format::Gif) => return remove_gif(matches),
+        Some(Format::WebP | Format::Wav) => return remove_riff(matches),
+        _ => {}
+    }
+    let mut img = png::Png::from_path(matches.value_of("path").unwrap())?;
+    let message = match method(matches)? {
+        Method::Chunk => {
+            let deleted_chunk = img.delete_chunk(chunk_type_arg(matches)?);
+            let chunk = match deleted_chunk {
+                Ok(chunk) => chunk,
+                Err(err) => panic!("Could not find chunk: {:?}", err),
+            };
+            chunk.data
+        }
+        Method::Text => text::remove(&mut img)?,
+        Method::Trailer => trailer::remove(&mut img)?,
+        Method::FrameChunks => {
+            apng_carrier::remove_chunks(&mut img, parse_chunk_type(matches)?)?.into()
+        }
+        Method::Lsb => lsb::remove_png(&mut img, &lsb_options(matches)?)?.into(),
+        Method::FrameLsb => apng_carrier::remove_lsb(&mut img, &lsb_options(matches)?)?.into(),
+    };
+
+    println!("{}", String::from_utf8(message.to_vec())?);
+    if matches.is_present("strip_trailer") {
+        img.strip_trailer();
+    }
+    let output_path = matches.value_of("path").unwrap();
+    std::fs::write(output_path, img.as_bytes())
+        .with_context(|| format!("Could not write to {}", output_path))?;
+    Ok(())
+}
 
+fn encode_jpeg(matches: &ArgMatches) -> Result<(), Error> {
+    let mut img = Jpeg::from_path(matches.value_of("path").unwrap())?;
     let message = matches.value_of("message").unwrap().as_bytes();
-    let chunk = Chunk::new(chunk_type, message.to_vec());
-    img.append_chunk(chunk);
+    if matches.is_present("strip_trailer") {
+        img.set_trailer(bytes::Bytes::new());
+    }
+    jpeg_carrier::embed(&mut img, jpeg_marker(matches)?, message)?;
     let output_path = matches
         .value_of("output")
         .unwrap_or_else(|| matches.value_of("path").unwrap());
@@ -25,60 +255,579 @@ pub fn encode(matches: &ArgMatches) -> Result<(), Error> {
     Ok(())
 }
 
-pub fn decode(matches: &ArgMatches) -> Result<(), Error> {
+fn decode_jpeg(matches: &ArgMatches) -> Result<(), Error> {
+    let img = Jpeg::from_path(matches.value_of("path").unwrap())?;
+    let marker = jpeg_marker(matches)?;
+    match jpeg_carrier::extract(&img, marker) {
+        Some(message) => println!("{}", String::from_utf8(message.to_vec())?),
+        None => println!("No message found in {} segments", jpeg::marker_name(marker)),
+    }
+    Ok(())
+}
+
+fn remove_jpeg(matches: &ArgMatches) -> Result<(), Error> {
+    let mut img = Jpeg::from_path(matches.value_of("path").unwrap())?;
+    let message = jpeg_carrier::remove(&mut img, jpeg_marker(matches)?)?;
+    println!("{}", String::from_utf8(message.to_vec())?);
+    if matches.is_present("strip_trailer") {
+        img.set_trailer(bytes::Bytes::new());
+    }
+    let output_path = matches.value_of("path").unwrap();
+    std::fs::write(output_path, img.as_bytes())
+        .with_context(|| format!("Could not write to {}", output_path))?;
+    Ok(())
+}
+
+fn encode_gif(matches: &ArgMatches) -> Result<(), Error> {
+    let mut img = Gif::from_path(matches.value_of("path").unwrap())?;
+    let message = matches.value_of("message").unwrap().as_bytes();
+    if matches.is_present("strip_trailer") {
+        img.set_trailer(bytes::Bytes::new());
+    }
+    gif_carrier::embed(&mut img, gif_label(matches)?, message)?;
+    let output_path = matches
+        .value_of("output")
+        .unwrap_or_else(|| matches.value_of("path").unwrap());
+    std::fs::write(output_path, img.as_bytes())
+        .with_context(|| format!("Could not write to {}", output_path))?;
+    Ok(())
+}
+
+fn decode_gif(matches: &ArgMatches) -> Result<(), Error> {
+    let img = Gif::from_path(matches.value_of("path").unwrap())?;
+    let label = gif_label(matches)?;
+    match gif_carrier::extract(&img, label) {
+        Some(message) => println!("{}", String::from_utf8(message.to_vec())?),
+        None => println!("No message found in a {} extension", gif::label_name(label)),
+    }
+    Ok(())
+}
+
+fn remove_gif(matches: &ArgMatches) -> Result<(), Error> {
+    let mut img = Gif::from_path(matches.value_of("path").unwrap())?;
+    let message = gif_carrier::remove(&mut img, gif_label(matches)?)?;
+    println!("{}", String::from_utf8(message.to_vec())?);
+    if matches.is_present("strip_trailer") {
+        img.set_trailer(bytes::Bytes::new());
+    }
+    let output_path = matches.value_of("path").unwrap();
+    std::fs::write(output_path, img.as_bytes())
+        .with_context(|| format!("Could not write to {}", output_path))?;
+    Ok(())
+}
+
+fn encode_riff(matches: &ArgMatches) -> Result<(), Error> {
+    let mut img = Riff::from_path(matches.value_of("path").unwrap())?;
+    let message = matches.value_of("message").unwrap().as_bytes();
+    let fourcc = riff_fourcc(matches)?;
+    if matches.is_present("strip_trailer") {
+        img.set_trailer(bytes::Bytes::new());
+    }
+    img.append_chunk(RiffChunk::new(
+        fourcc,
+        bytes::Bytes::copy_from_slice(message),
+    )?);
+    let output_path = matches
+        .value_of("output")
+        .unwrap_or_else(|| matches.value_of("path").unwrap());
+    std::fs::write(output_path, img.as_bytes()?)
+        .with_context(|| format!("Could not write to {}", output_path))?;
+    Ok(())
+}
+
+fn decode_riff(matches: &ArgMatches) -> Result<(), Error> {
+    let img = Riff::from_path(matches.value_of("path").unwrap())?;
+    let fourcc = riff_fourcc(matches)?;
+    match img.chunk_by_fourcc(&fourcc) {
+        Some(chunk) => println!("{}", String::from_utf8(chunk.data.to_vec())?),
+        None => println!("No chunk found with type {}", riff::fourcc_name(&fourcc)),
+    }
+    Ok(())
+}
+
+fn remove_riff(matches: &ArgMatches) -> Result<(), Error> {
+    let mut img = Riff::from_path(matches.value_of("path").unwrap())?;
+    let chunk = img.delete_chunk(&riff_fourcc(matches)?)?;
+    println!("{}", String::from_utf8(chunk.data.to_vec())?);
+    if matches.is_present("strip_trailer") {
+        img.set_trailer(bytes::Bytes::new());
+    }
+    let output_path = matches.value_of("path").unwrap();
+    std::fs::write(output_path, img.as_bytes()?)
+        .with_context(|| format!("Could not write to {}", output_path))?;
+    Ok(())
+}
+
+pub fn chunk(matches: &ArgMatches) -> Result<(), Error> {
+    match matches.subcommand() {
+        Some(("export", sub_matches)) => chunk_export(sub_matches),
+        Some(("import", sub_matches)) => chunk_import(sub_matches),
+        _ => unreachable!("a chunk subcommand is required"),
+    }
+}
+
+// Positions are 1-based, as listed by info.
+fn index_arg(matches: &ArgMatches) -> Result<Option<usize>, Error> {
+    match matches.value_of("index") {
+        Some(index) => match index.parse::<usize>() {
+            Ok(index) if index > 0 => Ok(Some(index - 1)),
+            _ => Err(anyhow::anyhow!("Invalid index: {}", index)),
+        },
+        None => Ok(None),
+    }
+}
+
+fn chunk_export(matches: &ArgMatches) -> Result<(), Error> {
+    let img = MappedPng::open(matches.value_of("path").unwrap())?;
+    let chunk = match (index_arg(matches)?, matches.value_of("chunk_type")) {
+        (Some(index), _) => img
+            .headers()
+            .get(index)
+            .map(|header| img.chunk(header))
+            .ok_or_else(|| anyhow::anyhow!("There are only {} chunks", img.headers().len()))?,
+        (None, Some(chunk_type)) => img
+            .chunk_by_type(chunk_type)
+            .ok_or_else(|| anyhow::anyhow!("No chunk found with type {}", chunk_type))?,
+        (None, None) => unreachable!("clap requires a type or an index"),
+    };
+    let output_path = matches.value_of("output").unwrap();
+    std::fs::write(output_path, chunk.data())
+        .with_context(|| format!("Could not write to {}", output_path))?;
+    println!(
+        "Wrote {} bytes of {} to {}",
+        chunk.data().len(),
+        chunk.chunk_type(),
+        output_path
+    );
+    Ok(())
+}
+
+fn chunk_import(matches: &ArgMatches) -> Result<(), Error> {
     let mut img = png::Png::from_path(matches.value_of("path").unwrap())?;
-    // find chunk with given type
-    let chunk = img.chunk_by_type(matches.value_of("chunk_type").unwrap());
-    // check if the chunk is null
-    let chunk = match chunk {
-        Some(chunk) => chunk,
-        None => {
+    let file = matches.value_of("file").unwrap();
+    let data = std::fs::read(file).with_context(|| format!("Could not read {}", file))?;
+    let chunk = Chunk::new(parse_chunk_type(matches)?, data);
+    match index_arg(matches)? {
+        Some(index) => img.insert_chunk(index, chunk)?,
+        None => img.append_chunk(chunk),
+    }
+    let output_path = matches
+        .value_of("output")
+        .unwrap_or_else(|| matches.value_of("path").unwrap());
+    std::fs::write(output_path, img.as_bytes())
+        .with_context(|| format!("Could not write to {}", output_path))?;
+    Ok(())
+}
+
+pub fn diff(matches: &ArgMatches) -> Result<(), Error> {
+    let old = png::Png::from_path(matches.value_of("path").unwrap())?;
+    let new = png::Png::from_path(matches.value_of("with").unwrap())?;
+    let diff = diff::diff(&old, &new);
+    for change in &diff.changes {
+        if matches!(change, Change::Unchanged(..)) && !matches.is_present("verbose") {
+            continue;
+        }
+        println!("{}", change);
+    }
+    if !diff.trailers_equal {
+        println!(
+            "Trailer: {} -> {} bytes after IEND",
+            diff.trailers.0, diff.trailers.1
+        );
+    }
+    if diff.is_identical() {
+        println!("The files are identical");
+    }
+    println!("Pixels: {}", diff.pixels);
+    Ok(())
+}
+
+pub fn info(matches: &ArgMatches) -> Result<(), Error> {
+    if let Some(Format::WebP | Format::Wav) = format(matches)? {
+        return info_riff(matches);
+    }
+    let img = MappedPng::open(matches.value_of("path").unwrap())?;
+    let indent = " ".repeat(4);
+    for (i, header) in img.headers().iter().enumerate() {
+        println!(
+            "[{}] {} at offset {}, {} bytes",
+            i + 1,
+            header.chunk_type,
+            header.offset,
+            header.length
+        );
+        if matches.is_present("verbose") {
+            let chunk_type = header.chunk_type;
+            let chunk = img.chunk(header);
+            let is_reserved_bit_valid = chunk_type.is_reserved_bit_valid();
+            let is_safe_to_copy = chunk_type.is_safe_to_copy();
+            println!("{}is critical: {}", indent, chunk_type.is_critical());
+            println!("{}is public: {}", indent, chunk_type.is_public());
+            println!("{}has valid reserve bit: {}", indent, is_reserved_bit_valid);
+            println!("{}is safe to copy: {}", indent, is_safe_to_copy);
+            println!("{}crc as dec: {}", indent, chunk.crc());
+            println!("{}crc as hex: {:x}", indent, chunk.crc());
+        }
+    }
+    let chunks: Vec<_> = img
+        .headers()
+        .iter()
+        .map(|header| img.chunk(header))
+        .collect();
+    // A broken frame sequence is reported rather than returned, so the chunk
+    // list above is still useful for finding the problem.
+    let animation = Animation::from_chunks(&chunks).unwrap_or_else(|err| {
+        println!("Animation: invalid, {}", err);
+        None
+    });
+    if let Some(animation) = animation {
+        println!(
+            "Animation: {} frames, {}",
+            animation.frames.len(),
+            match animation.control.num_plays {
+                0 => "loops forever".to_string(),
+                plays => format!("plays {} times", plays),
+            }
+        );
+        for (i, frame) in animation.frames.iter().enumerate() {
+            let control = frame.control;
             println!(
-                "No chunk found with type {}",
-                matches.value_of("chunk_type").unwrap()
+                "{}frame {}: {}x{} at ({}, {}), delay {}/{} ({:.3}s), dispose {}, blend {}, {} {} chunk(s)",
+                indent,
+                i,
+                control.width,
+                control.height,
+                control.x_offset,
+                control.y_offset,
+                control.delay_num,
+                control.delay_den,
+                control.delay(),
+                control.dispose_op,
+                control.blend_op,
+                frame.data.len(),
+                if frame.is_default_image { "IDAT" } else { "fdAT" }
             );
-            return Ok(());
         }
+    }
+    let trailer = img.trailer();
+    println!("Trailer: {} bytes after IEND", trailer.len());
+    if let Some(start) = trailer::find(&trailer) {
+        println!(
+            "{}holds a {} byte message at offset {}",
+            indent,
+            trailer.len() - start - trailer::FOOTER_LEN,
+            img.trailer_offset() + start
+        );
+    }
+
+    Ok(())
+}
+
+fn info_riff(matches: &ArgMatches) -> Result<(), Error> {
+    let img = Riff::from_path(matches.value_of("path").unwrap())?;
+    let indent = " ".repeat(4);
+    println!(
+        "RIFF {}, {} bytes",
+        riff::fourcc_name(&img.form_type),
+        img.size()
+    );
+    let mut offset = 12;
+    for (i, chunk) in img.chunks().iter().enumerate() {
+        println!(
+            "[{}] {} at offset {}, {} bytes",
+            i + 1,
+            riff::fourcc_name(&chunk.fourcc),
+            offset,
+            chunk.data.len()
+        );
+        if matches.is_present("verbose") {
+            println!("{}is standard: {}", indent, riff::is_known(&chunk.fourcc));
+            println!("{}is padded: {}", indent, chunk.data.len() % 2 == 1);
+        }
+        offset += chunk.serialized_len();
+    }
+    if !img.trailer().is_empty() {
+        println!("{} bytes after the RIFF data", img.trailer().len());
+    }
+    Ok(())
+}
+
+pub fn asm(matches: &ArgMatches) -> Result<(), Error> {
+    let path = matches.value_of("path").unwrap();
+    let listing =
+        std::fs::read_to_string(path).with_context(|| format!("Could not read {}", path))?;
+    let output_path = matches.value_of("output").unwrap();
+    std::fs::write(output_path, asm::assemble(&listing)?)
+        .with_context(|| format!("Could not write to {}", output_path))?;
+    Ok(())
+}
+
+pub fn disasm(matches: &ArgMatches) -> Result<(), Error> {
+    let path = matches.value_of("path").unwrap();
+    // Read the raw bytes: the point is to see files the parser would reject.
+    let bytes = std::fs::read(path).with_context(|| format!("Could not read {}", path))?;
+    let listing = asm::disassemble(&bytes);
+    match matches.value_of("output") {
+        Some(output_path) => std::fs::write(output_path, listing)
+            .with_context(|| format!("Could not write to {}", output_path))?,
+        None => print!("{}", listing),
+    }
+    Ok(())
+}
+
+pub fn analyze(matches: &ArgMatches) -> Result<(), Error> {
+    match matches.subcommand() {
+        Some(("bitplane", sub_matches)) => analyze_bitplane(sub_matches),
+        _ => analyze_detectors(matches),
+    }
+}
+
+fn analyze_detectors(matches: &ArgMatches) -> Result<(), Error> {
+    let image = Image::from_png(&png::Png::from_path(matches.value_of("path").unwrap())?)?;
+    let format = |value: Option<f64>| match value {
+        Some(value) => format!("{:.3}", value),
+        None => "-".to_string(),
     };
-    // print the message
-    println!("{}", String::from_utf8(chunk.data().to_vec())?);
+    println!(
+        "{:<8} {:>12} {:>15} {:>8} {:>8}",
+        "channel", "chi-square p", "chi-square rate", "RS rate", "SPA rate"
+    );
+    for report in steganalysis::analyze(&image) {
+        println!(
+            "{:<8} {:>12} {:>15} {:>8} {:>8}",
+            bitplane::channel_name(&image.header, report.channel),
+            format(report.chi_square_p),
+            format(report.chi_square_rate),
+            format(report.rs_rate),
+            format(report.spa_rate)
+        );
+    }
     Ok(())
 }
 
-pub fn remove(matches: &ArgMatches) -> Result<(), Error> {
+fn analyze_bitplane(matches: &ArgMatches) -> Result<(), Error> {
+    let bit_plane = |path: &str| -> Result<BitPlane, Error> {
+        let image = Image::from_png(&png::Png::from_path(path)?)?;
+        let channel = bitplane::channel_index(&image.header, matches.value_of("channel").unwrap())?;
+        let bit = matches
+            .value_of("bit")
+            .unwrap()
+            .parse::<u8>()
+            .context("Invalid bit")?;
+        BitPlane::extract(&image, channel, bit)
+    };
+    let mut plane = bit_plane(matches.value_of("path").unwrap())?;
+    if let Some(other) = matches.value_of("xor") {
+        plane = plane.xor(&bit_plane(other)?)?;
+        println!("{} of {} bits differ", plane.count_ones(), plane.bits.len());
+    }
+    let output_path = matches.value_of("output").unwrap();
+    std::fs::write(output_path, plane.to_image().to_png()?.as_bytes())
+        .with_context(|| format!("Could not write to {}", output_path))?;
+    Ok(())
+}
+
+pub fn capacity(matches: &ArgMatches) -> Result<(), Error> {
+    let img = png::Png::from_path(matches.value_of("path").unwrap())?;
+    // There is no encryption or error correction yet, so the only overhead
+    // is the framing each method adds around the message.
+    for capacity in capacity::capacities(&img) {
+        let name = if capacity.variant.is_empty() {
+            capacity.method.to_string()
+        } else {
+            format!("{} ({})", capacity.method, capacity.variant)
+        };
+        match capacity.bytes {
+            Ok(bytes) => println!("{:<32} {:>12} bytes", name, bytes),
+            Err(reason) => println!("{:<32} {:>12}  ({})", name, "-", reason),
+        }
+    }
+    Ok(())
+}
+
+pub fn repair(matches: &ArgMatches) -> Result<(), Error> {
+    let path = matches.value_of("path").unwrap();
+    let bytes = std::fs::read(path).with_context(|| format!("Could not read {}", path))?;
+    let (img, fixes) = repair::repair(&bytes);
+    if fixes.is_empty() {
+        println!("Nothing to repair");
+    }
+    for fix in &fixes {
+        println!("{}", fix);
+    }
+    let output_path = matches.value_of("output").unwrap();
+    std::fs::write(output_path, img.as_bytes())
+        .with_context(|| format!("Could not write to {}", output_path))?;
+    Ok(())
+}
+
+pub fn sanitize(matches: &ArgMatches) -> Result<(), Error> {
     let mut img = png::Png::from_path(matches.value_of("path").unwrap())?;
-    let deleted_chunk = img.delete_chunk(matches.value_of("chunk_type").unwrap());
+    let list = |id: &str| -> Option<Vec<String>> {
+        matches
+            .values_of(id)
+            .map(|values| values.map(|value| value.to_string()).collect())
+    };
+    let policy = match (list("allow"), list("deny")) {
+        (Some(allow), _) => Policy::Allow(allow),
+        (None, Some(deny)) => Policy::Deny(deny),
+        (None, None) => Policy::default(),
+    };
+    let report = sanitize::sanitize(&mut img, &policy)?;
+    if report.is_empty() {
+        println!("Nothing to remove");
+    }
+    for removed in &report.chunks {
+        println!(
+            "Removed {} {} chunk(s), {} bytes",
+            removed.count, removed.chunk_type, removed.bytes
+        );
+    }
+    if report.trailer > 0 {
+        println!("Removed {} bytes after IEND", report.trailer);
+    }
+    let output_path = matches
+        .value_of("output")
+        .unwrap_or_else(|| matches.value_of("path").unwrap());
+    std::fs::write(output_path, img.as_bytes())
+        .with_context(|| format!("Could not write to {}", output_path))?;
+    Ok(())
+}
 
-    let chunk = match deleted_chunk {
-        Ok(chunk) => chunk,
-        Err(err) => panic!("Could not find chunk: {:?}", err),
+pub fn scan(matches: &ArgMatches) -> Result<(), Error> {
+    let options = ScanOptions {
+        max_ancillary: matches
+            .value_of("max_ancillary")
+            .unwrap()
+            .parse()
+            .context("Invalid --max-ancillary")?,
+        max_entropy: matches
+            .value_of("max_entropy")
+            .unwrap()
+            .parse()
+            .context("Invalid --max-entropy")?,
     };
+    let reports = scan::scan_dir(
+        std::path::Path::new(matches.value_of("path").unwrap()),
+        &options,
+    )?;
+    if matches.is_present("json") {
+        println!("{}", scan::to_json(&reports));
+        return Ok(());
+    }
+    let indent = " ".repeat(4);
+    for report in &reports {
+        match &report.findings {
+            Ok(findings) if findings.is_empty() => {
+                println!("{}: clean", report.path.display())
+            }
+            Ok(findings) => {
+                println!("{}:", report.path.display());
+                for finding in findings {
+                    println!(
+                        "{}{:<16} at offset {:<10} {}",
+                        indent,
+                        finding.kind(),
+                        finding.offset(),
+                        finding
+                    );
+                }
+            }
+            Err(err) => println!("{}: unreadable: {}", report.path.display(), err),
+        }
+    }
+    let summary = scan::Summary::new(&reports);
+    println!(
+        "Scanned {} PNG(s): {} flagged with {} finding(s), {} unreadable",
+        summary.scanned, summary.flagged, summary.findings, summary.unreadable
+    );
+    Ok(())
+}
 
-    let message = chunk.data_as_string()?;
-    println!("{}", message);
-    let output_path = matches.value_of("path").unwrap();
+pub fn transplant(matches: &ArgMatches) -> Result<(), Error> {
+    let source = png::Png::from_path(matches.value_of("source").unwrap())?;
+    let mut img = png::Png::from_path(matches.value_of("path").unwrap())?;
+    let options = TransplantOptions {
+        force: matches.is_present("force"),
+        types: matches
+            .values_of("chunk_type")
+            .map(|values| values.map(|value| value.to_string()).collect())
+            .unwrap_or_default(),
+    };
+    let outcomes = transplant::transplant(&source, &mut img, &options)?;
+    if outcomes.is_empty() {
+        println!("Nothing to copy");
+    }
+    for (chunk_type, outcome) in outcomes {
+        println!("{}: {}", chunk_type, outcome);
+    }
+    let output_path = matches
+        .value_of("output")
+        .unwrap_or_else(|| matches.value_of("path").unwrap());
     std::fs::write(output_path, img.as_bytes())
         .with_context(|| format!("Could not write to {}", output_path))?;
     Ok(())
 }
 
-// pub fn print(matches: &ArgMatches) -> Result<(), Error> {
-//     let img = png::Png::from_path(matches.value_of("path").unwrap())?;
-//     let indent = " ".repeat(4);
-//     for (i, chunk) in img.chunks().iter().enumerate() {
-//         println!("[{}] {}", i + 1, chunk);
-//         if matches.verbose {
-//             let chunk_type = chunk.chunk_type();
-//             let is_reserved_bit_valid = chunk_type.is_reserved_bit_valid();
-//             let is_safe_to_copy = chunk_type.is_safe_to_copy();
-//             println!("{}is critical: {}", indent, chunk_type.is_critical());
-//             println!("{}is public: {}", indent, chunk_type.is_public());
-//             println!("{}has valid reserve bit: {}", indent, is_reserved_bit_valid);
-//             println!("{}is safe to copy: {}", indent, is_safe_to_copy);
-//             println!("{}crc as dec: {}", indent, chunk.crc());
-//             println!("{}crc as hex: {:x}", indent, chunk.crc());
-//         }
-//     }
-
-//     Ok(())
-// }
+pub fn polyglot(matches: &ArgMatches) -> Result<(), Error> {
+    match matches.subcommand() {
+        Some(("create", sub_matches)) => polyglot_create(sub_matches),
+        Some(("list", sub_matches)) => polyglot_list(sub_matches),
+        Some(("extract", sub_matches)) => polyglot_extract(sub_matches),
+        _ => unreachable!("a polyglot subcommand is required"),
+    }
+}
+
+fn polyglot_create(matches: &ArgMatches) -> Result<(), Error> {
+    let mut img = png::Png::from_path(matches.value_of("path").unwrap())?;
+    let zip_path = matches.value_of("zip").unwrap();
+    let zip = std::fs::read(zip_path).with_context(|| format!("Could not read {}", zip_path))?;
+    polyglot::append_zip(&mut img, &zip)?;
+    let output_path = matches
+        .value_of("output")
+        .unwrap_or_else(|| matches.value_of("path").unwrap());
+    std::fs::write(output_path, img.as_bytes())
+        .with_context(|| format!("Could not write to {}", output_path))?;
+    Ok(())
+}
+
+fn polyglot_list(matches: &ArgMatches) -> Result<(), Error> {
+    let img = MappedPng::open(matches.value_of("path").unwrap())?;
+    let data = img.as_bytes();
+    for entry in polyglot::entries(data)? {
+        println!(
+            "{:>10} {:>10}  {}",
+            entry.uncompressed_size, entry.compressed_size, entry.name
+        );
+    }
+    Ok(())
+}
+
+fn polyglot_extract(matches: &ArgMatches) -> Result<(), Error> {
+    let img = MappedPng::open(matches.value_of("path").unwrap())?;
+    let data = img.as_bytes();
+    let dir = std::path::Path::new(matches.value_of("dir").unwrap_or("."));
+    for entry in polyglot::entries(data)? {
+        // Refuse names that would escape the output directory.
+        let name = std::path::Path::new(&entry.name);
+        if name.is_absolute()
+            || name
+                .components()
+                .any(|c| matches!(c, std::path::Component::ParentDir))
+        {
+            return Err(anyhow::anyhow!("Refusing to extract {}", entry.name));
+        }
+        let path = dir.join(name);
+        if entry.name.ends_with('/') {
+            std::fs::create_dir_all(&path)?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, polyglot::extract(data, &entry)?)
+            .with_context(|| format!("Could not write to {}", path.display()))?;
+        println!("{}", path.display());
+    }
+    Ok(())
+}
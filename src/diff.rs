@@ -0,0 +1,381 @@
+use crate::{chunk::Chunk, chunk_type::ChunkType, pixels::Image, png::Png};
+use std::fmt;
+
+// Chunk-level comparison of two files. The chunk lists are aligned on their
+// longest common subsequence of identical chunks; what is left over is then
+// matched up as moved (identical chunk, other place) or modified (same type,
+// other contents) before the rest is reported as added or removed.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChunkSummary {
+    pub index: usize,
+    pub chunk_type: ChunkType,
+    pub length: u32,
+    pub crc: u32,
+}
+
+impl ChunkSummary {
+    fn new(index: usize, chunk: &Chunk) -> ChunkSummary {
+        ChunkSummary {
+            index,
+            chunk_type: *chunk.chunk_type(),
+            length: chunk.length(),
+            crc: chunk.crc(),
+        }
+    }
+}
+
+impl fmt::Display for ChunkSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} [{}] {} bytes, crc {:08x}",
+            self.chunk_type, self.index, self.length, self.crc
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Unchanged(ChunkSummary, ChunkSummary),
+    Moved(ChunkSummary, ChunkSummary),
+    Modified(ChunkSummary, ChunkSummary),
+    Removed(ChunkSummary),
+    Added(ChunkSummary),
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Unchanged(old, new) => write!(f, "= {} -> [{}]", old, new.index),
+            Change::Moved(old, new) => write!(
+                f,
+                "> {} [{}] moved to [{}]",
+                old.chunk_type, old.index, new.index
+            ),
+            Change::Modified(old, new) => write!(
+                f,
+                "~ {} [{}] -> [{}]: {} -> {} bytes, crc {:08x} -> {:08x}",
+                old.chunk_type, old.index, new.index, old.length, new.length, old.crc, new.crc
+            ),
+            Change::Removed(old) => write!(f, "- {}", old),
+            Change::Added(new) => write!(f, "+ {}", new),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pixels {
+    Identical,
+    // Same size and format, this many samples differ.
+    Different(usize),
+    DifferentFormat,
+    // One of the images couldn't be decoded.
+    Unknown(String),
+}
+
+impl fmt::Display for Pixels {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pixels::Identical => write!(f, "identical"),
+            Pixels::Different(samples) => write!(f, "{} samples differ", samples),
+            Pixels::DifferentFormat => write!(f, "different size or format"),
+            Pixels::Unknown(err) => write!(f, "could not compare: {}", err),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PngDiff {
+    pub changes: Vec<Change>,
+    // Bytes after IEND in each file.
+    pub trailers: (usize, usize),
+    pub trailers_equal: bool,
+    pub pixels: Pixels,
+}
+
+impl PngDiff {
+    pub fn is_identical(&self) -> bool {
+        self.trailers_equal
+            && self
+                .changes
+                .iter()
+                .all(|change| matches!(change, Change::Unchanged(..)))
+    }
+}
+
+fn same(a: &Chunk, b: &Chunk) -> bool {
+    a.chunk_type() == b.chunk_type() && a.data() == b.data() && a.crc() == b.crc()
+}
+
+// The LCS table has a cell for every pair of chunks. Files split into many
+// small IDAT chunks can have tens of thousands, so above this size the
+// alignment falls back to a greedy match.
+const MAX_TABLE_CELLS: usize = 1 << 22;
+
+// Removed, Added and Unchanged entries in file order.
+fn align(old: &[Chunk], new: &[Chunk]) -> Vec<Change> {
+    let (n, m) = (old.len(), new.len());
+    if (n + 1).saturating_mul(m + 1) > MAX_TABLE_CELLS {
+        return align_greedy(old, new);
+    }
+    // lengths[i][j] is the LCS length of old[i..] and new[j..].
+    let mut lengths = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if same(&old[i], &new[j]) {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && same(&old[i], &new[j]) {
+            changes.push(Change::Unchanged(
+                ChunkSummary::new(i, &old[i]),
+                ChunkSummary::new(j, &new[j]),
+            ));
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lengths[i + 1][j] >= lengths[i][j + 1]) {
+            changes.push(Change::Removed(ChunkSummary::new(i, &old[i])));
+            i += 1;
+        } else {
+            changes.push(Change::Added(ChunkSummary::new(j, &new[j])));
+            j += 1;
+        }
+    }
+    changes
+}
+
+// A single pass that looks one chunk ahead: it keeps pairs in step across
+// a lone added, removed or modified chunk, which is what an embedded message
+// looks like, but doesn't find longer reorderings the way the LCS does.
+fn align_greedy(old: &[Chunk], new: &[Chunk]) -> Vec<Change> {
+    let (n, m) = (old.len(), new.len());
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && same(&old[i], &new[j]) {
+            changes.push(Change::Unchanged(
+                ChunkSummary::new(i, &old[i]),
+                ChunkSummary::new(j, &new[j]),
+            ));
+            i += 1;
+            j += 1;
+        } else if i == n || (j + 1 < m && same(&old[i], &new[j + 1])) {
+            changes.push(Change::Added(ChunkSummary::new(j, &new[j])));
+            j += 1;
+        } else {
+            changes.push(Change::Removed(ChunkSummary::new(i, &old[i])));
+            i += 1;
+            if j < m && !(i < n && same(&old[i], &new[j])) {
+                changes.push(Change::Added(ChunkSummary::new(j, &new[j])));
+                j += 1;
+            }
+        }
+    }
+    changes
+}
+
+// Turn a Removed entry and a matching Added entry into one entry at the
+// position of the Removed one.
+fn pair_up(
+    changes: &mut Vec<Change>,
+    old: &[Chunk],
+    new: &[Chunk],
+    matches: impl Fn(&Chunk, &Chunk) -> bool,
+    pair: impl Fn(ChunkSummary, ChunkSummary) -> Change,
+) {
+    let mut i = 0;
+    while i < changes.len() {
+        if let Change::Removed(removed) = changes[i] {
+            let found = changes.iter().position(|change| match change {
+                Change::Added(added) => matches(&old[removed.index], &new[added.index]),
+                _ => false,
+            });
+            if let Some(position) = found {
+                let Change::Added(added) = changes.remove(position) else {
+                    unreachable!()
+                };
+                if position < i {
+                    i -= 1;
+                }
+                changes[i] = pair(removed, added);
+            }
+        }
+        i += 1;
+    }
+}
+
+fn compare_pixels(old: &Png, new: &Png) -> Pixels {
+    let (old, new) = match (Image::from_png(old), Image::from_png(new)) {
+        (Ok(old), Ok(new)) => (old, new),
+        (Err(err), _) | (_, Err(err)) => return Pixels::Unknown(err.to_string()),
+    };
+    if old.header.width != new.header.width
+        || old.header.height != new.header.height
+        || old.header.bit_depth != new.header.bit_depth
+        || old.header.color_type != new.header.color_type
+    {
+        return Pixels::DifferentFormat;
+    }
+    match old
+        .samples
+        .iter()
+        .zip(&new.samples)
+        .filter(|(a, b)| a != b)
+        .count()
+    {
+        0 => Pixels::Identical,
+        samples => Pixels::Different(samples),
+    }
+}
+
+pub fn diff(old: &Png, new: &Png) -> PngDiff {
+    let (old_chunks, new_chunks) = (old.chunks(), new.chunks());
+    let mut changes = align(old_chunks, new_chunks);
+    pair_up(&mut changes, old_chunks, new_chunks, same, Change::Moved);
+    pair_up(
+        &mut changes,
+        old_chunks,
+        new_chunks,
+        |a, b| a.chunk_type() == b.chunk_type(),
+        Change::Modified,
+    );
+    PngDiff {
+        changes,
+        trailers: (old.trailer().len(), new.trailer().len()),
+        trailers_equal: old.trailer() == new.trailer(),
+        pixels: compare_pixels(old, new),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    fn testing_chunks() -> Vec<Chunk> {
+        let image = Image {
+            header: crate::pixels::ImageHeader {
+                width: 4,
+                height: 4,
+                bit_depth: 8,
+                color_type: 0,
+                interlace: 0,
+            },
+            samples: (0..16).collect(),
+        };
+        let mut chunks = image.to_png().unwrap().chunks().to_vec();
+        chunks.insert(1, chunk("tEXt", b"Title\0one"));
+        chunks.insert(2, chunk("gAMA", &[0, 0, 177, 143]));
+        chunks
+    }
+
+    fn kinds(diff: &PngDiff) -> Vec<String> {
+        diff.changes
+            .iter()
+            .map(|change| change.to_string()[..1].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_identical() {
+        let png = Png::from_chunks(testing_chunks());
+        let diff = diff(&png, &Png::from_chunks(testing_chunks()));
+        assert!(diff.is_identical());
+        assert_eq!(diff.pixels, Pixels::Identical);
+    }
+
+    #[test]
+    fn test_added_removed_modified() {
+        let old = Png::from_chunks(testing_chunks());
+        let mut chunks = testing_chunks();
+        chunks[1] = chunk("tEXt", b"Title\0two!");
+        chunks.remove(2);
+        chunks.insert(3, chunk("ruSt", b"payload"));
+        let new = Png::from_chunks(chunks);
+        let diff = diff(&old, &new);
+        assert_eq!(kinds(&diff), vec!["=", "~", "-", "=", "+", "="]);
+        match diff.changes[1] {
+            Change::Modified(old, new) => assert_eq!((old.length, new.length), (9, 10)),
+            _ => unreachable!(),
+        }
+        assert!(!diff.is_identical());
+    }
+
+    #[test]
+    fn test_moved() {
+        let old = Png::from_chunks(testing_chunks());
+        let mut chunks = testing_chunks();
+        let text = chunks.remove(1);
+        chunks.insert(3, text);
+        let diff = diff(&old, &Png::from_chunks(chunks));
+        assert_eq!(kinds(&diff), vec!["=", ">", "=", "=", "="]);
+    }
+
+    #[test]
+    fn test_modified_before_removed() {
+        let mut chunks = testing_chunks();
+        chunks[2] = chunk("tEXt", b"Title\0three");
+        let old = Png::from_chunks(chunks.clone());
+        chunks.remove(2);
+        chunks.insert(1, chunk("tEXt", b"Title\0two"));
+        chunks.insert(3, chunk("gAMA", &[0, 0, 177, 143]));
+        let diff = diff(&old, &Png::from_chunks(chunks));
+        // The added tEXt is aligned before the removed one; the pair is
+        // reported where the old chunk was.
+        assert_eq!(kinds(&diff), vec!["=", "=", "~", "+", "=", "="]);
+    }
+
+    #[test]
+    fn test_many_chunks() {
+        let idats: Vec<Chunk> = (0..3000u16)
+            .map(|i| chunk("IDAT", &i.to_be_bytes()))
+            .collect();
+        let mut chunks = idats.clone();
+        chunks[1500] = chunk("IDAT", b"changed");
+        chunks.insert(10, chunk("ruSt", b"payload"));
+        let diff = diff(&Png::from_chunks(idats), &Png::from_chunks(chunks));
+        let count = |kind: &str| kinds(&diff).iter().filter(|k| *k == kind).count();
+        assert_eq!(diff.changes.len(), 3001);
+        assert_eq!(count("+"), 1);
+        assert_eq!(count("~"), 1);
+        assert_eq!(count("="), 2999);
+        assert_eq!(kinds(&diff)[10], "+");
+    }
+
+    #[test]
+    fn test_pixels_survive_recompression() {
+        let old = Png::from_chunks(testing_chunks());
+        let mut chunks = testing_chunks();
+        let idat = chunks[3].data().to_vec();
+        // Split the IDAT in two: the bytes change, the pixels don't.
+        chunks[3] = chunk("IDAT", &idat[..5]);
+        chunks.insert(4, chunk("IDAT", &idat[5..]));
+        let mut new = Png::from_chunks(chunks);
+        new.set_trailer(bytes::Bytes::from_static(b"extra"));
+        let diff = diff(&old, &new);
+        assert_eq!(diff.pixels, Pixels::Identical);
+        assert_eq!(diff.trailers, (0, 5));
+        assert!(!diff.is_identical());
+    }
+
+    #[test]
+    fn test_pixels_differ() {
+        let old = Png::from_chunks(testing_chunks());
+        let mut image = Image::from_png(&old).unwrap();
+        image.samples[3] ^= 1;
+        let mut new = Png::from_chunks(testing_chunks());
+        image.write_to_png(&mut new).unwrap();
+        assert_eq!(diff(&old, &new).pixels, Pixels::Different(1));
+    }
+}
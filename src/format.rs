@@ -0,0 +1,85 @@
+// The file formats a message can be hidden in, told apart by their first
+// bytes.
+use crate::{gif, jpeg, png::Png, riff};
+use anyhow::Result;
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Png,
+    Jpeg,
+    Gif,
+    WebP,
+    Wav,
+}
+
+impl Format {
+    // Enough bytes to recognise any of the formats, up to the form type of a
+    // RIFF file.
+    pub const SNIFF_LEN: usize = 12;
+
+    // RIFF files other than WebP and WAV, such as AVI, aren't supported.
+    pub fn detect(bytes: &[u8]) -> Option<Format> {
+        if bytes.starts_with(&Png::STANDARD_HEADER) {
+            Some(Format::Png)
+        } else if bytes.starts_with(&jpeg::SIGNATURE) {
+            Some(Format::Jpeg)
+        } else if bytes.starts_with(&gif::SIGNATURE_87A) || bytes.starts_with(&gif::SIGNATURE_89A) {
+            Some(Format::Gif)
+        } else if bytes.starts_with(&riff::MAGIC) {
+            match bytes.get(8..12) {
+                Some(form_type) if form_type == riff::WEBP => Some(Format::WebP),
+                Some(form_type) if form_type == riff::WAVE => Some(Format::Wav),
+                _ => None,
+            }
+        } else {
+            None
+        }
+    }
+
+    // Only the first bytes are read, so the file can still be mapped
+    // afterwards.
+    pub fn of_path(path: impl AsRef<Path>) -> Result<Option<Format>> {
+        let file = std::fs::File::open(path)?;
+        let mut start = Vec::with_capacity(Format::SNIFF_LEN);
+        file.take(Format::SNIFF_LEN as u64)
+            .read_to_end(&mut start)?;
+        Ok(Format::detect(&start))
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Png => write!(f, "PNG"),
+            Format::Jpeg => write!(f, "JPEG"),
+            Format::Gif => write!(f, "GIF"),
+            Format::WebP => write!(f, "WebP"),
+            Format::Wav => write!(f, "WAV"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Format::detect(&Png::STANDARD_HEADER), Some(Format::Png));
+        assert_eq!(
+            Format::detect(&[0xff, 0xd8, 0xff, 0xe0]),
+            Some(Format::Jpeg)
+        );
+        assert_eq!(Format::detect(b"GIF87a"), Some(Format::Gif));
+        assert_eq!(Format::detect(b"GIF89a"), Some(Format::Gif));
+        assert_eq!(Format::detect(b"RIFF\x24\0\0\0WEBP"), Some(Format::WebP));
+        assert_eq!(Format::detect(b"RIFF\x24\0\0\0WAVE"), Some(Format::Wav));
+        assert_eq!(Format::detect(b"RIFF\x24\0\0\0AVI "), None);
+        assert_eq!(Format::detect(b"RIFF\x24\0\0\0"), None);
+        assert_eq!(Format::detect(b"GIF8"), None);
+        assert_eq!(Format::detect(b"PK\x03\x04"), None);
+    }
+}
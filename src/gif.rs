@@ -0,0 +1,354 @@
+use anyhow::Result;
+use bytes::Bytes;
+use std::io::Read;
+
+// A GIF file starts with a six byte header, the logical screen descriptor and
+// an optional global color table. Then come extension blocks (0x21, a label
+// and data sub-blocks) and images (0x2c, a descriptor, an optional local color
+// table and LZW data in sub-blocks), until the trailer byte 0x3b. Sub-blocks
+// are a length byte followed by that many bytes, ended by an empty one.
+pub const SIGNATURE_87A: [u8; 6] = *b"GIF87a";
+pub const SIGNATURE_89A: [u8; 6] = *b"GIF89a";
+pub const EXTENSION_INTRODUCER: u8 = 0x21;
+pub const IMAGE_SEPARATOR: u8 = 0x2c;
+pub const TRAILER: u8 = 0x3b;
+pub const PLAIN_TEXT_LABEL: u8 = 0x01;
+pub const GRAPHIC_CONTROL_LABEL: u8 = 0xf9;
+pub const COMMENT_LABEL: u8 = 0xfe;
+pub const APPLICATION_LABEL: u8 = 0xff;
+
+const MAX_SUB_BLOCK_LEN: usize = 255;
+
+pub fn label_name(label: u8) -> String {
+    match label {
+        PLAIN_TEXT_LABEL => "Plain Text".to_string(),
+        GRAPHIC_CONTROL_LABEL => "Graphic Control".to_string(),
+        COMMENT_LABEL => "Comment".to_string(),
+        APPLICATION_LABEL => "Application".to_string(),
+        _ => format!("0x{:02x}", label),
+    }
+}
+
+// The label of an extension that can hold arbitrary data, by name.
+pub fn parse_label(name: &str) -> Result<u8> {
+    match name.to_ascii_lowercase().as_str() {
+        "comment" => Ok(COMMENT_LABEL),
+        "application" => Ok(APPLICATION_LABEL),
+        _ => Err(anyhow::anyhow!(
+            "Unknown extension {}, expected comment or application",
+            name
+        )),
+    }
+}
+
+// Size in bytes of the color table announced by a packed field, where the
+// flag is the top bit and the size the low three bits.
+fn color_table_len(packed: u8) -> usize {
+    if packed & 0x80 == 0 {
+        0
+    } else {
+        3 << ((packed & 0x07) + 1)
+    }
+}
+
+fn take(value: &Bytes, offset: &mut usize, len: usize) -> Result<Bytes> {
+    if value.len() - *offset < len {
+        return Err(anyhow::anyhow!(
+            "The GIF is truncated at offset {}",
+            *offset
+        ));
+    }
+    let bytes = value.slice(*offset..*offset + len);
+    *offset += len;
+    Ok(bytes)
+}
+
+fn read_sub_blocks(value: &Bytes, offset: &mut usize) -> Result<Vec<Bytes>> {
+    let mut sub_blocks = Vec::new();
+    loop {
+        let len = take(value, offset, 1)?[0] as usize;
+        if len == 0 {
+            return Ok(sub_blocks);
+        }
+        sub_blocks.push(take(value, offset, len)?);
+    }
+}
+
+fn write_sub_blocks(sub_blocks: &[Bytes], buf: &mut Vec<u8>) {
+    for sub_block in sub_blocks {
+        buf.push(sub_block.len() as u8);
+        buf.extend_from_slice(sub_block);
+    }
+    buf.push(0);
+}
+
+#[derive(Debug, Clone)]
+pub struct Extension {
+    pub label: u8,
+    pub sub_blocks: Vec<Bytes>,
+}
+
+impl Extension {
+    // Split `data` into as many full sub-blocks as it takes.
+    pub fn new(label: u8, data: &[u8]) -> Extension {
+        Extension {
+            label,
+            sub_blocks: data
+                .chunks(MAX_SUB_BLOCK_LEN)
+                .map(Bytes::copy_from_slice)
+                .collect(),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        label_name(self.label)
+    }
+
+    // The sub-blocks joined together.
+    pub fn data(&self) -> Vec<u8> {
+        self.sub_blocks.concat()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Image {
+    // Left, top, width and height as little-endian u16, then a packed field.
+    pub descriptor: [u8; 9],
+    pub local_color_table: Bytes,
+    pub min_code_size: u8,
+    pub data: Vec<Bytes>,
+}
+
+impl Image {
+    pub fn width(&self) -> u16 {
+        u16::from_le_bytes([self.descriptor[4], self.descriptor[5]])
+    }
+
+    pub fn height(&self) -> u16 {
+        u16::from_le_bytes([self.descriptor[6], self.descriptor[7]])
+    }
+
+    // Size of the LZW data, without the sub-block lengths.
+    pub fn data_len(&self) -> usize {
+        self.data.iter().map(|sub_block| sub_block.len()).sum()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Block {
+    Extension(Extension),
+    Image(Image),
+}
+
+// A GIF as a list of blocks. Sub-blocks are kept as they were read, so
+// writing the file back gives the same bytes, and whatever follows the
+// trailer byte is kept too.
+pub struct Gif {
+    pub header: [u8; 6],
+    // Width and height as little-endian u16, a packed field, the background
+    // color index and the pixel aspect ratio.
+    pub screen: [u8; 7],
+    pub global_color_table: Bytes,
+    blocks: Vec<Block>,
+    trailer: Bytes,
+}
+
+impl Gif {
+    pub fn from_path(path: &str) -> Result<Gif> {
+        let mut file = std::fs::File::open(path)?;
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)?;
+        Gif::from_bytes(Bytes::from(buffer))
+    }
+
+    // Block data is sliced out of `value` rather than copied.
+    pub fn from_bytes(value: Bytes) -> Result<Gif> {
+        if !value.starts_with(&SIGNATURE_87A) && !value.starts_with(&SIGNATURE_89A) {
+            return Err(anyhow::anyhow!("The file does not start with a GIF header"));
+        }
+        let mut offset = 0;
+        let header = take(&value, &mut offset, 6)?[..].try_into()?;
+        let screen: [u8; 7] = take(&value, &mut offset, 7)?[..].try_into()?;
+        let global_color_table = take(&value, &mut offset, color_table_len(screen[4]))?;
+        let mut blocks = Vec::new();
+        loop {
+            let introducer = take(&value, &mut offset, 1)?[0];
+            match introducer {
+                EXTENSION_INTRODUCER => {
+                    let label = take(&value, &mut offset, 1)?[0];
+                    let sub_blocks = read_sub_blocks(&value, &mut offset)?;
+                    blocks.push(Block::Extension(Extension { label, sub_blocks }));
+                }
+                IMAGE_SEPARATOR => {
+                    let descriptor: [u8; 9] = take(&value, &mut offset, 9)?[..].try_into()?;
+                    let local_color_table =
+                        take(&value, &mut offset, color_table_len(descriptor[8]))?;
+                    let min_code_size = take(&value, &mut offset, 1)?[0];
+                    let data = read_sub_blocks(&value, &mut offset)?;
+                    blocks.push(Block::Image(Image {
+                        descriptor,
+                        local_color_table,
+                        min_code_size,
+                        data,
+                    }));
+                }
+                TRAILER => break,
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "Unknown block 0x{:02x} at offset {}",
+                        introducer,
+                        offset - 1
+                    ))
+                }
+            }
+        }
+        Ok(Gif {
+            header,
+            screen,
+            global_color_table,
+            blocks,
+            trailer: value.slice(offset..),
+        })
+    }
+
+    pub fn width(&self) -> u16 {
+        u16::from_le_bytes([self.screen[0], self.screen[1]])
+    }
+
+    pub fn height(&self) -> u16 {
+        u16::from_le_bytes([self.screen[2], self.screen[3]])
+    }
+
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    pub fn set_blocks(&mut self, blocks: Vec<Block>) {
+        self.blocks = blocks;
+    }
+
+    // Add a block after all the others, just before the trailer byte.
+    pub fn append_block(&mut self, block: Block) {
+        self.blocks.push(block);
+    }
+
+    pub fn images(&self) -> impl Iterator<Item = &Image> {
+        self.blocks.iter().filter_map(|block| match block {
+            Block::Image(image) => Some(image),
+            Block::Extension(_) => None,
+        })
+    }
+
+    // The bytes following the trailer byte, kept verbatim so they survive a
+    // rewrite.
+    pub fn trailer(&self) -> &Bytes {
+        &self.trailer
+    }
+
+    pub fn set_trailer(&mut self, trailer: Bytes) {
+        self.trailer = trailer;
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&self.header);
+        bytes.extend_from_slice(&self.screen);
+        bytes.extend_from_slice(&self.global_color_table);
+        for block in &self.blocks {
+            match block {
+                Block::Extension(extension) => {
+                    bytes.extend_from_slice(&[EXTENSION_INTRODUCER, extension.label]);
+                    write_sub_blocks(&extension.sub_blocks, &mut bytes);
+                }
+                Block::Image(image) => {
+                    bytes.push(IMAGE_SEPARATOR);
+                    bytes.extend_from_slice(&image.descriptor);
+                    bytes.extend_from_slice(&image.local_color_table);
+                    bytes.push(image.min_code_size);
+                    write_sub_blocks(&image.data, &mut bytes);
+                }
+            }
+        }
+        bytes.push(TRAILER);
+        bytes.extend_from_slice(&self.trailer);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 1x1 GIF89a with a two color global table, a graphic control
+    // extension, one image with a two color local table, and a trailer.
+    fn testing_gif() -> Vec<u8> {
+        let mut bytes = SIGNATURE_89A.to_vec();
+        bytes.extend_from_slice(&[0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00]);
+        bytes.extend_from_slice(&[0x00, 0x00, 0x00, 0xff, 0xff, 0xff]);
+        bytes.extend_from_slice(&[0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00]);
+        bytes.extend_from_slice(&[0x2c, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x80]);
+        bytes.extend_from_slice(&[0xff, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        bytes.extend_from_slice(&[0x02, 0x02, 0x44, 0x01, 0x00]);
+        bytes.push(TRAILER);
+        bytes.extend_from_slice(b"appended data");
+        bytes
+    }
+
+    #[test]
+    fn test_parse_blocks() {
+        let gif = Gif::from_bytes(Bytes::from(testing_gif())).unwrap();
+        assert_eq!((gif.width(), gif.height()), (1, 1));
+        assert_eq!(gif.global_color_table.len(), 6);
+        assert_eq!(gif.blocks().len(), 2);
+        match &gif.blocks()[0] {
+            Block::Extension(extension) => assert_eq!(extension.name(), "Graphic Control"),
+            Block::Image(_) => panic!("expected an extension"),
+        }
+        let image = gif.images().next().unwrap();
+        assert_eq!((image.width(), image.height()), (1, 1));
+        assert_eq!(image.local_color_table.len(), 6);
+        assert_eq!(image.min_code_size, 2);
+        assert_eq!(image.data_len(), 2);
+        assert_eq!(&gif.trailer()[..], b"appended data");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = testing_gif();
+        let gif = Gif::from_bytes(Bytes::from(bytes.clone())).unwrap();
+        assert_eq!(gif.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_append_extension() {
+        let mut gif = Gif::from_bytes(Bytes::from(testing_gif())).unwrap();
+        let comment: Vec<u8> = (0..600u32).map(|i| i as u8).collect();
+        gif.append_block(Block::Extension(Extension::new(COMMENT_LABEL, &comment)));
+        let gif = Gif::from_bytes(Bytes::from(gif.as_bytes())).unwrap();
+        match gif.blocks().last().unwrap() {
+            Block::Extension(extension) => {
+                assert_eq!(extension.sub_blocks.len(), 3);
+                assert_eq!(extension.data(), comment);
+            }
+            Block::Image(_) => panic!("expected an extension"),
+        }
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        assert!(Gif::from_bytes(Bytes::from_static(b"\x89PNG")).is_err());
+        let mut truncated = testing_gif();
+        truncated.truncate(30);
+        assert!(Gif::from_bytes(Bytes::from(truncated)).is_err());
+        let mut unknown = testing_gif();
+        unknown[19] = 0x42;
+        assert!(Gif::from_bytes(Bytes::from(unknown)).is_err());
+    }
+
+    #[test]
+    fn test_parse_label() {
+        assert_eq!(parse_label("comment").unwrap(), COMMENT_LABEL);
+        assert_eq!(parse_label("Application").unwrap(), APPLICATION_LABEL);
+        assert!(parse_label("graphic").is_err());
+    }
+}
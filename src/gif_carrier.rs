@@ -0,0 +1,161 @@
+use crate::gif::{self, Block, Extension, Gif};
+use crate::trailer;
+use anyhow::Result;
+use bytes::Bytes;
+
+// A message is stored in a GIF as the framed payload of trailer.rs, in a single
+// extension block appended after the last image so the frames are left alone.
+// A Comment extension starts its data with COMMENT_IDENTIFIER; an Application
+// extension has APPLICATION_IDENTIFIER as its first sub-block, which holds the
+// application name and authentication code.
+pub const COMMENT_IDENTIFIER: &[u8] = b"ENCODEME\0";
+pub const APPLICATION_IDENTIFIER: &[u8; 11] = b"ENCODEME1.0";
+
+// The framed payload held by `extension`, if it is one of ours.
+fn framed_payload(extension: &Extension, label: u8) -> Option<Vec<u8>> {
+    if extension.label != label {
+        return None;
+    }
+    match label {
+        gif::COMMENT_LABEL => extension
+            .data()
+            .strip_prefix(COMMENT_IDENTIFIER)
+            .map(|x| x.to_vec()),
+        gif::APPLICATION_LABEL
+            if extension.sub_blocks.first()?[..] == APPLICATION_IDENTIFIER[..] =>
+        {
+            Some(extension.sub_blocks[1..].concat())
+        }
+        _ => None,
+    }
+}
+
+fn is_payload_block(block: &Block, label: u8) -> bool {
+    match block {
+        Block::Extension(extension) => framed_payload(extension, label).is_some(),
+        Block::Image(_) => false,
+    }
+}
+
+pub fn embed(gif: &mut Gif, label: u8, payload: &[u8]) -> Result<()> {
+    if extract(gif, label).is_some() {
+        return Err(anyhow::anyhow!(
+            "The image already holds a message in a {} extension",
+            gif::label_name(label)
+        ));
+    }
+    let framed = trailer::frame(payload);
+    let extension = match label {
+        gif::COMMENT_LABEL => Extension::new(label, &[COMMENT_IDENTIFIER, &framed].concat()),
+        gif::APPLICATION_LABEL => {
+            let mut extension = Extension::new(label, &framed);
+            extension
+                .sub_blocks
+                .insert(0, Bytes::from_static(APPLICATION_IDENTIFIER));
+            extension
+        }
+        _ => {
+            return Err(anyhow::anyhow!(
+                "A message can only be stored in Comment or Application extensions, not {}",
+                gif::label_name(label)
+            ))
+        }
+    };
+    // Extensions only exist since GIF89a. The header isn't changed back on
+    // removal, as a GIF89a file without extensions would lose its version.
+    gif.header = gif::SIGNATURE_89A;
+    gif.append_block(Block::Extension(extension));
+    Ok(())
+}
+
+pub fn extract(gif: &Gif, label: u8) -> Option<Bytes> {
+    let framed = gif.blocks().iter().find_map(|block| match block {
+        Block::Extension(extension) => framed_payload(extension, label),
+        Block::Image(_) => None,
+    })?;
+    trailer::extract(&Bytes::from(framed))
+}
+
+pub fn remove(gif: &mut Gif, label: u8) -> Result<Bytes> {
+    let payload = extract(gif, label).ok_or_else(|| {
+        anyhow::anyhow!("No message found in a {} extension", gif::label_name(label))
+    })?;
+    let blocks = gif
+        .blocks()
+        .iter()
+        .filter(|block| !is_payload_block(block, label))
+        .cloned()
+        .collect();
+    gif.set_blocks(blocks);
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_gif() -> Gif {
+        let mut bytes = gif::SIGNATURE_89A.to_vec();
+        bytes.extend_from_slice(&[0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00]);
+        bytes.extend_from_slice(&[0x00, 0x00, 0x00, 0xff, 0xff, 0xff]);
+        bytes.extend_from_slice(&[0x2c, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00]);
+        bytes.extend_from_slice(&[0x02, 0x02, 0x44, 0x01, 0x00]);
+        bytes.push(gif::TRAILER);
+        Gif::from_bytes(Bytes::from(bytes)).unwrap()
+    }
+
+    #[test]
+    fn test_embed_and_extract() {
+        for label in [gif::COMMENT_LABEL, gif::APPLICATION_LABEL] {
+            let mut gif = testing_gif();
+            embed(&mut gif, label, b"My secret message").unwrap();
+            let gif = Gif::from_bytes(Bytes::from(gif.as_bytes())).unwrap();
+            assert_eq!(gif.blocks().len(), 2);
+            assert_eq!(&extract(&gif, label).unwrap()[..], b"My secret message");
+        }
+    }
+
+    #[test]
+    fn test_frames_are_untouched() {
+        let original = testing_gif().as_bytes();
+        let mut gif = testing_gif();
+        let payload: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+        embed(&mut gif, gif::APPLICATION_LABEL, &payload).unwrap();
+        let bytes = gif.as_bytes();
+        assert_eq!(bytes[..original.len() - 1], original[..original.len() - 1]);
+        assert!(embed(&mut gif, gif::APPLICATION_LABEL, b"Another message").is_err());
+        assert_eq!(
+            &remove(&mut gif, gif::APPLICATION_LABEL).unwrap()[..],
+            &payload[..]
+        );
+        assert_eq!(gif.as_bytes(), original);
+        assert!(remove(&mut gif, gif::APPLICATION_LABEL).is_err());
+    }
+
+    #[test]
+    fn test_other_extensions_are_ignored() {
+        let mut gif = testing_gif();
+        gif.append_block(Block::Extension(Extension::new(
+            gif::COMMENT_LABEL,
+            b"Just a comment",
+        )));
+        let mut netscape = Extension::new(gif::APPLICATION_LABEL, &[1, 0, 0]);
+        netscape
+            .sub_blocks
+            .insert(0, Bytes::from_static(b"NETSCAPE2.0"));
+        gif.append_block(Block::Extension(netscape));
+        assert!(extract(&gif, gif::COMMENT_LABEL).is_none());
+        assert!(extract(&gif, gif::APPLICATION_LABEL).is_none());
+        assert!(embed(&mut gif, gif::GRAPHIC_CONTROL_LABEL, b"message").is_err());
+    }
+
+    #[test]
+    fn test_gif87a_is_upgraded() {
+        let mut gif = testing_gif();
+        gif.header = gif::SIGNATURE_87A;
+        embed(&mut gif, gif::COMMENT_LABEL, b"My secret message").unwrap();
+        assert_eq!(gif.header, gif::SIGNATURE_89A);
+        remove(&mut gif, gif::COMMENT_LABEL).unwrap();
+        assert_eq!(gif.header, gif::SIGNATURE_89A);
+    }
+}
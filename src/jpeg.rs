@@ -0,0 +1,358 @@
+use anyhow::Result;
+use bytes::Bytes;
+use std::fmt;
+use std::io::Read;
+
+// A JPEG file is a sequence of segments, each introduced by a 0xff byte and a
+// marker. Most segments carry a big-endian length that counts itself but not
+// the marker, while SOI, EOI, RSTn and TEM stand alone. An SOS segment is
+// followed by the entropy-coded scan, which runs up to the next marker other
+// than RSTn, with 0xff bytes in it stuffed as 0xff 0x00.
+pub const SIGNATURE: [u8; 3] = [0xff, 0xd8, 0xff];
+pub const SOI: u8 = 0xd8;
+pub const EOI: u8 = 0xd9;
+pub const SOS: u8 = 0xda;
+pub const COM: u8 = 0xfe;
+pub const APP0: u8 = 0xe0;
+pub const APP15: u8 = 0xef;
+const TEM: u8 = 0x01;
+
+// The largest segment data the two byte length allows.
+pub const MAX_DATA_LEN: usize = 0xffff - 2;
+
+pub fn is_standalone(marker: u8) -> bool {
+    matches!(marker, TEM | 0xd0..=0xd9)
+}
+
+pub fn is_app(marker: u8) -> bool {
+    (APP0..=APP15).contains(&marker)
+}
+
+pub fn marker_name(marker: u8) -> String {
+    match marker {
+        0xc4 => "DHT".to_string(),
+        0xc8 => "JPG".to_string(),
+        0xcc => "DAC".to_string(),
+        0xc0..=0xcf => format!("SOF{}", marker - 0xc0),
+        0xd0..=0xd7 => format!("RST{}", marker - 0xd0),
+        SOI => "SOI".to_string(),
+        EOI => "EOI".to_string(),
+        SOS => "SOS".to_string(),
+        0xdb => "DQT".to_string(),
+        0xdc => "DNL".to_string(),
+        0xdd => "DRI".to_string(),
+        0xde => "DHP".to_string(),
+        0xdf => "EXP".to_string(),
+        APP0..=APP15 => format!("APP{}", marker - APP0),
+        COM => "COM".to_string(),
+        TEM => "TEM".to_string(),
+        _ => format!("0x{:02x}", marker),
+    }
+}
+
+// The marker of a segment that can hold arbitrary data, by name: APP0 to
+// APP15 or COM.
+pub fn parse_marker(name: &str) -> Result<u8> {
+    let upper = name.to_ascii_uppercase();
+    if upper == "COM" {
+        return Ok(COM);
+    }
+    match upper.strip_prefix("APP").map(|n| n.parse::<u8>()) {
+        Some(Ok(n)) if n <= 15 => Ok(APP0 + n),
+        _ => Err(anyhow::anyhow!(
+            "Unknown segment {}, expected APP0 to APP15 or COM",
+            name
+        )),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Segment {
+    marker: u8,
+    data: Bytes,
+    // The entropy-coded data following an SOS segment, empty for any other.
+    entropy_coded: Bytes,
+}
+
+impl Segment {
+    pub fn new(marker: u8, data: Bytes) -> Result<Segment> {
+        if is_standalone(marker) && !data.is_empty() {
+            return Err(anyhow::anyhow!(
+                "A {} segment has no data",
+                marker_name(marker)
+            ));
+        }
+        if data.len() > MAX_DATA_LEN {
+            return Err(anyhow::anyhow!(
+                "A segment holds at most {} bytes, not {}",
+                MAX_DATA_LEN,
+                data.len()
+            ));
+        }
+        Ok(Segment {
+            marker,
+            data,
+            entropy_coded: Bytes::new(),
+        })
+    }
+
+    pub fn marker(&self) -> u8 {
+        self.marker
+    }
+
+    pub fn name(&self) -> String {
+        marker_name(self.marker)
+    }
+
+    pub fn data(&self) -> &Bytes {
+        &self.data
+    }
+
+    pub fn entropy_coded(&self) -> &Bytes {
+        &self.entropy_coded
+    }
+
+    // Append the serialized segment to `buf`.
+    pub fn write_to(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&[0xff, self.marker]);
+        if !is_standalone(self.marker) {
+            buf.extend_from_slice(&(self.data.len() as u16 + 2).to_be_bytes());
+            buf.extend_from_slice(&self.data);
+        }
+        buf.extend_from_slice(&self.entropy_coded);
+    }
+}
+
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} bytes", self.name(), self.data.len())?;
+        if !self.entropy_coded.is_empty() {
+            write!(
+                f,
+                ", {} bytes of entropy-coded data",
+                self.entropy_coded.len()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Where the entropy-coded data starting at `offset` ends: at the first 0xff
+// that isn't stuffing or a restart marker, or at the end of the buffer.
+fn entropy_coded_end(buffer: &[u8], offset: usize) -> usize {
+    let mut i = offset;
+    while i + 1 < buffer.len() {
+        if buffer[i] == 0xff && buffer[i + 1] != 0 && !(0xd0..=0xd7).contains(&buffer[i + 1]) {
+            return i;
+        }
+        i += 1;
+    }
+    buffer.len()
+}
+
+// A JPEG as a list of segments, with whatever follows EOI kept as a trailer.
+// Fill bytes (extra 0xff before a marker) are dropped; everything else is
+// written back as it was read.
+pub struct Jpeg {
+    segments: Vec<Segment>,
+    trailer: Bytes,
+}
+
+impl Jpeg {
+    pub fn from_path(path: &str) -> Result<Jpeg> {
+        let mut file = std::fs::File::open(path)?;
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)?;
+        Jpeg::from_bytes(Bytes::from(buffer))
+    }
+
+    // Segment data is sliced out of `value` rather than copied.
+    pub fn from_bytes(value: Bytes) -> Result<Jpeg> {
+        if !value.starts_with(&[0xff, SOI]) {
+            return Err(anyhow::anyhow!(
+                "The file does not start with a JPEG SOI marker"
+            ));
+        }
+        let mut segments = Vec::new();
+        let mut trailer = Bytes::new();
+        let mut offset = 0;
+        while offset < value.len() {
+            if value[offset] != 0xff {
+                return Err(anyhow::anyhow!("Expected a marker at offset {}", offset));
+            }
+            while offset + 1 < value.len() && value[offset + 1] == 0xff {
+                offset += 1;
+            }
+            if offset + 1 >= value.len() {
+                return Err(anyhow::anyhow!(
+                    "The marker at offset {} is truncated",
+                    offset
+                ));
+            }
+            let marker = value[offset + 1];
+            if marker == 0 {
+                return Err(anyhow::anyhow!("Invalid marker at offset {}", offset));
+            }
+            if is_standalone(marker) {
+                segments.push(Segment::new(marker, Bytes::new())?);
+                offset += 2;
+                if marker == EOI {
+                    trailer = value.slice(offset..);
+                    break;
+                }
+                continue;
+            }
+            if offset + 4 > value.len() {
+                return Err(anyhow::anyhow!(
+                    "The segment at offset {} is truncated",
+                    offset
+                ));
+            }
+            let length = u16::from_be_bytes([value[offset + 2], value[offset + 3]]) as usize;
+            let end = offset + 2 + length;
+            if length < 2 || end > value.len() {
+                return Err(anyhow::anyhow!(
+                    "The {} segment at offset {} has an invalid length",
+                    marker_name(marker),
+                    offset
+                ));
+            }
+            let mut segment = Segment::new(marker, value.slice(offset + 4..end))?;
+            offset = end;
+            if marker == SOS {
+                let scan_end = entropy_coded_end(&value, offset);
+                segment.entropy_coded = value.slice(offset..scan_end);
+                offset = scan_end;
+            }
+            segments.push(segment);
+        }
+        Ok(Jpeg { segments, trailer })
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    pub fn set_segments(&mut self, segments: Vec<Segment>) {
+        self.segments = segments;
+    }
+
+    pub fn insert_segment(&mut self, index: usize, segment: Segment) -> Result<()> {
+        if index == 0 || index > self.segments.len() {
+            return Err(anyhow::anyhow!(
+                "A segment can't be inserted before SOI or after the end"
+            ));
+        }
+        self.segments.insert(index, segment);
+        Ok(())
+    }
+
+    // The bytes following EOI, kept verbatim so they survive a rewrite.
+    pub fn trailer(&self) -> &Bytes {
+        &self.trailer
+    }
+
+    pub fn set_trailer(&mut self, trailer: Bytes) {
+        self.trailer = trailer;
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let size = self
+            .segments
+            .iter()
+            .map(|segment| segment.data.len() + segment.entropy_coded.len() + 4)
+            .sum::<usize>();
+        let mut bytes = Vec::with_capacity(size + self.trailer.len());
+        for segment in &self.segments {
+            segment.write_to(&mut bytes);
+        }
+        bytes.extend_from_slice(&self.trailer);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // SOI, APP0, DQT (shortened), SOS with stuffed bytes and a restart marker,
+    // EOI and a trailer.
+    fn testing_jpeg() -> Vec<u8> {
+        let mut bytes = vec![0xff, SOI];
+        bytes.extend_from_slice(&[0xff, APP0, 0x00, 0x07]);
+        bytes.extend_from_slice(b"JFIF\0");
+        bytes.extend_from_slice(&[0xff, 0xdb, 0x00, 0x04, 0x00, 0x01]);
+        bytes.extend_from_slice(&[0xff, SOS, 0x00, 0x03, 0x01]);
+        bytes.extend_from_slice(&[0x12, 0xff, 0x00, 0x34, 0xff, 0xd0, 0x56]);
+        bytes.extend_from_slice(&[0xff, EOI]);
+        bytes.extend_from_slice(b"appended data");
+        bytes
+    }
+
+    #[test]
+    fn test_parse_segments() {
+        let jpeg = Jpeg::from_bytes(Bytes::from(testing_jpeg())).unwrap();
+        let names: Vec<String> = jpeg.segments().iter().map(|x| x.name()).collect();
+        assert_eq!(names, ["SOI", "APP0", "DQT", "SOS", "EOI"]);
+        assert_eq!(&jpeg.segments()[1].data()[..], b"JFIF\0");
+        assert_eq!(&jpeg.segments()[3].data()[..], [0x01]);
+        assert_eq!(
+            &jpeg.segments()[3].entropy_coded()[..],
+            [0x12, 0xff, 0x00, 0x34, 0xff, 0xd0, 0x56]
+        );
+        assert_eq!(&jpeg.trailer()[..], b"appended data");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = testing_jpeg();
+        let jpeg = Jpeg::from_bytes(Bytes::from(bytes.clone())).unwrap();
+        assert_eq!(jpeg.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_fill_bytes_are_dropped() {
+        let mut bytes = testing_jpeg();
+        bytes.insert(2, 0xff);
+        let jpeg = Jpeg::from_bytes(Bytes::from(bytes)).unwrap();
+        assert_eq!(jpeg.as_bytes(), testing_jpeg());
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        assert!(Jpeg::from_bytes(Bytes::from_static(b"\x89PNG")).is_err());
+        let mut truncated = testing_jpeg();
+        truncated.truncate(8);
+        assert!(Jpeg::from_bytes(Bytes::from(truncated)).is_err());
+        let mut bad_length = testing_jpeg();
+        bad_length[5] = 0x01;
+        assert!(Jpeg::from_bytes(Bytes::from(bad_length)).is_err());
+    }
+
+    #[test]
+    fn test_insert_segment() {
+        let mut jpeg = Jpeg::from_bytes(Bytes::from(testing_jpeg())).unwrap();
+        let comment = Segment::new(COM, Bytes::from_static(b"hello")).unwrap();
+        assert!(jpeg.insert_segment(0, comment.clone()).is_err());
+        jpeg.insert_segment(2, comment).unwrap();
+        let reparsed = Jpeg::from_bytes(Bytes::from(jpeg.as_bytes())).unwrap();
+        assert_eq!(reparsed.segments()[2].name(), "COM");
+        assert_eq!(&reparsed.segments()[2].data()[..], b"hello");
+    }
+
+    #[test]
+    fn test_segment_limits() {
+        assert!(Segment::new(EOI, Bytes::from_static(b"x")).is_err());
+        assert!(Segment::new(COM, Bytes::from(vec![0; MAX_DATA_LEN])).is_ok());
+        assert!(Segment::new(COM, Bytes::from(vec![0; MAX_DATA_LEN + 1])).is_err());
+    }
+
+    #[test]
+    fn test_parse_marker() {
+        assert_eq!(parse_marker("COM").unwrap(), COM);
+        assert_eq!(parse_marker("app15").unwrap(), APP15);
+        assert_eq!(marker_name(parse_marker("APP1").unwrap()), "APP1");
+        assert!(parse_marker("APP16").is_err());
+        assert!(parse_marker("SOS").is_err());
+    }
+}
@@ -0,0 +1,141 @@
+use crate::jpeg::{self, Jpeg, Segment};
+use crate::trailer;
+use anyhow::Result;
+use bytes::Bytes;
+
+// A message is stored in a JPEG as the framed payload of trailer.rs, split
+// over as many APPn or COM segments as it takes. Every one of them starts with
+// IDENTIFIER, the way APPn segments usually name their format, so they can be
+// told apart from other segments with the same marker.
+pub const IDENTIFIER: &[u8] = b"ENCODEME\0";
+const PIECE_LEN: usize = jpeg::MAX_DATA_LEN - IDENTIFIER.len();
+
+fn is_payload_segment(segment: &Segment, marker: u8) -> bool {
+    segment.marker() == marker && segment.data().starts_with(IDENTIFIER)
+}
+
+fn check_marker(marker: u8) -> Result<()> {
+    if !jpeg::is_app(marker) && marker != jpeg::COM {
+        return Err(anyhow::anyhow!(
+            "A message can only be stored in APPn or COM segments, not {}",
+            jpeg::marker_name(marker)
+        ));
+    }
+    Ok(())
+}
+
+// The payload segments go after SOI and the APPn segments that follow it, as
+// readers expect JFIF or Exif to come first.
+fn insert_position(jpeg: &Jpeg) -> usize {
+    1 + jpeg.segments()[1..]
+        .iter()
+        .take_while(|segment| jpeg::is_app(segment.marker()))
+        .count()
+}
+
+pub fn embed(jpeg: &mut Jpeg, marker: u8, payload: &[u8]) -> Result<()> {
+    check_marker(marker)?;
+    if extract(jpeg, marker).is_some() {
+        return Err(anyhow::anyhow!(
+            "The image already holds a message in {} segments",
+            jpeg::marker_name(marker)
+        ));
+    }
+    let pieces = trailer::frame(payload);
+    for (index, piece) in (insert_position(jpeg)..).zip(pieces.chunks(PIECE_LEN)) {
+        let mut data = IDENTIFIER.to_vec();
+        data.extend_from_slice(piece);
+        jpeg.insert_segment(index, Segment::new(marker, Bytes::from(data))?)?;
+    }
+    Ok(())
+}
+
+pub fn extract(jpeg: &Jpeg, marker: u8) -> Option<Bytes> {
+    let framed: Vec<u8> = jpeg
+        .segments()
+        .iter()
+        .filter(|segment| is_payload_segment(segment, marker))
+        .flat_map(|segment| segment.data()[IDENTIFIER.len()..].iter().copied())
+        .collect();
+    trailer::extract(&Bytes::from(framed))
+}
+
+pub fn remove(jpeg: &mut Jpeg, marker: u8) -> Result<Bytes> {
+    let payload = extract(jpeg, marker).ok_or_else(|| {
+        anyhow::anyhow!("No message found in {} segments", jpeg::marker_name(marker))
+    })?;
+    let segments = jpeg
+        .segments()
+        .iter()
+        .filter(|segment| !is_payload_segment(segment, marker))
+        .cloned()
+        .collect();
+    jpeg.set_segments(segments);
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_jpeg() -> Jpeg {
+        let mut bytes = vec![0xff, jpeg::SOI];
+        bytes.extend_from_slice(&[0xff, jpeg::APP0, 0x00, 0x07]);
+        bytes.extend_from_slice(b"JFIF\0");
+        bytes.extend_from_slice(&[0xff, 0xdb, 0x00, 0x04, 0x00, 0x01]);
+        bytes.extend_from_slice(&[0xff, jpeg::SOS, 0x00, 0x03, 0x01, 0x12, 0x34]);
+        bytes.extend_from_slice(&[0xff, jpeg::EOI]);
+        Jpeg::from_bytes(Bytes::from(bytes)).unwrap()
+    }
+
+    #[test]
+    fn test_embed_and_extract() {
+        let mut jpeg = testing_jpeg();
+        embed(&mut jpeg, jpeg::APP15, b"My secret message").unwrap();
+        let jpeg = Jpeg::from_bytes(Bytes::from(jpeg.as_bytes())).unwrap();
+        let names: Vec<String> = jpeg.segments().iter().map(|x| x.name()).collect();
+        assert_eq!(names, ["SOI", "APP0", "APP15", "DQT", "SOS", "EOI"]);
+        assert_eq!(
+            &extract(&jpeg, jpeg::APP15).unwrap()[..],
+            b"My secret message"
+        );
+        assert!(extract(&jpeg, jpeg::COM).is_none());
+    }
+
+    #[test]
+    fn test_large_payload_spans_segments() {
+        let mut jpeg = testing_jpeg();
+        let payload: Vec<u8> = (0..150_000u32).map(|i| i as u8).collect();
+        embed(&mut jpeg, jpeg::COM, &payload).unwrap();
+        let count = jpeg
+            .segments()
+            .iter()
+            .filter(|x| x.marker() == jpeg::COM)
+            .count();
+        assert_eq!(count, 3);
+        assert_eq!(&extract(&jpeg, jpeg::COM).unwrap()[..], &payload[..]);
+    }
+
+    #[test]
+    fn test_remove() {
+        let original = testing_jpeg().as_bytes();
+        let mut jpeg = testing_jpeg();
+        embed(&mut jpeg, jpeg::COM, b"My secret message").unwrap();
+        assert!(embed(&mut jpeg, jpeg::COM, b"Another message").is_err());
+        assert_eq!(
+            &remove(&mut jpeg, jpeg::COM).unwrap()[..],
+            b"My secret message"
+        );
+        assert_eq!(jpeg.as_bytes(), original);
+        assert!(remove(&mut jpeg, jpeg::COM).is_err());
+    }
+
+    #[test]
+    fn test_other_segments_are_ignored() {
+        let mut jpeg = testing_jpeg();
+        let comment = Segment::new(jpeg::COM, Bytes::from_static(b"Just a comment")).unwrap();
+        jpeg.insert_segment(2, comment).unwrap();
+        assert!(extract(&jpeg, jpeg::COM).is_none());
+        assert!(embed(&mut jpeg, jpeg::SOS, b"message").is_err());
+    }
+}
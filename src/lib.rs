@@ -0,0 +1,31 @@
+pub mod adaptive;
+pub mod apng;
+pub mod apng_carrier;
+pub mod asm;
+pub mod bitplane;
+pub mod capacity;
+pub mod chunk;
+pub mod chunk_type;
+pub mod diff;
+pub mod format;
+pub mod gif;
+pub mod gif_carrier;
+pub mod jpeg;
+pub mod jpeg_carrier;
+pub mod lsb;
+pub mod mapped_png;
+pub mod matrix;
+pub mod method;
+pub mod pixels;
+pub mod png;
+pub mod polyglot;
+pub mod repair;
+pub mod riff;
+pub mod sanitize;
+pub mod scan;
+#[cfg(feature = "serde")]
+mod serde_base64;
+pub mod steganalysis;
+pub mod text;
+pub mod trailer;
+pub mod transplant;
@@ -0,0 +1,524 @@
+use crate::{adaptive, matrix, pixels::Image, png::Png};
+use anyhow::Result;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
+
+// Least significant bit embedding over decoded samples. The payload is
+// prefixed with its length as a u32 (big endian) and written most significant
+// bit first, `bits` bits per sample, skipping alpha samples unless asked.
+//
+// Without a key the samples are used from the top-left pixel on. With a key,
+// their order is a permutation drawn from ChaCha20 seeded with a hash of the
+// key, so the bits can't be located without it.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LsbOptions {
+    pub bits: u8,
+    pub alpha: bool,
+    pub key: Option<Vec<u8>>,
+    // LSB matching: change samples by ±1 rather than replacing their low
+    // bits, which avoids the pairs of values artifact of plain replacement.
+    pub matching: bool,
+    // Matrix embedding with Hamming codes, see `matrix`. Needs `bits` = 1.
+    pub matrix: bool,
+    // Content-adaptive ordering with the given strength, see `adaptive`.
+    pub adaptive: Option<f64>,
+}
+
+// How much of the image an embedding touched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmbedReport {
+    // Samples that carry the message, including any header.
+    pub samples: usize,
+    pub changed: usize,
+    // The Hamming code parameter when matrix embedding was used.
+    pub matrix_k: Option<u8>,
+}
+
+impl EmbedReport {
+    pub fn change_rate(&self) -> f64 {
+        self.changed as f64 / self.samples.max(1) as f64
+    }
+
+    // The fraction of samples expected to change for a random message.
+    pub fn expected_change_rate(&self) -> f64 {
+        match self.matrix_k {
+            Some(k) => matrix::expected_change_rate(k),
+            None => 0.5,
+        }
+    }
+}
+
+impl Default for LsbOptions {
+    fn default() -> Self {
+        LsbOptions {
+            bits: 1,
+            alpha: false,
+            key: None,
+            matching: false,
+            matrix: false,
+            adaptive: None,
+        }
+    }
+}
+
+const KEY_CONTEXT: &[u8] = b"encodeme lsb permutation v1";
+
+// A CSPRNG whose stream only depends on the key.
+pub fn keyed_rng(key: &[u8]) -> ChaCha20Rng {
+    let mut hasher = Sha256::new();
+    hasher.update(KEY_CONTEXT);
+    hasher.update(key);
+    ChaCha20Rng::from_seed(hasher.finalize().into())
+}
+
+pub const LENGTH_LEN: usize = 4;
+
+pub fn check(image: &Image, options: &LsbOptions) -> Result<()> {
+    if image.header.is_palette() {
+        return Err(anyhow::anyhow!(
+            "LSB embedding needs a grayscale or truecolor image, not a palette"
+        ));
+    }
+    if options.adaptive.is_some() && options.matching {
+        // ±1 changes can carry into the bits the texture is measured on.
+        return Err(anyhow::anyhow!(
+            "Adaptive embedding cannot be combined with LSB matching"
+        ));
+    }
+    if options
+        .adaptive
+        .is_some_and(|strength| strength.is_nan() || strength < 0.0)
+    {
+        return Err(anyhow::anyhow!(
+            "The adaptive strength must not be negative"
+        ));
+    }
+    if options.matrix && options.bits != 1 {
+        return Err(anyhow::anyhow!("Matrix embedding uses 1 bit per sample"));
+    }
+    if options.bits == 0 || options.bits > image.header.bit_depth.min(8) {
+        return Err(anyhow::anyhow!(
+            "Cannot use {} bits per sample at bit depth {}",
+            options.bits,
+            image.header.bit_depth
+        ));
+    }
+    Ok(())
+}
+
+// Indices of the samples that carry payload bits, in embedding order.
+pub fn positions(image: &Image, options: &LsbOptions) -> Vec<usize> {
+    let channels = image.channels();
+    let skip_alpha = image.header.has_alpha() && !options.alpha;
+    let mut positions: Vec<usize> = (0..image.samples.len())
+        .filter(|i| !(skip_alpha && i % channels == channels - 1))
+        .collect();
+    let key = options.key.as_deref();
+    if let Some(strength) = options.adaptive {
+        adaptive::order(
+            image,
+            &mut positions,
+            options.bits,
+            strength,
+            key.unwrap_or_default(),
+        );
+    } else if let Some(key) = key {
+        positions.shuffle(&mut keyed_rng(key));
+    }
+    positions
+}
+
+// Payload bytes that fit, after the length prefix.
+pub fn capacity(image: &Image, options: &LsbOptions) -> usize {
+    let positions = positions(image, options).len();
+    let bits = if options.matrix {
+        matrix::capacity_bits(positions, 1)
+    } else {
+        positions * options.bits as usize
+    };
+    (bits / 8).saturating_sub(LENGTH_LEN)
+}
+
+pub(crate) fn frame(payload: &[u8]) -> Result<Vec<u8>> {
+    let length = u32::try_from(payload.len())?;
+    Ok([&length.to_be_bytes()[..], payload].concat())
+}
+
+fn bits_of(bytes: &[u8]) -> impl Iterator<Item = u16> + '_ {
+    bytes
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) as u16 & 1))
+}
+
+// The closest value to `sample` whose low bits are `value`, within 0..=max.
+// Ties are broken at random, which for a single bit means adding or
+// subtracting 1 with equal probability.
+pub(crate) fn matched(sample: u16, value: u16, bits: u8, max: u16, rng: &mut impl Rng) -> u16 {
+    let step = 1i32 << bits;
+    let base = ((sample & !((1 << bits) - 1)) | value) as i32;
+    let sample = sample as i32;
+    let mut candidates: Vec<i32> = [base - step, base, base + step]
+        .into_iter()
+        .filter(|&c| c >= 0 && c <= max as i32)
+        .collect();
+    let best = candidates.iter().map(|c| (c - sample).abs()).min().unwrap();
+    candidates.retain(|c| (c - sample).abs() == best);
+    *candidates.choose(rng).unwrap() as u16
+}
+
+// Write `data` into the low `bits` bits of `positions`, in order. With
+// `matching`, samples are moved to the nearest value carrying the bits
+// instead of having them overwritten.
+pub fn write_bits(image: &mut Image, positions: &[usize], bits: u8, data: &[u8], matching: bool) {
+    let mut stream = bits_of(data);
+    let mask = (1u16 << bits) - 1;
+    let max = image.header.max_value();
+    let mut rng = rand::thread_rng();
+    for &position in positions {
+        let mut value = 0;
+        let mut any = false;
+        for _ in 0..bits {
+            let bit = stream.next();
+            any |= bit.is_some();
+            value = (value << 1) | bit.unwrap_or(0);
+        }
+        if !any {
+            break;
+        }
+        let sample = &mut image.samples[position];
+        if *sample & mask == value {
+            continue;
+        }
+        *sample = if matching {
+            matched(*sample, value, bits, max, &mut rng)
+        } else {
+            (*sample & !mask) | value
+        };
+    }
+}
+
+// Read `count` bytes from the low `bits` bits of `positions`.
+pub fn read_bits(image: &Image, positions: &[usize], bits: u8, count: usize) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(count);
+    let (mut byte, mut filled) = (0u8, 0);
+    'outer: for &position in positions {
+        let sample = image.samples[position];
+        for i in (0..bits).rev() {
+            byte = (byte << 1) | ((sample >> i) & 1) as u8;
+            filled += 1;
+            if filled == 8 {
+                bytes.push(byte);
+                (byte, filled) = (0, 0);
+                if bytes.len() == count {
+                    break 'outer;
+                }
+            }
+        }
+    }
+    bytes
+}
+
+pub fn embed_at(
+    image: &mut Image,
+    positions: &[usize],
+    payload: &[u8],
+    bits: u8,
+    matching: bool,
+) -> Result<()> {
+    // Checked in bits first, as an image too small for the length prefix has
+    // no room for even an empty message.
+    if LENGTH_LEN * 8 > positions.len() * bits as usize {
+        return Err(anyhow::anyhow!("The image is too small to hold a message"));
+    }
+    let capacity = (positions.len() * bits as usize / 8) - LENGTH_LEN;
+    if payload.len() > capacity {
+        return Err(anyhow::anyhow!(
+            "The message is {} bytes but only {} fit in the image",
+            payload.len(),
+            capacity
+        ));
+    }
+    write_bits(image, positions, bits, &frame(payload)?, matching);
+    Ok(())
+}
+
+pub fn extract_at(image: &Image, positions: &[usize], bits: u8) -> Result<Vec<u8>> {
+    if LENGTH_LEN * 8 > positions.len() * bits as usize {
+        return Err(anyhow::anyhow!("No message found in the image"));
+    }
+    let length = read_bits(image, positions, bits, LENGTH_LEN);
+    let length = u32::from_be_bytes(length.as_slice().try_into()?) as usize;
+    let capacity = (positions.len() * bits as usize / 8).saturating_sub(LENGTH_LEN);
+    if length > capacity {
+        return Err(anyhow::anyhow!("No message found in the image"));
+    }
+    let bytes = read_bits(image, positions, bits, LENGTH_LEN + length);
+    Ok(bytes[LENGTH_LEN..].to_vec())
+}
+
+pub fn embed(image: &mut Image, payload: &[u8], options: &LsbOptions) -> Result<EmbedReport> {
+    check(image, options)?;
+    let positions = positions(image, options);
+    let original = image.samples.clone();
+    let (matrix_k, samples) = if options.matrix {
+        let (k, used) = matrix::embed_at(image, &positions, payload, options.matching)?;
+        (Some(k), used)
+    } else {
+        embed_at(image, &positions, payload, options.bits, options.matching)?;
+        let bits = (payload.len() + LENGTH_LEN) * 8;
+        (None, bits.div_ceil(options.bits as usize))
+    };
+    let changed = positions[..samples]
+        .iter()
+        .filter(|&&position| original[position] != image.samples[position])
+        .count();
+    Ok(EmbedReport {
+        samples,
+        changed,
+        matrix_k,
+    })
+}
+
+pub fn extract(image: &Image, options: &LsbOptions) -> Result<Vec<u8>> {
+    check(image, options)?;
+    if options.matrix {
+        return matrix::extract_at(image, &positions(image, options));
+    }
+    extract_at(image, &positions(image, options), options.bits)
+}
+
+pub fn embed_png(png: &mut Png, payload: &[u8], options: &LsbOptions) -> Result<EmbedReport> {
+    let mut image = Image::from_png(png)?;
+    let report = embed(&mut image, payload, options)?;
+    image.write_to_png(png)?;
+    Ok(report)
+}
+
+pub fn extract_png(png: &Png, options: &LsbOptions) -> Result<Vec<u8>> {
+    extract(&Image::from_png(png)?, options)
+}
+
+// Pixels can't be restored, so removal leaves an empty message behind.
+pub fn remove_png(png: &mut Png, options: &LsbOptions) -> Result<Vec<u8>> {
+    let payload = extract_png(png, options)?;
+    embed_png(png, &[], options)?;
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixels::ImageHeader;
+
+    fn testing_image(bit_depth: u8, color_type: u8) -> Image {
+        let header = ImageHeader {
+            width: 16,
+            height: 16,
+            bit_depth,
+            color_type,
+            interlace: 0,
+        };
+        let count = 16 * 16 * header.channels();
+        let samples = (0..count)
+            .map(|i| ((i * 31) % (header.max_value() as usize + 1)) as u16)
+            .collect();
+        Image { header, samples }
+    }
+
+    #[test]
+    fn test_embed_and_extract() {
+        for (bit_depth, color_type) in [(8, 2), (16, 6), (2, 0)] {
+            let mut image = testing_image(bit_depth, color_type);
+            let options = LsbOptions::default();
+            embed(&mut image, b"My secret message", &options).unwrap();
+            assert_eq!(extract(&image, &options).unwrap(), b"My secret message");
+        }
+    }
+
+    #[test]
+    fn test_only_low_bits_change() {
+        let original = testing_image(8, 6);
+        let mut image = original.clone();
+        let options = LsbOptions {
+            bits: 2,
+            ..Default::default()
+        };
+        embed(&mut image, b"My secret message", &options).unwrap();
+        for (i, (a, b)) in original.samples.iter().zip(&image.samples).enumerate() {
+            assert_eq!(a >> 2, b >> 2);
+            if i % 4 == 3 {
+                assert_eq!(a, b);
+            }
+        }
+        assert_eq!(extract(&image, &options).unwrap(), b"My secret message");
+    }
+
+    #[test]
+    fn test_three_bits_with_alpha() {
+        let mut image = testing_image(8, 6);
+        let options = LsbOptions {
+            bits: 3,
+            alpha: true,
+            ..Default::default()
+        };
+        // More than fits in one bit of the color channels.
+        let payload: Vec<u8> = (0..300u32).map(|i| (i * 7) as u8).collect();
+        assert!(payload.len() > capacity(&image, &LsbOptions::default()));
+        embed(&mut image, &payload, &options).unwrap();
+        assert_eq!(extract(&image, &options).unwrap(), payload);
+        assert_ne!(extract(&image, &LsbOptions::default()).ok(), Some(payload));
+    }
+
+    #[test]
+    fn test_capacity() {
+        let image = testing_image(8, 6);
+        assert_eq!(
+            capacity(&image, &LsbOptions::default()),
+            16 * 16 * 3 / 8 - 4
+        );
+        let options = LsbOptions {
+            bits: 4,
+            alpha: true,
+            ..Default::default()
+        };
+        assert_eq!(capacity(&image, &options), 16 * 16 * 4 / 2 - 4);
+    }
+
+    #[test]
+    fn test_payload_too_large() {
+        let mut image = testing_image(8, 0);
+        let payload = vec![0; capacity(&image, &LsbOptions::default()) + 1];
+        assert!(embed(&mut image, &payload, &LsbOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_image_too_small_for_length() {
+        let header = ImageHeader {
+            width: 2,
+            height: 1,
+            bit_depth: 8,
+            color_type: 2,
+            interlace: 0,
+        };
+        let mut image = Image {
+            header,
+            samples: vec![0; 6],
+        };
+        let options = LsbOptions::default();
+        assert_eq!(capacity(&image, &options), 0);
+        assert!(embed(&mut image, b"", &options).is_err());
+        assert!(extract(&image, &options).is_err());
+        let matrix = LsbOptions {
+            matrix: true,
+            ..Default::default()
+        };
+        assert!(embed(&mut image, b"", &matrix).is_err());
+        assert!(extract(&image, &matrix).is_err());
+    }
+
+    fn keyed(key: &str) -> LsbOptions {
+        LsbOptions {
+            key: Some(key.as_bytes().to_vec()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_keyed_embed_and_extract() {
+        let mut image = testing_image(8, 6);
+        embed(&mut image, b"My secret message", &keyed("hunter2")).unwrap();
+        assert_eq!(
+            extract(&image, &keyed("hunter2")).unwrap(),
+            b"My secret message"
+        );
+        let wrong = extract(&image, &keyed("hunter3"));
+        assert!(wrong.map_or(true, |payload| payload != b"My secret message"));
+    }
+
+    #[test]
+    fn test_key_scatters_positions() {
+        let image = testing_image(8, 6);
+        let sequential = positions(&image, &LsbOptions::default());
+        let permuted = positions(&image, &keyed("hunter2"));
+        assert_ne!(sequential, permuted);
+        assert_eq!(permuted, positions(&image, &keyed("hunter2")));
+        let mut sorted = permuted.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, sequential);
+        // The first bytes of the message must not land in the first pixels.
+        assert!(permuted[..32].iter().any(|&i| i > 96));
+    }
+
+    fn matching(bits: u8) -> LsbOptions {
+        LsbOptions {
+            bits,
+            matching: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_matching_changes_by_one() {
+        for (bit_depth, color_type) in [(1, 0), (2, 0), (4, 0), (8, 2), (16, 2)] {
+            let original = testing_image(bit_depth, color_type);
+            let mut image = original.clone();
+            let payload = vec![0xa5; capacity(&image, &matching(1))];
+            embed(&mut image, &payload, &matching(1)).unwrap();
+            let max = image.header.max_value();
+            for (a, b) in original.samples.iter().zip(&image.samples) {
+                assert!(a.abs_diff(*b) <= 1);
+                assert!(*b <= max);
+            }
+            assert_eq!(extract(&image, &LsbOptions::default()).unwrap(), payload);
+        }
+    }
+
+    #[test]
+    fn test_matching_goes_both_ways() {
+        let original = testing_image(8, 2);
+        let mut image = original.clone();
+        let payload = vec![0x5a; capacity(&image, &matching(1))];
+        embed(&mut image, &payload, &matching(1)).unwrap();
+        let diffs = original.samples.iter().zip(&image.samples);
+        let up = diffs.clone().filter(|(a, b)| b > a).count();
+        let down = diffs.filter(|(a, b)| b < a).count();
+        assert!(up > 0 && down > 0);
+    }
+
+    #[test]
+    fn test_matching_clamps() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..16 {
+            assert_eq!(matched(0, 1, 1, 255, &mut rng), 1);
+            assert_eq!(matched(255, 0, 1, 255, &mut rng), 254);
+            assert_eq!(matched(65535, 0, 1, 65535, &mut rng), 65534);
+            assert_eq!(matched(1, 0, 1, 1, &mut rng), 0);
+            assert_eq!(matched(3, 0, 2, 3, &mut rng), 0);
+            assert_eq!(matched(0, 3, 2, 255, &mut rng), 3);
+        }
+    }
+
+    #[test]
+    fn test_matching_multiple_bits() {
+        let original = testing_image(8, 0);
+        let mut image = original.clone();
+        embed(&mut image, b"My secret message", &matching(2)).unwrap();
+        for (a, b) in original.samples.iter().zip(&image.samples) {
+            assert!(a.abs_diff(*b) <= 2);
+        }
+        let options = LsbOptions {
+            bits: 2,
+            ..Default::default()
+        };
+        assert_eq!(extract(&image, &options).unwrap(), b"My secret message");
+    }
+
+    #[test]
+    fn test_palette_is_rejected() {
+        let mut image = testing_image(8, 3);
+        assert!(embed(&mut image, b"message", &LsbOptions::default()).is_err());
+    }
+}
@@ -1,17 +1,34 @@
-mod args;
-mod chunk;
-mod chunk_type;
 mod commands;
-mod png;
 use anyhow::{Ok, Result};
 use clap::{App, AppSettings, Arg};
+use encodeme::method::Method;
 // TODO: Switch to Derive API for parsing args
 fn main() -> Result<()> {
     let mut matches = App::new(env!("CARGO_CRATE_NAME"))
         .setting(AppSettings::ArgRequiredElseHelp)
+        .subcommand(
+            App::new("disasm")
+                .about("Lists the structure of a PNG image as editable text")
+                .arg(
+                    Arg::new("path")
+                        .takes_value(true)
+                        .required(true)
+                        .short('p')
+                        .value_name("path")
+                        .help("specify the path of the png image"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .takes_value(true)
+                        .required(false)
+                        .short('o')
+                        .value_name("output")
+                        .help("write the listing to a file instead of printing it"),
+                ),
+        )
         .subcommand(
             App::new("encode")
-                .about("Encodes a message into a PNG image")
+                .about("Encodes a message into a PNG, JPEG, GIF, WebP or WAV file")
                 // Automatically deduce the arguements based on their index.
                 .arg(
                     Arg::new("path")
@@ -19,15 +36,62 @@ fn main() -> Result<()> {
                         .required(true)
                         .short('p')
                         .value_name("path")
-                        .help("specify the path of the png image"),
+                        .help("specify the path of the png, jpeg, gif, webp or wav file"),
+                )
+                .arg(
+                    Arg::new("method")
+                        .takes_value(true)
+                        .long("method")
+                        .value_name("method")
+                        .possible_values(Method::NAMES)
+                        .default_value("chunk")
+                        .help("specify where the message is stored"),
+                )
+                .arg(
+                    Arg::new("key")
+                        .takes_value(true)
+                        .long("key")
+                        .value_name("key")
+                        .help("specify the key that scatters the message over the pixels"),
+                )
+                .arg(
+                    Arg::new("bits")
+                        .takes_value(true)
+                        .long("bits")
+                        .value_name("bits")
+                        .possible_values(["1", "2", "3", "4"])
+                        .default_value("1")
+                        .help("specify how many low bits of each sample carry the message"),
+                )
+                .arg(
+                    Arg::new("alpha")
+                        .long("alpha")
+                        .help("store message bits in the alpha channel as well"),
+                )
+                .arg(
+                    Arg::new("matching")
+                        .long("matching")
+                        .help("change samples by ±1 instead of overwriting their low bits"),
+                )
+                .arg(
+                    Arg::new("matrix")
+                        .long("matrix")
+                        .help("use Hamming code matrix embedding to change fewer samples"),
+                )
+                .arg(
+                    Arg::new("adaptive")
+                        .takes_value(true)
+                        .long("adaptive")
+                        .value_name("strength")
+                        .help("prefer textured areas of the image, the more the higher the strength"),
                 )
                 .arg(
                     Arg::new("chunk_type")
                         .takes_value(true)
-                        .required(true)
+                        .required_if_eq("method", "chunk")
                         .short('t')
                         .value_name("chunk_type")
-                        .help("specify the chunk type of the message"),
+                        .help("specify the chunk type of the message, or a JPEG segment (APP0-APP15, COM) GIF extension (comment, application) or RIFF FourCC"),
                 )
                 .arg(
                     Arg::new("message")
@@ -44,58 +108,564 @@ fn main() -> Result<()> {
                         .short('o')
                         .value_name("output")
                         .help("specify the path of the output png image"),
+                )
+                .arg(
+                    Arg::new("strip_trailer")
+                        .long("strip-trailer")
+                        .help("drop any data stored after the end of the image"),
                 ),
         )
         .subcommand(
             App::new("decode")
-                .about("Decodes a PNG image into a message")
+                .about("Decodes a PNG, JPEG, GIF, WebP or WAV file into a message")
                 .arg(
                     Arg::new("path")
                         .takes_value(true)
                         .required(true)
                         .short('p')
                         .value_name("chunk_type")
-                        .help("specify the path of the png image"),
+                        .help("specify the path of the png, jpeg, gif, webp or wav file"),
+                )
+                .arg(
+                    Arg::new("method")
+                        .takes_value(true)
+                        .long("method")
+                        .value_name("method")
+                        .possible_values(Method::NAMES)
+                        .default_value("chunk")
+                        .help("specify where the message is stored"),
+                )
+                .arg(
+                    Arg::new("key")
+                        .takes_value(true)
+                        .long("key")
+                        .value_name("key")
+                        .help("specify the key that scatters the message over the pixels"),
+                )
+                .arg(
+                    Arg::new("bits")
+                        .takes_value(true)
+                        .long("bits")
+                        .value_name("bits")
+                        .possible_values(["1", "2", "3", "4"])
+                        .default_value("1")
+                        .help("specify how many low bits of each sample carry the message"),
+                )
+                .arg(
+                    Arg::new("alpha")
+                        .long("alpha")
+                        .help("store message bits in the alpha channel as well"),
+                )
+                .arg(
+                    Arg::new("matrix")
+                        .long("matrix")
+                        .help("use Hamming code matrix embedding to change fewer samples"),
+                )
+                .arg(
+                    Arg::new("adaptive")
+                        .takes_value(true)
+                        .long("adaptive")
+                        .value_name("strength")
+                        .help("prefer textured areas of the image, the more the higher the strength"),
                 )
                 .arg(
                     Arg::new("chunk_type")
                         .takes_value(true)
-                        .required(true)
+                        .required_if_eq("method", "chunk")
                         .short('t')
                         .value_name("type")
-                        .help("specify the chunk type of the message"),
+                        .help("specify the chunk type of the message, or a JPEG segment (APP0-APP15, COM) GIF extension (comment, application) or RIFF FourCC"),
                 ),
         )
         .subcommand(
             App::new("remove")
-                .about("Removes the secret message from the PNG image")
+                .about("Removes the secret message from the PNG, JPEG, GIF, WebP or WAV file")
                 .arg(
                     Arg::new("path")
                         .takes_value(true)
                         .required(true)
                         .short('p')
                         .value_name("path")
-                        .help("specify the path of the png image"),
+                        .help("specify the path of the png, jpeg, gif, webp or wav file"),
+                )
+                .arg(
+                    Arg::new("method")
+                        .takes_value(true)
+                        .long("method")
+                        .value_name("method")
+                        .possible_values(Method::NAMES)
+                        .default_value("chunk")
+                        .help("specify where the message is stored"),
+                )
+                .arg(
+                    Arg::new("key")
+                        .takes_value(true)
+                        .long("key")
+                        .value_name("key")
+                        .help("specify the key that scatters the message over the pixels"),
+                )
+                .arg(
+                    Arg::new("bits")
+                        .takes_value(true)
+                        .long("bits")
+                        .value_name("bits")
+                        .possible_values(["1", "2", "3", "4"])
+                        .default_value("1")
+                        .help("specify how many low bits of each sample carry the message"),
+                )
+                .arg(
+                    Arg::new("alpha")
+                        .long("alpha")
+                        .help("store message bits in the alpha channel as well"),
+                )
+                .arg(
+                    Arg::new("matching")
+                        .long("matching")
+                        .help("change samples by ±1 instead of overwriting their low bits"),
+                )
+                .arg(
+                    Arg::new("matrix")
+                        .long("matrix")
+                        .help("use Hamming code matrix embedding to change fewer samples"),
+                )
+                .arg(
+                    Arg::new("adaptive")
+                        .takes_value(true)
+                        .long("adaptive")
+                        .value_name("strength")
+                        .help("prefer textured areas of the image, the more the higher the strength"),
                 )
                 .arg(
                     Arg::new("chunk_type")
                         .takes_value(true)
-                        .required(true)
+                        .required_if_eq("method", "chunk")
                         .short('t')
                         .value_name("type")
-                        .help("specify the chunk type of the message"),
+                        .help("specify the chunk type of the message, or a JPEG segment (APP0-APP15, COM) GIF extension (comment, application) or RIFF FourCC"),
+                )
+                .arg(
+                    Arg::new("strip_trailer")
+                        .long("strip-trailer")
+                        .help("drop any data stored after the end of the image"),
+                ),
+        )
+        .subcommand(
+            App::new("chunk")
+                .about("Copies raw chunk data to and from files")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    App::new("export")
+                        .about("Writes the data of a chunk to a file")
+                        .arg(
+                            Arg::new("path")
+                                .takes_value(true)
+                                .required(true)
+                                .short('p')
+                                .value_name("path")
+                                .help("specify the path of the png image"),
+                        )
+                        .arg(
+                            Arg::new("chunk_type")
+                                .takes_value(true)
+                                .short('t')
+                                .value_name("type")
+                                .required_unless_present("index")
+                                .conflicts_with("index")
+                                .help("export the first chunk of this type"),
+                        )
+                        .arg(
+                            Arg::new("index")
+                                .takes_value(true)
+                                .short('i')
+                                .value_name("index")
+                                .help("export the chunk at this position, as listed by info"),
+                        )
+                        .arg(
+                            Arg::new("output")
+                                .takes_value(true)
+                                .required(true)
+                                .short('o')
+                                .value_name("output")
+                                .help("specify the file to write the data to"),
+                        ),
+                )
+                .subcommand(
+                    App::new("import")
+                        .about("Inserts a chunk holding the contents of a file")
+                        .arg(
+                            Arg::new("path")
+                                .takes_value(true)
+                                .required(true)
+                                .short('p')
+                                .value_name("path")
+                                .help("specify the path of the png image"),
+                        )
+                        .arg(
+                            Arg::new("chunk_type")
+                                .takes_value(true)
+                                .required(true)
+                                .short('t')
+                                .value_name("type")
+                                .help("specify the chunk type"),
+                        )
+                        .arg(
+                            Arg::new("file")
+                                .takes_value(true)
+                                .required(true)
+                                .short('f')
+                                .value_name("file")
+                                .help("specify the file holding the chunk data"),
+                        )
+                        .arg(
+                            Arg::new("index")
+                                .takes_value(true)
+                                .short('i')
+                                .value_name("index")
+                                .help("the position of the new chunk, as listed by info; defaults to before IEND"),
+                        )
+                        .arg(
+                            Arg::new("output")
+                                .takes_value(true)
+                                .required(false)
+                                .short('o')
+                                .value_name("output")
+                                .help("specify the path of the output png image"),
+                        ),
+                ),
+        )
+        .subcommand(
+            App::new("diff")
+                .about("Compares the chunks and pixels of two PNG images")
+                .arg(
+                    Arg::new("path")
+                        .takes_value(true)
+                        .required(true)
+                        .short('p')
+                        .value_name("path")
+                        .help("specify the path of the original png image"),
+                )
+                .arg(
+                    Arg::new("with")
+                        .takes_value(true)
+                        .required(true)
+                        .short('w')
+                        .long("with")
+                        .value_name("path")
+                        .help("specify the path of the png image to compare with"),
+                )
+                .arg(
+                    Arg::new("verbose")
+                        .short('v')
+                        .help("also list the chunks that are unchanged"),
+                ),
+        )
+        .subcommand(
+            App::new("info")
+                .about("Lists the chunks of a PNG, WebP or WAV file")
+                .arg(
+                    Arg::new("path")
+                        .takes_value(true)
+                        .required(true)
+                        .short('p')
+                        .value_name("path")
+                        .help("specify the path of the png, webp or wav file"),
+                )
+                .arg(
+                    Arg::new("verbose")
+                        .short('v')
+                        .help("print the properties and crc of every chunk"),
+                ),
+        )
+        .subcommand(
+            App::new("asm")
+                .about("Builds a PNG image from a disasm listing")
+                .arg(
+                    Arg::new("path")
+                        .takes_value(true)
+                        .required(true)
+                        .short('p')
+                        .value_name("path")
+                        .help("specify the path of the listing"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .takes_value(true)
+                        .required(true)
+                        .short('o')
+                        .value_name("output")
+                        .help("specify the path of the output png image"),
+                ),
+        )
+        .subcommand(
+            App::new("analyze")
+                .about("Inspects the pixels of a PNG image for hidden data")
+                .setting(AppSettings::ArgRequiredElseHelp)
+                .args_conflicts_with_subcommands(true)
+                .arg(
+                    Arg::new("path")
+                        .takes_value(true)
+                        .short('p')
+                        .value_name("path")
+                        .help("run the chi-square, RS and sample pair detectors on a png image"),
+                )
+                .subcommand(
+                    App::new("bitplane")
+                        .about("Renders one bit plane of a channel as a black and white image")
+                        .arg(
+                            Arg::new("path")
+                                .takes_value(true)
+                                .required(true)
+                                .short('p')
+                                .value_name("path")
+                                .help("specify the path of the png image"),
+                        )
+                        .arg(
+                            Arg::new("channel")
+                                .takes_value(true)
+                                .short('c')
+                                .long("channel")
+                                .value_name("channel")
+                                .default_value("0")
+                                .help("the channel, by index or as r, g, b, a, gray or index"),
+                        )
+                        .arg(
+                            Arg::new("bit")
+                                .takes_value(true)
+                                .short('b')
+                                .long("bit")
+                                .value_name("bit")
+                                .default_value("0")
+                                .help("the bit, 0 being the least significant"),
+                        )
+                        .arg(
+                            Arg::new("xor")
+                                .takes_value(true)
+                                .long("xor")
+                                .value_name("other")
+                                .help("render where the plane differs from the same plane of another image"),
+                        )
+                        .arg(
+                            Arg::new("output")
+                                .takes_value(true)
+                                .required(true)
+                                .short('o')
+                                .value_name("output")
+                                .help("specify the path of the output png image"),
+                        ),
+                ),
+        )
+        .subcommand(
+            App::new("capacity")
+                .about("Shows how many bytes each method can hide in a PNG image")
+                .arg(
+                    Arg::new("path")
+                        .takes_value(true)
+                        .required(true)
+                        .short('p')
+                        .value_name("path")
+                        .help("specify the path of the png image"),
+                ),
+        )
+        .subcommand(
+            App::new("repair")
+                .about("Recovers what it can from a damaged PNG image")
+                .arg(
+                    Arg::new("path")
+                        .takes_value(true)
+                        .required(true)
+                        .short('p')
+                        .value_name("path")
+                        .help("specify the path of the png image"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .takes_value(true)
+                        .required(true)
+                        .short('o')
+                        .value_name("output")
+                        .help("specify the path of the repaired png image"),
+                ),
+        )
+        .subcommand(
+            App::new("sanitize")
+                .about("Strips identifying metadata from a PNG image")
+                .arg(
+                    Arg::new("path")
+                        .takes_value(true)
+                        .required(true)
+                        .short('p')
+                        .value_name("path")
+                        .help("specify the path of the png image"),
+                )
+                .arg(
+                    Arg::new("allow")
+                        .takes_value(true)
+                        .long("allow")
+                        .value_name("types")
+                        .use_value_delimiter(true)
+                        .multiple_values(true)
+                        .conflicts_with("deny")
+                        .help("keep only critical chunks and these, e.g. sRGB,gAMA,private,trailer"),
+                )
+                .arg(
+                    Arg::new("deny")
+                        .takes_value(true)
+                        .long("deny")
+                        .value_name("types")
+                        .use_value_delimiter(true)
+                        .multiple_values(true)
+                        .help("remove these instead of the default tEXt,zTXt,iTXt,eXIf,tIME,iCCP,private,trailer"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .takes_value(true)
+                        .required(false)
+                        .short('o')
+                        .value_name("output")
+                        .help("specify the path of the output png image"),
+                ),
+        )
+        .subcommand(
+            App::new("scan")
+                .about("Flags suspicious chunks in every PNG under a directory")
+                .arg(
+                    Arg::new("path")
+                        .takes_value(true)
+                        .required(true)
+                        .short('p')
+                        .value_name("path")
+                        .help("specify the directory or png image to scan"),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .help("print the report as JSON"),
+                )
+                .arg(
+                    Arg::new("max_ancillary")
+                        .takes_value(true)
+                        .long("max-ancillary")
+                        .value_name("bytes")
+                        .default_value("262144")
+                        .help("flag ancillary chunks larger than this"),
+                )
+                .arg(
+                    Arg::new("max_entropy")
+                        .takes_value(true)
+                        .long("max-entropy")
+                        .value_name("bits")
+                        .default_value("7.5")
+                        .help("flag uncompressed chunks with more entropy per byte than this"),
+                ),
+        )
+        .subcommand(
+            App::new("transplant")
+                .about("Copies ancillary chunks from one PNG image to another")
+                .arg(
+                    Arg::new("path")
+                        .takes_value(true)
+                        .required(true)
+                        .short('p')
+                        .value_name("path")
+                        .help("specify the path of the png image to copy into"),
+                )
+                .arg(
+                    Arg::new("source")
+                        .takes_value(true)
+                        .required(true)
+                        .short('s')
+                        .long("source")
+                        .value_name("source")
+                        .help("specify the path of the png image to copy from"),
+                )
+                .arg(
+                    Arg::new("chunk_type")
+                        .takes_value(true)
+                        .short('t')
+                        .value_name("types")
+                        .use_value_delimiter(true)
+                        .multiple_values(true)
+                        .help("only copy chunks of these types"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .help("also copy chunks that are not safe to copy to a different image"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .takes_value(true)
+                        .required(false)
+                        .short('o')
+                        .value_name("output")
+                        .help("specify the path of the output png image"),
+                ),
+        )
+        .subcommand(
+            App::new("polyglot")
+                .about("Stores a ZIP archive inside a PNG image")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    App::new("create")
+                        .about("Appends a ZIP archive so the image is also a valid archive")
+                        .arg(
+                            Arg::new("path")
+                                .takes_value(true)
+                                .required(true)
+                                .short('p')
+                                .value_name("path")
+                                .help("specify the path of the png image"),
+                        )
+                        .arg(
+                            Arg::new("zip")
+                                .takes_value(true)
+                                .required(true)
+                                .short('z')
+                                .value_name("zip")
+                                .help("specify the path of the zip archive"),
+                        )
+                        .arg(
+                            Arg::new("output")
+                                .takes_value(true)
+                                .required(false)
+                                .short('o')
+                                .value_name("output")
+                                .help("specify the path of the output png image"),
+                        ),
+                )
+                .subcommand(
+                    App::new("list")
+                        .about("Lists the files of the archive in a polyglot")
+                        .arg(
+                            Arg::new("path")
+                                .takes_value(true)
+                                .required(true)
+                                .short('p')
+                                .value_name("path")
+                                .help("specify the path of the png image"),
+                        ),
+                )
+                .subcommand(
+                    App::new("extract")
+                        .about("Extracts the files of the archive in a polyglot")
+                        .arg(
+                            Arg::new("path")
+                                .takes_value(true)
+                                .required(true)
+                                .short('p')
+                                .value_name("path")
+                                .help("specify the path of the png image"),
+                        )
+                        .arg(
+                            Arg::new("dir")
+                                .takes_value(true)
+                                .required(false)
+                                .short('d')
+                                .value_name("dir")
+                                .help("specify the directory to extract into"),
+                        ),
                 ),
         );
-        // .subcommand(
-        //     App::new("print").arg(
-        //         Arg::new("path")
-        //             .takes_value(true)
-        //             .required(true)
-        //             .short('p')
-        //             .value_name("path")
-        //             .help("specify the path of the png image"),
-        //     ),
-        // );
     let get_matches = matches.get_matches_mut();
 
     match get_matches.subcommand() {
@@ -108,9 +678,42 @@ fn main() -> Result<()> {
         Some(("remove", sub_matches)) => {
             commands::remove(sub_matches)?;
         }
-        // Some(("print", sub_matches)) => {
-        //     commands::print(sub_matches)?;
-        // }
+        Some(("chunk", sub_matches)) => {
+            commands::chunk(sub_matches)?;
+        }
+        Some(("diff", sub_matches)) => {
+            commands::diff(sub_matches)?;
+        }
+        Some(("disasm", sub_matches)) => {
+            commands::disasm(sub_matches)?;
+        }
+        Some(("info", sub_matches)) => {
+            commands::info(sub_matches)?;
+        }
+        Some(("asm", sub_matches)) => {
+            commands::asm(sub_matches)?;
+        }
+        Some(("analyze", sub_matches)) => {
+            commands::analyze(sub_matches)?;
+        }
+        Some(("capacity", sub_matches)) => {
+            commands::capacity(sub_matches)?;
+        }
+        Some(("repair", sub_matches)) => {
+            commands::repair(sub_matches)?;
+        }
+        Some(("sanitize", sub_matches)) => {
+            commands::sanitize(sub_matches)?;
+        }
+        Some(("scan", sub_matches)) => {
+            commands::scan(sub_matches)?;
+        }
+        Some(("transplant", sub_matches)) => {
+            commands::transplant(sub_matches)?;
+        }
+        Some(("polyglot", sub_matches)) => {
+            commands::polyglot(sub_matches)?;
+        }
         _ => {
             matches.write_help(&mut std::io::stdout()).expect(
                 "
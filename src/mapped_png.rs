@@ -0,0 +1,215 @@
+use crate::{
+    chunk::{chunk_crc, Chunk},
+    chunk_type::ChunkType,
+    png::Png,
+};
+use anyhow::Result;
+use bytes::Bytes;
+use memmap2::Mmap;
+use std::{convert::TryFrom, path::Path, str::FromStr};
+
+// Where a chunk lives inside the mapped file. Only the length and type are
+// read while scanning; the data and CRC stay untouched until asked for.
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkHeader {
+    pub offset: usize,
+    pub length: u32,
+    pub chunk_type: ChunkType,
+}
+
+impl ChunkHeader {
+    fn data_range(&self) -> std::ops::Range<usize> {
+        self.offset + 8..self.offset + 8 + self.length as usize
+    }
+}
+
+// A png opened read-only through a memory map. Scanning walks the chunk
+// headers in place, so a lookup only faults in the pages holding the headers
+// and the chunks that are actually read.
+pub struct MappedPng {
+    buffer: Bytes,
+    headers: Vec<ChunkHeader>,
+    trailer_offset: usize,
+}
+
+// Walk the chunk headers after the signature. Parsing stops at IEND and the
+// offset of whatever follows it is returned along with the headers. Earlier
+// versions of encode appended their chunk after IEND, so complete chunks with
+// a valid CRC directly after IEND are still read as chunks.
+fn scan(buffer: &[u8]) -> Result<(Vec<ChunkHeader>, usize)> {
+    let iend = ChunkType::from_str("IEND")?;
+    let mut headers = Vec::new();
+    let mut offset = 8;
+    let mut seen_iend = false;
+    while buffer.len() - offset >= 4 {
+        let length = u32::from_be_bytes(buffer[offset..offset + 4].try_into()?);
+        let end = offset + 12 + length as usize;
+        if end > buffer.len() {
+            if seen_iend {
+                break;
+            }
+            return Err(anyhow::anyhow!(
+                "The chunk at offset {} is truncated",
+                offset
+            ));
+        }
+        let chunk_type: [u8; 4] = buffer[offset + 4..offset + 8].try_into()?;
+        let chunk_type = ChunkType::try_from(chunk_type)?;
+        if seen_iend {
+            let crc = u32::from_be_bytes(buffer[end - 4..end].try_into()?);
+            if !chunk_type.is_alpha() || crc != chunk_crc(&chunk_type, &buffer[offset + 8..end - 4])
+            {
+                break;
+            }
+        }
+        headers.push(ChunkHeader {
+            offset,
+            length,
+            chunk_type,
+        });
+        seen_iend |= chunk_type == iend;
+        offset = end;
+    }
+    Ok((headers, offset))
+}
+
+impl MappedPng {
+    pub fn open(path: impl AsRef<Path>) -> Result<MappedPng> {
+        let file = std::fs::File::open(path)?;
+        // Safety: the map is read-only and we don't guard against the file
+        // being truncated by another process while it is open.
+        let mmap = unsafe { Mmap::map(&file)? };
+        MappedPng::from_bytes(Bytes::from_owner(mmap))
+    }
+
+    pub fn from_bytes(buffer: Bytes) -> Result<MappedPng> {
+        if buffer.len() < 8 || buffer[..8] != Png::STANDARD_HEADER {
+            return Err(anyhow::anyhow!(
+                "The header of the PNG is not equal to the standard header"
+            ));
+        }
+        let (headers, trailer_offset) = scan(&buffer)?;
+        Ok(MappedPng {
+            buffer,
+            headers,
+            trailer_offset,
+        })
+    }
+
+    // The whole file, for formats layered on top of the png such as a polyglot.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    pub fn headers(&self) -> &[ChunkHeader] {
+        &self.headers
+    }
+
+    // Read a single chunk. The data is a view into the map, not a copy.
+    pub fn chunk(&self, header: &ChunkHeader) -> Chunk {
+        let range = header.data_range();
+        let crc = u32::from_be_bytes(self.buffer[range.end..range.end + 4].try_into().unwrap());
+        Chunk {
+            length: header.length,
+            chunk_type: header.chunk_type,
+            data: self.buffer.slice(range),
+            crc,
+        }
+    }
+
+    pub fn trailer_offset(&self) -> usize {
+        self.trailer_offset
+    }
+
+    // Everything after the last chunk, exactly as it appears in the file.
+    pub fn trailer(&self) -> Bytes {
+        self.buffer.slice(self.trailer_offset..)
+    }
+
+    // Parse every chunk, for callers that need the whole image after all.
+    pub fn to_png(&self) -> Result<Png> {
+        Png::from_bytes(self.buffer.clone())
+    }
+
+    pub fn chunk_by_type(&self, chunk_type: &str) -> Option<Chunk> {
+        let chunk_type = ChunkType::from_str(chunk_type).ok()?;
+        self.headers
+            .iter()
+            .find(|header| header.chunk_type == chunk_type)
+            .map(|header| self.chunk(header))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_png() -> Png {
+        let chunks = vec![
+            Chunk::new(
+                ChunkType::from_str("FrSt").unwrap(),
+                b"I am the first chunk".to_vec(),
+            ),
+            Chunk::new(
+                ChunkType::from_str("miDl").unwrap(),
+                b"I am another chunk".to_vec(),
+            ),
+            Chunk::new(
+                ChunkType::from_str("LASt").unwrap(),
+                b"I am the last chunk".to_vec(),
+            ),
+        ];
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_headers_match_chunks() {
+        let png = testing_png();
+        let mapped = MappedPng::from_bytes(Bytes::from(png.as_bytes())).unwrap();
+        assert_eq!(mapped.headers().len(), 3);
+        assert_eq!(mapped.headers()[0].offset, 8);
+        for (header, chunk) in mapped.headers().iter().zip(png.chunks()) {
+            assert_eq!(header.chunk_type, chunk.chunk_type);
+            assert_eq!(header.length, chunk.length());
+            assert_eq!(mapped.chunk(header).as_bytes(), chunk.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_chunk_by_type() {
+        let mapped = MappedPng::from_bytes(Bytes::from(testing_png().as_bytes())).unwrap();
+        let chunk = mapped.chunk_by_type("miDl").unwrap();
+        assert_eq!(&chunk.data_as_string().unwrap(), "I am another chunk");
+        assert!(mapped.chunk_by_type("NoNe").is_none());
+    }
+
+    #[test]
+    fn test_open_file() {
+        let path = std::env::temp_dir().join(format!(
+            "encodeme_{}_test_open_file.png",
+            std::process::id()
+        ));
+        std::fs::write(&path, testing_png().as_bytes()).unwrap();
+        let mapped = MappedPng::open(path.to_str().unwrap()).unwrap();
+        assert_eq!(mapped.headers().len(), 3);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_trailer() {
+        let mut chunks = testing_png().chunks().to_vec();
+        chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()));
+        let mut bytes = Png::from_chunks(chunks).as_bytes();
+        bytes.extend_from_slice(b"appended data");
+        let mapped = MappedPng::from_bytes(Bytes::from(bytes)).unwrap();
+        assert_eq!(mapped.headers().len(), 4);
+        assert_eq!(&mapped.trailer()[..], b"appended data");
+    }
+
+    #[test]
+    fn test_truncated_chunk() {
+        let bytes = testing_png().as_bytes();
+        let mapped = MappedPng::from_bytes(Bytes::from(bytes[..bytes.len() - 3].to_vec()));
+        assert!(mapped.is_err());
+    }
+}
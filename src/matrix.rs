@@ -0,0 +1,223 @@
+use crate::lsb::{self, LENGTH_LEN};
+use crate::pixels::Image;
+use anyhow::Result;
+
+// Matrix embedding with the (1, 2^k - 1, k) Hamming codes: every block of
+// n = 2^k - 1 least significant bits carries k message bits as its syndrome,
+// and at most one bit of the block has to change to make the syndrome match.
+// A larger k changes fewer samples per message bit but holds less, so k is
+// the largest value for which the message still fits.
+//
+// The first HEADER_BITS samples hold k in plain LSB; the blocks follow and
+// carry the length-prefixed message like plain LSB embedding does.
+
+pub const HEADER_BITS: usize = 8;
+pub const MAX_K: u8 = 16;
+
+fn block_len(k: u8) -> usize {
+    (1 << k) - 1
+}
+
+// Message bits that fit in `positions` one-bit samples with the given k.
+pub fn capacity_bits(positions: usize, k: u8) -> usize {
+    positions.saturating_sub(HEADER_BITS) / block_len(k) * k as usize
+}
+
+// The largest k that fits `bits` message bits, if any does.
+pub fn choose_k(positions: usize, bits: usize) -> Option<u8> {
+    (1..=MAX_K)
+        .rev()
+        .find(|&k| capacity_bits(positions, k) >= bits)
+}
+
+// Expected fraction of the samples in the blocks that change, for random
+// message bits: a block needs no change only when its syndrome already matches.
+pub fn expected_change_rate(k: u8) -> f64 {
+    (1.0 - 0.5f64.powi(k as i32)) / block_len(k) as f64
+}
+
+fn syndrome(image: &Image, block: &[usize]) -> usize {
+    block
+        .iter()
+        .enumerate()
+        .filter(|(_, &position)| image.samples[position] & 1 == 1)
+        .fold(0, |syndrome, (i, _)| syndrome ^ (i + 1))
+}
+
+fn bits_of(bytes: &[u8]) -> impl Iterator<Item = usize> + '_ {
+    bytes
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) as usize & 1))
+}
+
+// Embed `payload` and return k and the number of samples used, header included.
+pub fn embed_at(
+    image: &mut Image,
+    positions: &[usize],
+    payload: &[u8],
+    matching: bool,
+) -> Result<(u8, usize)> {
+    let data = lsb::frame(payload)?;
+    let k = choose_k(positions.len(), data.len() * 8).ok_or_else(|| {
+        anyhow::anyhow!(
+            "The message is {} bytes but only {} fit in the image",
+            payload.len(),
+            (capacity_bits(positions.len(), 1) / 8).saturating_sub(LENGTH_LEN)
+        )
+    })?;
+    lsb::write_bits(image, &positions[..HEADER_BITS], 1, &[k], matching);
+
+    let n = block_len(k);
+    let max = image.header.max_value();
+    let mut rng = rand::thread_rng();
+    let mut stream = bits_of(&data).peekable();
+    let mut used = HEADER_BITS;
+    for block in positions[HEADER_BITS..].chunks_exact(n) {
+        if stream.peek().is_none() {
+            break;
+        }
+        let message = (0..k).fold(0, |m, _| (m << 1) | stream.next().unwrap_or(0));
+        let flip = syndrome(image, block) ^ message;
+        if flip != 0 {
+            let sample = &mut image.samples[block[flip - 1]];
+            *sample = if matching {
+                lsb::matched(*sample, (*sample & 1) ^ 1, 1, max, &mut rng)
+            } else {
+                *sample ^ 1
+            };
+        }
+        used += n;
+    }
+    Ok((k, used))
+}
+
+pub fn extract_at(image: &Image, positions: &[usize]) -> Result<Vec<u8>> {
+    if positions.len() < HEADER_BITS {
+        return Err(anyhow::anyhow!("No message found in the image"));
+    }
+    let k = lsb::read_bits(image, &positions[..HEADER_BITS], 1, 1)[0];
+    if k == 0 || k > MAX_K {
+        return Err(anyhow::anyhow!("No message found in the image"));
+    }
+
+    let mut bytes = Vec::new();
+    let (mut byte, mut filled) = (0u8, 0);
+    let mut wanted = LENGTH_LEN;
+    for block in positions[HEADER_BITS..].chunks_exact(block_len(k)) {
+        let message = syndrome(image, block);
+        for i in (0..k).rev() {
+            byte = (byte << 1) | ((message >> i) & 1) as u8;
+            filled += 1;
+            if filled == 8 {
+                bytes.push(byte);
+                (byte, filled) = (0, 0);
+                if bytes.len() == LENGTH_LEN {
+                    let length = u32::from_be_bytes(bytes[..].try_into()?) as usize;
+                    if (LENGTH_LEN + length) * 8 > capacity_bits(positions.len(), k) {
+                        return Err(anyhow::anyhow!("No message found in the image"));
+                    }
+                    wanted = LENGTH_LEN + length;
+                }
+                if bytes.len() == wanted {
+                    return Ok(bytes.split_off(LENGTH_LEN));
+                }
+            }
+        }
+    }
+    Err(anyhow::anyhow!("No message found in the image"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsb::LsbOptions;
+    use crate::pixels::ImageHeader;
+
+    fn testing_image() -> Image {
+        let header = ImageHeader {
+            width: 64,
+            height: 64,
+            bit_depth: 8,
+            color_type: 2,
+            interlace: 0,
+        };
+        let samples = (0..64 * 64 * 3).map(|i| ((i * 131) % 256) as u16).collect();
+        Image { header, samples }
+    }
+
+    fn matrix() -> LsbOptions {
+        LsbOptions {
+            matrix: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_syndrome_decoding() {
+        let mut image = testing_image();
+        let positions: Vec<usize> = (0..image.samples.len()).collect();
+        let (k, used) = embed_at(&mut image, &positions, b"My secret message", false).unwrap();
+        assert_eq!(k, choose_k(positions.len(), (17 + 4) * 8).unwrap());
+        assert!(used <= HEADER_BITS + (17 + 4) * 8 / k as usize * block_len(k) + block_len(k));
+        assert_eq!(
+            extract_at(&image, &positions).unwrap(),
+            b"My secret message"
+        );
+    }
+
+    #[test]
+    fn test_k_shrinks_with_payload() {
+        let positions = 64 * 64 * 3;
+        let small = choose_k(positions, 21 * 8).unwrap();
+        let large = choose_k(positions, 1000 * 8).unwrap();
+        assert!(small > large);
+        assert_eq!(choose_k(positions, positions), None);
+        assert_eq!(choose_k(positions, positions - HEADER_BITS), Some(1));
+    }
+
+    #[test]
+    fn test_at_most_one_change_per_block() {
+        let original = testing_image();
+        let mut image = original.clone();
+        let report = lsb::embed(&mut image, b"My secret message", &matrix()).unwrap();
+        let k = report.matrix_k.unwrap();
+        let blocks = (report.samples - HEADER_BITS) / block_len(k);
+        assert!(report.changed <= HEADER_BITS + blocks);
+        assert!(report.change_rate() < 0.5);
+        assert_eq!(
+            lsb::extract(&image, &matrix()).unwrap(),
+            b"My secret message"
+        );
+        for (a, b) in original.samples.iter().zip(&image.samples) {
+            assert!(a.abs_diff(*b) <= 1);
+        }
+    }
+
+    #[test]
+    fn test_with_key_and_matching() {
+        let mut image = testing_image();
+        let options = LsbOptions {
+            key: Some(b"hunter2".to_vec()),
+            matching: true,
+            ..matrix()
+        };
+        lsb::embed(&mut image, b"My secret message", &options).unwrap();
+        assert_eq!(
+            lsb::extract(&image, &options).unwrap(),
+            b"My secret message"
+        );
+    }
+
+    #[test]
+    fn test_expected_change_rate() {
+        assert_eq!(expected_change_rate(1), 0.5);
+        assert!((expected_change_rate(3) - 0.875 / 7.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_too_large() {
+        let mut image = testing_image();
+        let payload = vec![0; 64 * 64 * 3 / 8];
+        assert!(lsb::embed(&mut image, &payload, &matrix()).is_err());
+    }
+}
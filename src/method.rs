@@ -0,0 +1,75 @@
+use std::fmt;
+use std::str::FromStr;
+
+// Where a message is stored inside the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    // A chunk of the given type, inserted before IEND.
+    Chunk,
+    // The text of a tEXt chunk with the encodeme keyword.
+    Text,
+    // A framed payload appended after IEND.
+    Trailer,
+    // The low bits of the pixels of the image.
+    Lsb,
+    // Chunks of the given type between the frames of an animated png.
+    FrameChunks,
+    // The low bits of the pixels of every frame of an animated png.
+    FrameLsb,
+}
+
+impl FromStr for Method {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "chunk" => Ok(Method::Chunk),
+            "text" => Ok(Method::Text),
+            "trailer" => Ok(Method::Trailer),
+            "lsb" => Ok(Method::Lsb),
+            "frame-chunks" => Ok(Method::FrameChunks),
+            "frame-lsb" => Ok(Method::FrameLsb),
+            _ => Err(anyhow::anyhow!("Unknown method: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Method::Chunk => write!(f, "chunk"),
+            Method::Text => write!(f, "text"),
+            Method::Trailer => write!(f, "trailer"),
+            Method::Lsb => write!(f, "lsb"),
+            Method::FrameChunks => write!(f, "frame-chunks"),
+            Method::FrameLsb => write!(f, "frame-lsb"),
+        }
+    }
+}
+
+impl Method {
+    pub const NAMES: [&'static str; 6] = [
+        "chunk",
+        "text",
+        "trailer",
+        "lsb",
+        "frame-chunks",
+        "frame-lsb",
+    ];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_method_round_trip() {
+        for name in Method::NAMES {
+            assert_eq!(Method::from_str(name).unwrap().to_string(), name);
+        }
+    }
+
+    #[test]
+    fn test_unknown_method() {
+        assert!(Method::from_str("pixels").is_err());
+    }
+}
@@ -0,0 +1,445 @@
+use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png};
+use anyhow::Result;
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+// Decoding of the image data into samples and back. Only what the pixel
+// based methods need is supported: non-interlaced images of any color type
+// and bit depth. Every sample is widened to a u16 regardless of bit depth.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageHeader {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: u8,
+    pub interlace: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub header: ImageHeader,
+    // Samples in row-major order, `channels()` per pixel.
+    pub samples: Vec<u16>,
+}
+
+// Largest IDAT written back; matches what common encoders emit.
+const IDAT_SIZE: usize = 64 * 1024;
+
+impl TryFrom<&[u8]> for ImageHeader {
+    type Error = anyhow::Error;
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        if value.len() != 13 {
+            return Err(anyhow::anyhow!("The IHDR chunk must be 13 bytes long"));
+        }
+        let header = ImageHeader {
+            width: u32::from_be_bytes(value[0..4].try_into()?),
+            height: u32::from_be_bytes(value[4..8].try_into()?),
+            bit_depth: value[8],
+            color_type: value[9],
+            interlace: value[12],
+        };
+        let valid_depths: &[u8] = match header.color_type {
+            0 => &[1, 2, 4, 8, 16],
+            3 => &[1, 2, 4, 8],
+            2 | 4 | 6 => &[8, 16],
+            color_type => return Err(anyhow::anyhow!("Invalid color type {}", color_type)),
+        };
+        if !valid_depths.contains(&header.bit_depth) {
+            return Err(anyhow::anyhow!(
+                "Invalid bit depth {} for color type {}",
+                header.bit_depth,
+                header.color_type
+            ));
+        }
+        if header.width == 0 || header.height == 0 {
+            return Err(anyhow::anyhow!("The image is empty"));
+        }
+        Ok(header)
+    }
+}
+
+impl ImageHeader {
+    pub fn from_png(png: &Png) -> Result<ImageHeader> {
+        let ihdr = png
+            .chunk_by_type("IHDR")
+            .ok_or_else(|| anyhow::anyhow!("The PNG has no IHDR chunk"))?;
+        ImageHeader::try_from(ihdr.data())
+    }
+
+    pub fn channels(&self) -> usize {
+        match self.color_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        self.color_type == 4 || self.color_type == 6
+    }
+
+    pub fn is_palette(&self) -> bool {
+        self.color_type == 3
+    }
+
+    pub fn max_value(&self) -> u16 {
+        ((1u32 << self.bit_depth) - 1) as u16
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth as usize
+    }
+
+    // Bytes per complete pixel, rounded up to 1, as used by the filters.
+    fn filter_distance(&self) -> usize {
+        (self.bits_per_pixel() / 8).max(1)
+    }
+
+    pub fn row_bytes(&self) -> usize {
+        (self.width as usize * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    // Size of the filtered scanlines, a filter byte in front of each row. The
+    // dimensions come from an untrusted file, so the product may not fit.
+    pub fn data_len(&self) -> Result<usize> {
+        (self.row_bytes() + 1)
+            .checked_mul(self.height as usize)
+            .ok_or_else(|| {
+                anyhow::anyhow!("The image is too large: {}x{}", self.width, self.height)
+            })
+    }
+
+    // The IHDR chunk data, with the default compression and filter methods.
+    pub fn as_bytes(&self) -> [u8; 13] {
+        let mut bytes = [0; 13];
+        bytes[0..4].copy_from_slice(&self.width.to_be_bytes());
+        bytes[4..8].copy_from_slice(&self.height.to_be_bytes());
+        bytes[8] = self.bit_depth;
+        bytes[9] = self.color_type;
+        bytes[12] = self.interlace;
+        bytes
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+fn unfilter(filter: u8, row: &mut [u8], prev: &[u8], bpp: usize) -> Result<()> {
+    for i in 0..row.len() {
+        let a = if i >= bpp { row[i - bpp] } else { 0 };
+        let b = prev[i];
+        let c = if i >= bpp { prev[i - bpp] } else { 0 };
+        let predictor = match filter {
+            0 => 0,
+            1 => a,
+            2 => b,
+            3 => ((a as u16 + b as u16) / 2) as u8,
+            4 => paeth(a, b, c),
+            _ => return Err(anyhow::anyhow!("Invalid filter type {}", filter)),
+        };
+        row[i] = row[i].wrapping_add(predictor);
+    }
+    Ok(())
+}
+
+fn filter(filter: u8, row: &[u8], prev: &[u8], bpp: usize, out: &mut Vec<u8>) {
+    out.push(filter);
+    for i in 0..row.len() {
+        let a = if i >= bpp { row[i - bpp] } else { 0 };
+        let b = prev[i];
+        let c = if i >= bpp { prev[i - bpp] } else { 0 };
+        let predictor = match filter {
+            1 => a,
+            2 => b,
+            3 => ((a as u16 + b as u16) / 2) as u8,
+            4 => paeth(a, b, c),
+            _ => 0,
+        };
+        out.push(row[i].wrapping_sub(predictor));
+    }
+}
+
+// Choose the filter with the smallest sum of absolute differences, the
+// heuristic suggested by the PNG specification.
+fn filter_row(row: &[u8], prev: &[u8], bpp: usize, out: &mut Vec<u8>) {
+    let mut best = Vec::new();
+    let mut best_score = u64::MAX;
+    let mut candidate = Vec::with_capacity(row.len() + 1);
+    for filter_type in 0..5 {
+        candidate.clear();
+        filter(filter_type, row, prev, bpp, &mut candidate);
+        let score: u64 = candidate[1..]
+            .iter()
+            .map(|&x| (x as i8).unsigned_abs() as u64)
+            .sum();
+        if score < best_score {
+            best_score = score;
+            std::mem::swap(&mut best, &mut candidate);
+        }
+    }
+    out.extend_from_slice(&best);
+}
+
+impl Image {
+    // Decode a zlib stream of filtered scanlines, e.g. the concatenated IDAT data.
+    pub fn decode(header: ImageHeader, data: &[u8]) -> Result<Image> {
+        if header.interlace != 0 {
+            return Err(anyhow::anyhow!("Interlaced images are not supported"));
+        }
+        let row_bytes = header.row_bytes();
+        let expected = header.data_len()?;
+        // The buffer grows with the data actually inflated rather than with
+        // what the header claims, and inflating stops just past the image.
+        let mut raw = Vec::new();
+        ZlibDecoder::new(data)
+            .take(expected as u64 + 1)
+            .read_to_end(&mut raw)?;
+        if raw.len() < expected {
+            return Err(anyhow::anyhow!(
+                "The image data is {} bytes, expected {}",
+                raw.len(),
+                expected
+            ));
+        }
+
+        let bpp = header.filter_distance();
+        let depth = header.bit_depth as usize;
+        let samples_per_row = header.width as usize * header.channels();
+        let mut samples = Vec::with_capacity(
+            samples_per_row
+                .checked_mul(header.height as usize)
+                .ok_or_else(|| anyhow::anyhow!("The image has too many samples"))?,
+        );
+        let mut prev = vec![0; row_bytes];
+        for line in raw[..expected].chunks_exact(row_bytes + 1) {
+            let mut row = line[1..].to_vec();
+            unfilter(line[0], &mut row, &prev, bpp)?;
+            match depth {
+                8 => samples.extend(row.iter().map(|&x| x as u16)),
+                16 => samples.extend(
+                    row.chunks_exact(2)
+                        .map(|x| u16::from_be_bytes([x[0], x[1]])),
+                ),
+                _ => {
+                    let mask = (1u16 << depth) - 1;
+                    samples.extend((0..samples_per_row).map(|i| {
+                        let bit = i * depth;
+                        (row[bit / 8] as u16 >> (8 - depth - bit % 8)) & mask
+                    }));
+                }
+            }
+            prev = row;
+        }
+        Ok(Image { header, samples })
+    }
+
+    // Encode back into a zlib stream of filtered scanlines.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let header = self.header;
+        let row_bytes = header.row_bytes();
+        let depth = header.bit_depth as usize;
+        let bpp = header.filter_distance();
+        let samples_per_row = header.width as usize * header.channels();
+        let mut filtered = Vec::with_capacity(header.data_len()?);
+        let mut prev = vec![0; row_bytes];
+        for samples in self.samples.chunks_exact(samples_per_row) {
+            let row: Vec<u8> = match depth {
+                8 => samples.iter().map(|&x| x as u8).collect(),
+                16 => samples.iter().flat_map(|x| x.to_be_bytes()).collect(),
+                _ => {
+                    let mut row = vec![0; row_bytes];
+                    for (i, &sample) in samples.iter().enumerate() {
+                        let bit = i * depth;
+                        row[bit / 8] |= (sample as u8) << (8 - depth - bit % 8);
+                    }
+                    row
+                }
+            };
+            filter_row(&row, &prev, bpp, &mut filtered);
+            prev = row;
+        }
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&filtered)?;
+        Ok(encoder.finish()?)
+    }
+
+    pub fn channels(&self) -> usize {
+        self.header.channels()
+    }
+
+    pub fn from_png(png: &Png) -> Result<Image> {
+        let header = ImageHeader::from_png(png)?;
+        let idat = ChunkType::from_str("IDAT")?;
+        let data: Vec<u8> = png
+            .chunks()
+            .iter()
+            .filter(|chunk| chunk.chunk_type == idat)
+            .flat_map(|chunk| chunk.data().iter().copied())
+            .collect();
+        Image::decode(header, &data)
+    }
+
+    // Replace the IDAT chunks of `png` with this image, where the first one was.
+    pub fn write_to_png(&self, png: &mut Png) -> Result<()> {
+        let idat = ChunkType::from_str("IDAT")?;
+        let data = self.encode()?;
+        let mut chunks = png.chunks().to_vec();
+        let index = chunks
+            .iter()
+            .position(|chunk| chunk.chunk_type == idat)
+            .ok_or_else(|| anyhow::anyhow!("The PNG has no IDAT chunk"))?;
+        chunks.retain(|chunk| chunk.chunk_type != idat);
+        let new_chunks = data
+            .chunks(IDAT_SIZE)
+            .map(|part| Chunk::new(idat, part.to_vec()));
+        chunks.splice(index..index, new_chunks);
+        png.set_chunks(chunks);
+        Ok(())
+    }
+
+    // A new PNG holding just this image. Palette images have no PLTE to go
+    // with them, so only the other color types make sense here.
+    pub fn to_png(&self) -> Result<Png> {
+        let mut png = Png::from_chunks(vec![
+            Chunk::new(
+                ChunkType::from_str("IHDR")?,
+                self.header.as_bytes().to_vec(),
+            ),
+            Chunk::new(ChunkType::from_str("IDAT")?, Vec::new()),
+            Chunk::new(ChunkType::from_str("IEND")?, Vec::new()),
+        ]);
+        self.write_to_png(&mut png)?;
+        Ok(png)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(width: u32, height: u32, bit_depth: u8, color_type: u8) -> ImageHeader {
+        ImageHeader {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            interlace: 0,
+        }
+    }
+
+    fn testing_image(header: ImageHeader) -> Image {
+        let count = (header.width * header.height) as usize * header.channels();
+        let samples = (0..count)
+            .map(|i| ((i * 7919) % (header.max_value() as usize + 1)) as u16)
+            .collect();
+        Image { header, samples }
+    }
+
+    #[test]
+    fn test_round_trip_every_format() {
+        for (bit_depth, color_type) in [
+            (1, 0),
+            (2, 0),
+            (4, 3),
+            (8, 0),
+            (16, 0),
+            (8, 2),
+            (16, 2),
+            (8, 4),
+            (8, 6),
+            (16, 6),
+        ] {
+            let image = testing_image(header(5, 3, bit_depth, color_type));
+            let decoded = Image::decode(image.header, &image.encode().unwrap()).unwrap();
+            assert_eq!(
+                decoded, image,
+                "bit depth {} color type {}",
+                bit_depth, color_type
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_every_filter() {
+        let image = testing_image(header(4, 5, 8, 2));
+        let row_bytes = image.header.row_bytes();
+        let rows: Vec<Vec<u8>> = image
+            .samples
+            .chunks(row_bytes)
+            .map(|row| row.iter().map(|&x| x as u8).collect())
+            .collect();
+        let mut filtered = Vec::new();
+        let mut prev = vec![0; row_bytes];
+        for (filter_type, row) in rows.iter().enumerate() {
+            filter(filter_type as u8, row, &prev, 3, &mut filtered);
+            prev = row.clone();
+        }
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&filtered).unwrap();
+        let decoded = Image::decode(image.header, &encoder.finish().unwrap()).unwrap();
+        assert_eq!(decoded, image);
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        let mut ihdr = [0, 0, 0, 4, 0, 0, 0, 4, 16, 3, 0, 0, 0];
+        assert!(ImageHeader::try_from(&ihdr[..]).is_err());
+        ihdr[8] = 8;
+        assert!(ImageHeader::try_from(&ihdr[..]).is_ok());
+    }
+
+    #[test]
+    fn test_png_round_trip() {
+        let image = testing_image(header(3, 3, 8, 6));
+        let ihdr = [0, 0, 0, 3, 0, 0, 0, 3, 8, 6, 0, 0, 0];
+        let mut png = Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), ihdr.to_vec()),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), Vec::new()),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ]);
+        image.write_to_png(&mut png).unwrap();
+        assert_eq!(png.chunks().len(), 3);
+        assert_eq!(Image::from_png(&png).unwrap(), image);
+    }
+
+    #[test]
+    fn test_to_png() {
+        let image = testing_image(header(7, 2, 1, 0));
+        let png = image.to_png().unwrap();
+        assert_eq!(ImageHeader::from_png(&png).unwrap(), image.header);
+        assert_eq!(Image::from_png(&png).unwrap(), image);
+    }
+
+    #[test]
+    fn test_truncated_data() {
+        let image = testing_image(header(4, 4, 8, 0));
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&[0; 10]).unwrap();
+        assert!(Image::decode(image.header, &encoder.finish().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_huge_header() {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&[0; 10]).unwrap();
+        let data = encoder.finish().unwrap();
+        assert!(Image::decode(header(u32::MAX, u32::MAX, 16, 6), &data).is_err());
+        assert!(Image::decode(header(1 << 30, 1 << 30, 8, 0), &data).is_err());
+    }
+}
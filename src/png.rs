@@ -1,55 +1,16 @@
-use crate::{chunk::Chunk, chunk_type::ChunkType};
+use crate::{chunk::Chunk, chunk_type::ChunkType, mapped_png::MappedPng};
 use anyhow::{Ok, Result};
-use std::{
-    convert::TryFrom,
-    fmt::Display,
-    io::{BufReader, Read},
-    str::FromStr,
-};
+use bytes::Bytes;
+use std::{convert::TryFrom, fmt::Display, io::Read, str::FromStr};
 pub struct Png {
     pub header: [u8; 8],
     chunks: Vec<Chunk>,
+    trailer: Bytes,
 }
 impl TryFrom<&[u8]> for Png {
     type Error = anyhow::Error;
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        let mut buf = BufReader::new(value);
-        let mut header: [u8; 8] = [0; 8];
-        buf.read_exact(&mut header)?;
-        if header != Png::STANDARD_HEADER {
-            return Err(anyhow::anyhow!("The header of the PNG is not equal to the standard header"));
-        }
-        
-        let mut chunks = Vec::new();
-        loop {
-            let mut chunk_length: [u8; 4] = [0; 4];
-            let ss = buf.read_exact(&mut chunk_length);
-            if ss.is_err() {
-                break;
-            } 
-            let chunk_length: u32 = u32::from_be_bytes(chunk_length);
-            let mut chunk_type: [u8; 4] = [0; 4];
-            buf.read_exact(&mut chunk_type)?;
-            let mut chunk_data: Vec<u8> = vec![0; chunk_length as usize];
-            buf.read_exact(&mut chunk_data)?;
-            let mut chunk_crc: [u8; 4] = [0; 4];
-            buf.read_exact(&mut chunk_crc)?;
-            let chunk_crc: u32 = u32::from_be_bytes(chunk_crc);
-            let chunk_type = ChunkType::try_from(chunk_type)?;
-            let chunk_data = chunk_data.as_slice();
-            let chunk = Chunk {
-                length: chunk_length,
-                chunk_type,
-                data: chunk_data.to_vec(),
-                crc: chunk_crc,
-            };
-            chunks.push(chunk);
-            // let types =  chunk_type.chunk_type();
-            // if types == "IEND" {
-            //     break;
-            // }
-        }
-        Ok(Png { header, chunks })
+        Png::from_bytes(Bytes::copy_from_slice(value))
     }
 }
 
@@ -58,33 +19,77 @@ impl Display for Png {
         writeln!(f, "Png {{",)?;
         writeln!(f, "  Header: {:?}", self.header.to_vec())?;
         writeln!(f, "  Chunks: {:?}", self.chunks.to_vec())?;
+        writeln!(f, "  Trailer: {} bytes", self.trailer.len())?;
         writeln!(f, "}}")
     }
 }
 
 impl Png {
-    const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+    pub const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
     // Load a png from a file
     pub fn from_path(path: &str) -> Result<Self, anyhow::Error> {
         let mut file = std::fs::File::open(path)?;
         let mut buffer = Vec::new();
         file.read_to_end(&mut buffer)?;
-        let png = Png::try_from(buffer.as_slice())?;
+        let png = Png::from_bytes(Bytes::from(buffer))?;
         Ok(png)
-    }    
+    }
+
+    // Parse a png from a shared buffer. Chunk data is sliced out of `value`
+    // rather than copied, so the chunks keep the buffer alive.
+    pub fn from_bytes(value: Bytes) -> Result<Self, anyhow::Error> {
+        let mapped = MappedPng::from_bytes(value)?;
+        let chunks = mapped
+            .headers()
+            .iter()
+            .map(|header| mapped.chunk(header))
+            .collect();
+        Ok(Png {
+            header: Png::STANDARD_HEADER,
+            chunks,
+            trailer: mapped.trailer(),
+        })
+    }
 
     pub fn from_chunks(chunks: Vec<Chunk>) -> Png {
         Png {
             header: Png::STANDARD_HEADER,
             chunks,
+            trailer: Bytes::new(),
         }
     }
     pub fn prepend_chunk(&mut self, chunk: Chunk) {
         self.chunks.insert(0, chunk);
     }
     pub fn append_chunk(&mut self, chunk: Chunk) {
-        // append chunk to chunks
-        self.chunks.push(chunk);
+        // append chunk to chunks, keeping IEND last
+        let iend = ChunkType::from_str("IEND").unwrap();
+        match self.chunks.iter().position(|x| x.chunk_type == iend) {
+            Some(index) => self.chunks.insert(index, chunk),
+            None => self.chunks.push(chunk),
+        }
+    }
+    // Insert a chunk so that it ends up at `index`. It can't go before IHDR
+    // or after IEND.
+    pub fn insert_chunk(&mut self, index: usize, chunk: Chunk) -> Result<()> {
+        let ihdr = ChunkType::from_str("IHDR")?;
+        let iend = ChunkType::from_str("IEND")?;
+        let first = match self.chunks.first() {
+            Some(first) if first.chunk_type == ihdr => 1,
+            _ => 0,
+        };
+        let last = self
+            .chunks
+            .iter()
+            .position(|x| x.chunk_type == iend)
+            .unwrap_or(self.chunks.len());
+        if index < first || index > last {
+            return Err(anyhow::anyhow!(
+                "A chunk can't be inserted before IHDR or after IEND"
+            ));
+        }
+        self.chunks.insert(index, chunk);
+        Ok(())
     }
     pub fn delete_chunk(&mut self, chunk_type: &str) -> Result<Chunk> {
         // remove chunk from chunks
@@ -105,6 +110,19 @@ impl Png {
     pub fn chunks(&self) -> &[Chunk] {
         &self.chunks
     }
+    pub fn set_chunks(&mut self, chunks: Vec<Chunk>) {
+        self.chunks = chunks;
+    }
+    // The bytes following IEND, kept verbatim so they survive a rewrite.
+    pub fn trailer(&self) -> &Bytes {
+        &self.trailer
+    }
+    pub fn set_trailer(&mut self, trailer: Bytes) {
+        self.trailer = trailer;
+    }
+    pub fn strip_trailer(&mut self) -> Bytes {
+        std::mem::take(&mut self.trailer)
+    }
 
     pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
         let type_u8 = ChunkType::from_str(chunk_type).unwrap();
@@ -112,15 +130,71 @@ impl Png {
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
+        let size = self
+            .chunks
+            .iter()
+            .map(|chunk| chunk.data.len() + 12)
+            .sum::<usize>();
+        let mut bytes = Vec::with_capacity(self.header.len() + size + self.trailer.len());
         bytes.extend_from_slice(&self.header);
         for chunk in &self.chunks {
-            bytes.extend_from_slice(&chunk.as_bytes());
+            chunk.write_to(&mut bytes);
         }
+        bytes.extend_from_slice(&self.trailer);
         bytes
     }
 }
 
+// A png is serialized as its signature, its chunks and whatever follows IEND,
+// which is enough to write the same bytes back out.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize)]
+struct PngRef<'a> {
+    #[serde(with = "crate::serde_base64")]
+    signature: &'a [u8; 8],
+    chunks: &'a [Chunk],
+    #[serde(with = "crate::serde_base64")]
+    trailer: &'a Bytes,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct PngRepr {
+    #[serde(default = "standard_header", with = "crate::serde_base64")]
+    signature: [u8; 8],
+    chunks: Vec<Chunk>,
+    #[serde(default, with = "crate::serde_base64")]
+    trailer: Bytes,
+}
+
+#[cfg(feature = "serde")]
+fn standard_header() -> [u8; 8] {
+    Png::STANDARD_HEADER
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Png {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let repr = PngRef {
+            signature: &self.header,
+            chunks: &self.chunks,
+            trailer: &self.trailer,
+        };
+        serde::Serialize::serialize(&repr, serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Png {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        PngRepr::deserialize(deserializer).map(|repr| Png {
+            header: repr.signature,
+            chunks: repr.chunks,
+            trailer: repr.trailer,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -260,6 +334,106 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_from_bytes_slices_buffer() {
+        let buffer = Bytes::from_static(&PNG_FILE);
+        let png = Png::from_bytes(buffer.clone()).unwrap();
+        let range = buffer.as_ptr_range();
+        for chunk in png.chunks() {
+            assert!(range.contains(&chunk.data().as_ptr()) || chunk.data().is_empty());
+        }
+        assert_eq!(png.as_bytes(), PNG_FILE.to_vec());
+    }
+
+    #[test]
+    fn test_truncated_chunk() {
+        let bytes = Png::try_from(&PNG_FILE[..]).unwrap().as_bytes();
+        let png = Png::try_from(&bytes[..bytes.len() - 6]);
+        assert!(png.is_err());
+    }
+
+    fn png_with_iend() -> Png {
+        let mut chunks = testing_chunks();
+        chunks.push(chunk_from_strings("IEND", "").unwrap());
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_trailer_round_trip() {
+        let mut bytes = png_with_iend().as_bytes();
+        bytes.extend_from_slice(b"PK\x03\x04 appended data");
+        let png = Png::try_from(bytes.as_ref()).unwrap();
+        assert_eq!(png.chunks().len(), 4);
+        assert_eq!(&png.trailer()[..], b"PK\x03\x04 appended data");
+        assert_eq!(png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_short_trailer_round_trip() {
+        let mut bytes = png_with_iend().as_bytes();
+        bytes.extend_from_slice(&[0, 0]);
+        let png = Png::try_from(bytes.as_ref()).unwrap();
+        assert_eq!(&png.trailer()[..], &[0, 0]);
+        assert_eq!(png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_strip_trailer() {
+        let mut bytes = png_with_iend().as_bytes();
+        bytes.extend_from_slice(b"appended data");
+        let mut png = Png::try_from(bytes.as_ref()).unwrap();
+        assert_eq!(&png.strip_trailer()[..], b"appended data");
+        assert!(png.trailer().is_empty());
+        assert_eq!(png.as_bytes(), png_with_iend().as_bytes());
+    }
+
+    #[test]
+    fn test_chunk_after_iend_is_kept() {
+        let mut bytes = png_with_iend().as_bytes();
+        bytes.extend(
+            chunk_from_strings("ruSt", "Old message")
+                .unwrap()
+                .as_bytes(),
+        );
+        let png = Png::try_from(bytes.as_ref()).unwrap();
+        assert!(png.trailer().is_empty());
+        assert_eq!(
+            &png.chunk_by_type("ruSt").unwrap().data_as_string().unwrap(),
+            "Old message"
+        );
+    }
+
+    #[test]
+    fn test_append_chunk_before_iend() {
+        let mut png = png_with_iend();
+        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|x| x.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["FrSt", "miDl", "LASt", "TeSt", "IEND"]);
+    }
+
+    #[test]
+    fn test_insert_chunk() {
+        let mut png = png_with_iend();
+        png.insert_chunk(1, chunk_from_strings("TeSt", "Message").unwrap())
+            .unwrap();
+        png.insert_chunk(4, chunk_from_strings("LaTe", "Message").unwrap())
+            .unwrap();
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|x| x.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["FrSt", "TeSt", "miDl", "LASt", "LaTe", "IEND"]);
+        // After IEND
+        assert!(png
+            .insert_chunk(6, chunk_from_strings("LaTe", "Message").unwrap())
+            .is_err());
+    }
+
     #[test]
     fn test_png_trait_impls() {
         let chunk_bytes: Vec<u8> = testing_chunks()
@@ -278,6 +452,22 @@ mod tests {
         let _png_string = format!("{}", png);
     }
 
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_png_serde_round_trip() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.set_trailer(Bytes::from_static(b"appended data"));
+        let json = serde_json::to_string(&png).unwrap();
+        let back: Png = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.as_bytes(), png.as_bytes());
+
+        // The signature and trailer can be left out.
+        let json = r#"{"chunks":[{"type":"IEND","data":""}]}"#;
+        let png: Png = serde_json::from_str(json).unwrap();
+        let iend = Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new());
+        assert_eq!(png.as_bytes(), Png::from_chunks(vec![iend]).as_bytes());
+    }
+
     // This is the raw bytes for a shrunken version of the `dice.png` image on Wikipedia
     const PNG_FILE: [u8; 4803] = [
         137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 50, 0, 0, 0, 50, 8,
@@ -0,0 +1,311 @@
+use crate::png::Png;
+use crate::trailer;
+use anyhow::Result;
+use bytes::Bytes;
+use flate2::read::DeflateDecoder;
+use std::io::Read;
+
+// A PNG/ZIP polyglot is a png with a zip archive stored after IEND. Zip
+// readers locate the archive from the end of the file, but the offsets in the
+// central directory are absolute, so they are shifted by the position the
+// archive ends up at.
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06054b50;
+const END_OF_CENTRAL_DIRECTORY_LEN: usize = 22;
+const MAX_COMMENT_LEN: usize = 0xffff;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZipEntry {
+    pub name: String,
+    pub method: u16,
+    pub crc: u32,
+    pub compressed_size: u32,
+    pub uncompressed_size: u32,
+    pub local_header_offset: u32,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16> {
+    let bytes = data
+        .get(offset..offset + 2)
+        .ok_or_else(|| anyhow::anyhow!("The archive is truncated at offset {}", offset))?;
+    Ok(u16::from_le_bytes(bytes.try_into()?))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    let bytes = data
+        .get(offset..offset + 4)
+        .ok_or_else(|| anyhow::anyhow!("The archive is truncated at offset {}", offset))?;
+    Ok(u32::from_le_bytes(bytes.try_into()?))
+}
+
+fn shift(value: u32, by: u32) -> Result<u32> {
+    if value == u32::MAX {
+        return Err(anyhow::anyhow!("Zip64 archives are not supported"));
+    }
+    value
+        .checked_add(by)
+        .ok_or_else(|| anyhow::anyhow!("The archive does not fit in a 32 bit zip"))
+}
+
+// Offset of the end of central directory record, searching backwards over the
+// largest comment it could be followed by.
+fn find_end_of_central_directory(data: &[u8]) -> Result<usize> {
+    if data.len() < END_OF_CENTRAL_DIRECTORY_LEN {
+        return Err(anyhow::anyhow!("No zip archive found"));
+    }
+    let last = data.len() - END_OF_CENTRAL_DIRECTORY_LEN;
+    let first = last.saturating_sub(MAX_COMMENT_LEN);
+    (first..=last)
+        .rev()
+        .find(|&offset| read_u32(data, offset).ok() == Some(END_OF_CENTRAL_DIRECTORY_SIGNATURE))
+        .ok_or_else(|| anyhow::anyhow!("No zip archive found"))
+}
+
+// Find the end record and the offset of every central directory entry.
+fn central_directory(data: &[u8]) -> Result<(usize, Vec<usize>)> {
+    let eocd = find_end_of_central_directory(data)?;
+    let count = read_u16(data, eocd + 10)? as usize;
+    let mut offset = read_u32(data, eocd + 16)? as usize;
+    let mut entries = Vec::with_capacity(count);
+    for _ in 0..count {
+        if read_u32(data, offset)? != CENTRAL_HEADER_SIGNATURE {
+            return Err(anyhow::anyhow!(
+                "Bad central directory entry at offset {}",
+                offset
+            ));
+        }
+        entries.push(offset);
+        let name_len = read_u16(data, offset + 28)? as usize;
+        let extra_len = read_u16(data, offset + 30)? as usize;
+        let comment_len = read_u16(data, offset + 32)? as usize;
+        offset += 46 + name_len + extra_len + comment_len;
+    }
+    Ok((eocd, entries))
+}
+
+// Rewrite a zip archive so that its offsets are correct once it is placed
+// `base` bytes into a file.
+pub fn relocate(zip: &[u8], base: u32) -> Result<Vec<u8>> {
+    let mut zip = zip.to_vec();
+    let (eocd, entries) = central_directory(&zip)?;
+    for entry in entries {
+        let offset = shift(read_u32(&zip, entry + 42)?, base)?;
+        zip[entry + 42..entry + 46].copy_from_slice(&offset.to_le_bytes());
+    }
+    let offset = shift(read_u32(&zip, eocd + 16)?, base)?;
+    zip[eocd + 16..eocd + 20].copy_from_slice(&offset.to_le_bytes());
+    Ok(zip)
+}
+
+// Append `zip` after IEND (and after anything already stored there).
+pub fn append_zip(png: &mut Png, zip: &[u8]) -> Result<()> {
+    trailer::check_iend(png)?;
+    let base = u32::try_from(png.as_bytes().len())?;
+    let mut trailer = png.trailer().to_vec();
+    trailer.extend(relocate(zip, base)?);
+    png.set_trailer(Bytes::from(trailer));
+    Ok(())
+}
+
+// List the archive stored in a polyglot file.
+pub fn entries(data: &[u8]) -> Result<Vec<ZipEntry>> {
+    let (_, offsets) = central_directory(data)?;
+    offsets
+        .into_iter()
+        .map(|offset| {
+            let name_len = read_u16(data, offset + 28)? as usize;
+            let name = data
+                .get(offset + 46..offset + 46 + name_len)
+                .ok_or_else(|| anyhow::anyhow!("The archive is truncated at offset {}", offset))?;
+            Ok(ZipEntry {
+                name: String::from_utf8_lossy(name).into_owned(),
+                method: read_u16(data, offset + 10)?,
+                crc: read_u32(data, offset + 16)?,
+                compressed_size: read_u32(data, offset + 20)?,
+                uncompressed_size: read_u32(data, offset + 24)?,
+                local_header_offset: read_u32(data, offset + 42)?,
+            })
+        })
+        .collect()
+}
+
+// Read the contents of a stored or deflated entry and check its CRC.
+pub fn extract(data: &[u8], entry: &ZipEntry) -> Result<Vec<u8>> {
+    let offset = entry.local_header_offset as usize;
+    if read_u32(data, offset)? != LOCAL_HEADER_SIGNATURE {
+        return Err(anyhow::anyhow!("Bad local header for {}", entry.name));
+    }
+    let name_len = read_u16(data, offset + 26)? as usize;
+    let extra_len = read_u16(data, offset + 28)? as usize;
+    let start = offset + 30 + name_len + extra_len;
+    let compressed = data
+        .get(start..start + entry.compressed_size as usize)
+        .ok_or_else(|| anyhow::anyhow!("The data of {} is truncated", entry.name))?;
+    // The declared size only bounds the output, it isn't trusted for allocation.
+    let limit = entry.uncompressed_size as u64 + 1;
+    let mut contents = Vec::new();
+    match entry.method {
+        0 => compressed.take(limit).read_to_end(&mut contents)?,
+        8 => DeflateDecoder::new(compressed)
+            .take(limit)
+            .read_to_end(&mut contents)?,
+        method => {
+            return Err(anyhow::anyhow!(
+                "Unsupported compression method {} for {}",
+                method,
+                entry.name
+            ))
+        }
+    };
+    if contents.len() as u64 > entry.uncompressed_size as u64 {
+        return Err(anyhow::anyhow!(
+            "{} holds more than its declared {} bytes",
+            entry.name,
+            entry.uncompressed_size
+        ));
+    }
+    if crc32fast::hash(&contents) != entry.crc {
+        return Err(anyhow::anyhow!("The CRC of {} does not match", entry.name));
+    }
+    Ok(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use flate2::{write::DeflateEncoder, Compression};
+    use std::io::Write;
+    use std::str::FromStr;
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            Chunk::new(
+                ChunkType::from_str("FrSt").unwrap(),
+                b"I am the first chunk".to_vec(),
+            ),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ])
+    }
+
+    // Build a zip archive the way a zip tool lays it out: local headers and
+    // data, then the central directory and its end record.
+    fn testing_zip(files: &[(&str, &[u8], bool)]) -> Vec<u8> {
+        let mut zip = Vec::new();
+        let mut central = Vec::new();
+        for (name, contents, deflate) in files {
+            let data = if *deflate {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(contents).unwrap();
+                encoder.finish().unwrap()
+            } else {
+                contents.to_vec()
+            };
+            let method: u16 = if *deflate { 8 } else { 0 };
+            let crc = crc32fast::hash(contents);
+            let offset = zip.len() as u32;
+            zip.extend(LOCAL_HEADER_SIGNATURE.to_le_bytes());
+            zip.extend([20, 0, 0, 0]);
+            zip.extend(method.to_le_bytes());
+            zip.extend([0; 4]);
+            zip.extend(crc.to_le_bytes());
+            zip.extend((data.len() as u32).to_le_bytes());
+            zip.extend((contents.len() as u32).to_le_bytes());
+            zip.extend((name.len() as u16).to_le_bytes());
+            zip.extend([0, 0]);
+            zip.extend(name.as_bytes());
+            zip.extend(&data);
+
+            central.extend(CENTRAL_HEADER_SIGNATURE.to_le_bytes());
+            central.extend([20, 0, 20, 0, 0, 0]);
+            central.extend(method.to_le_bytes());
+            central.extend([0; 4]);
+            central.extend(crc.to_le_bytes());
+            central.extend((data.len() as u32).to_le_bytes());
+            central.extend((contents.len() as u32).to_le_bytes());
+            central.extend((name.len() as u16).to_le_bytes());
+            central.extend([0; 12]);
+            central.extend(offset.to_le_bytes());
+            central.extend(name.as_bytes());
+        }
+        let central_offset = zip.len() as u32;
+        zip.extend(&central);
+        zip.extend(END_OF_CENTRAL_DIRECTORY_SIGNATURE.to_le_bytes());
+        zip.extend([0; 4]);
+        zip.extend((files.len() as u16).to_le_bytes());
+        zip.extend((files.len() as u16).to_le_bytes());
+        zip.extend((central.len() as u32).to_le_bytes());
+        zip.extend(central_offset.to_le_bytes());
+        zip.extend([0, 0]);
+        zip
+    }
+
+    #[test]
+    fn test_list_and_extract() {
+        let zip = testing_zip(&[
+            ("a.txt", b"stored file", false),
+            ("b.txt", b"deflated file", true),
+        ]);
+        let mut png = testing_png();
+        append_zip(&mut png, &zip).unwrap();
+        let bytes = png.as_bytes();
+
+        let entries = entries(&bytes).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].name, "a.txt");
+        assert_eq!(
+            entries[0].local_header_offset as usize,
+            testing_png().as_bytes().len()
+        );
+        assert_eq!(extract(&bytes, &entries[0]).unwrap(), b"stored file");
+        assert_eq!(extract(&bytes, &entries[1]).unwrap(), b"deflated file");
+    }
+
+    #[test]
+    fn test_declared_size_is_enforced() {
+        let zip = testing_zip(&[
+            ("a.txt", b"stored file", false),
+            ("b.txt", b"deflated file", true),
+        ]);
+        let mut entries = entries(&zip).unwrap();
+        for entry in &mut entries {
+            entry.uncompressed_size = 4;
+            assert!(extract(&zip, entry).is_err());
+        }
+    }
+
+    #[test]
+    fn test_polyglot_is_still_a_png() {
+        let zip = testing_zip(&[("a.txt", b"stored file", false)]);
+        let mut png = testing_png();
+        append_zip(&mut png, &zip).unwrap();
+        let parsed = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        assert_eq!(parsed.chunks().len(), 2);
+        assert_eq!(parsed.trailer().len(), zip.len());
+    }
+
+    #[test]
+    fn test_existing_trailer_is_kept() {
+        let zip = testing_zip(&[("a.txt", b"stored file", false)]);
+        let mut png = testing_png();
+        png.set_trailer(Bytes::from_static(b"appended data"));
+        append_zip(&mut png, &zip).unwrap();
+        let bytes = png.as_bytes();
+        let entries = entries(&bytes).unwrap();
+        assert_eq!(extract(&bytes, &entries[0]).unwrap(), b"stored file");
+    }
+
+    #[test]
+    fn test_no_iend() {
+        let zip = testing_zip(&[("a.txt", b"stored file", false)]);
+        let mut png = Png::from_chunks(testing_png().chunks()[..1].to_vec());
+        assert!(append_zip(&mut png, &zip).is_err());
+    }
+
+    #[test]
+    fn test_no_archive() {
+        assert!(entries(&testing_png().as_bytes()).is_err());
+    }
+}
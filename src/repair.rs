@@ -0,0 +1,282 @@
+use crate::{
+    chunk::{chunk_crc, Chunk},
+    chunk_type::ChunkType,
+    png::Png,
+};
+use bytes::Bytes;
+use std::{fmt, str::FromStr};
+
+// Best-effort recovery of damaged files. Chunks are walked like the normal
+// parser does, except that damage is worked around instead of reported:
+//
+// - garbage between chunks is skipped up to the next header whose CRC checks
+//   out, so one bad stretch doesn't lose the rest of the file;
+// - a chunk whose CRC is wrong but whose length leads to another chunk
+//   header (or to the end of the file) is taken to be intact, and its CRC
+//   is recomputed;
+// - a chunk whose length leads nowhere, or past the end of the file, is
+//   dropped;
+// - a missing IEND is appended.
+//
+// Whatever follows IEND is kept as the trailer, as it is by the parser.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fix {
+    Signature,
+    Skipped {
+        offset: usize,
+        length: usize,
+    },
+    Crc {
+        offset: usize,
+        chunk_type: ChunkType,
+        stored: u32,
+        computed: u32,
+    },
+    Dropped {
+        offset: usize,
+        chunk_type: ChunkType,
+        reason: &'static str,
+    },
+    Iend,
+}
+
+impl fmt::Display for Fix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fix::Signature => write!(f, "replaced the damaged signature"),
+            Fix::Skipped { offset, length } => {
+                write!(
+                    f,
+                    "skipped {} bytes of garbage at offset {}",
+                    length, offset
+                )
+            }
+            Fix::Crc {
+                offset,
+                chunk_type,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "fixed the crc of {} at offset {} from {:08x} to {:08x}",
+                chunk_type, offset, stored, computed
+            ),
+            Fix::Dropped {
+                offset,
+                chunk_type,
+                reason,
+            } => write!(f, "dropped {} at offset {}: {}", chunk_type, offset, reason),
+            Fix::Iend => write!(f, "appended a missing IEND"),
+        }
+    }
+}
+
+// The type and length of a chunk header at `offset`, if it looks like one.
+fn header_at(buffer: &[u8], offset: usize) -> Option<(ChunkType, usize)> {
+    let header = buffer.get(offset..offset + 8)?;
+    let length = u32::from_be_bytes(header[..4].try_into().ok()?) as usize;
+    let chunk_type: [u8; 4] = header[4..].try_into().ok()?;
+    let chunk_type = ChunkType::try_from(chunk_type).ok()?;
+    if !chunk_type.is_alpha() || length > i32::MAX as usize {
+        return None;
+    }
+    Some((chunk_type, length))
+}
+
+// Whether a complete chunk with a matching CRC starts at `offset`.
+fn valid_chunk_at(buffer: &[u8], offset: usize) -> bool {
+    let Some((chunk_type, length)) = header_at(buffer, offset) else {
+        return false;
+    };
+    let end = offset + 12 + length;
+    if end > buffer.len() {
+        return false;
+    }
+    let crc = u32::from_be_bytes(buffer[end - 4..end].try_into().unwrap());
+    crc == chunk_crc(&chunk_type, &buffer[offset + 8..end - 4])
+}
+
+pub fn repair(buffer: &[u8]) -> (Png, Vec<Fix>) {
+    let iend = ChunkType::from_str("IEND").unwrap();
+    let mut fixes = Vec::new();
+    let mut chunks = Vec::new();
+    let mut offset = 8;
+    if !buffer.starts_with(&Png::STANDARD_HEADER) {
+        fixes.push(Fix::Signature);
+        // Without IHDR where it belongs the signature may be missing
+        // altogether, so look for the first chunk from the start.
+        if header_at(buffer, 8).is_none_or(|(chunk_type, _)| chunk_type.bytes() != *b"IHDR") {
+            offset = 0;
+        }
+    }
+    let mut trailer_offset = None;
+    while offset < buffer.len() {
+        let header = header_at(buffer, offset);
+        let valid = valid_chunk_at(buffer, offset);
+        match header {
+            Some((chunk_type, length)) if valid || offset + 12 + length <= buffer.len() => {
+                let end = offset + 12 + length;
+                let data = &buffer[offset + 8..end - 4];
+                let stored = u32::from_be_bytes(buffer[end - 4..end].try_into().unwrap());
+                let computed = chunk_crc(&chunk_type, data);
+                let intact = valid
+                    || chunk_type == iend
+                    || end == buffer.len()
+                    || header_at(buffer, end)
+                        .is_some_and(|(_, next_length)| end + 12 + next_length <= buffer.len());
+                if intact {
+                    if stored != computed {
+                        fixes.push(Fix::Crc {
+                            offset,
+                            chunk_type,
+                            stored,
+                            computed,
+                        });
+                    }
+                    chunks.push(Chunk::new(chunk_type, data.to_vec()));
+                    offset = end;
+                    if chunk_type == iend {
+                        trailer_offset = Some(end);
+                        break;
+                    }
+                    continue;
+                }
+                fixes.push(Fix::Dropped {
+                    offset,
+                    chunk_type,
+                    reason: "its length doesn't lead to another chunk",
+                });
+            }
+            Some((chunk_type, _)) => {
+                fixes.push(Fix::Dropped {
+                    offset,
+                    chunk_type,
+                    reason: "it runs past the end of the file",
+                });
+            }
+            None => {}
+        }
+        // Resynchronise on the next complete chunk.
+        let next = (offset + 1..buffer.len())
+            .find(|&next| valid_chunk_at(buffer, next))
+            .unwrap_or(buffer.len());
+        if header.is_none() {
+            fixes.push(Fix::Skipped {
+                offset,
+                length: next - offset,
+            });
+        }
+        offset = next;
+    }
+    if trailer_offset.is_none() {
+        chunks.push(Chunk::new(iend, Vec::new()));
+        fixes.push(Fix::Iend);
+    }
+    let mut png = Png::from_chunks(chunks);
+    if let Some(trailer_offset) = trailer_offset {
+        png.set_trailer(Bytes::copy_from_slice(&buffer[trailer_offset..]));
+    }
+    (png, fixes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            chunk("IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+            chunk("tEXt", b"Comment\0hello"),
+            chunk("IDAT", &[1, 2, 3, 4, 5, 6, 7, 8]),
+            chunk("ruSt", b"payload"),
+            chunk("IEND", &[]),
+        ])
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect()
+    }
+
+    // Offset of the chunk at `index` in the testing png.
+    fn offset(index: usize) -> usize {
+        8 + testing_png().chunks()[..index]
+            .iter()
+            .map(|chunk| chunk.as_bytes().len())
+            .sum::<usize>()
+    }
+
+    #[test]
+    fn test_intact_file() {
+        let bytes = testing_png().as_bytes();
+        let (png, fixes) = repair(&bytes);
+        assert!(fixes.is_empty());
+        assert_eq!(png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_crc_fixed() {
+        let mut bytes = testing_png().as_bytes();
+        // Flip a byte of the tEXt data.
+        bytes[offset(1) + 10] ^= 0x20;
+        let (png, fixes) = repair(&bytes);
+        assert!(matches!(fixes[..], [Fix::Crc { offset: 33, .. }]));
+        assert_eq!(types(&png), types(&testing_png()));
+        assert!(Png::try_from(&png.as_bytes()[..]).is_ok());
+    }
+
+    #[test]
+    fn test_garbage_skipped() {
+        let mut bytes = testing_png().as_bytes();
+        let at = offset(2);
+        bytes.splice(at..at, [0xde, 0xad, 0xbe, 0xef, 0x00, 0x01, 0x02]);
+        let (png, fixes) = repair(&bytes);
+        assert_eq!(
+            fixes,
+            vec![Fix::Skipped {
+                offset: at,
+                length: 7
+            }]
+        );
+        assert_eq!(types(&png), types(&testing_png()));
+    }
+
+    #[test]
+    fn test_bad_length_dropped() {
+        let mut bytes = testing_png().as_bytes();
+        // Claim the IDAT is two bytes longer than it is.
+        bytes[offset(2) + 3] += 2;
+        let (png, fixes) = repair(&bytes);
+        assert!(matches!(fixes[..], [Fix::Dropped { .. }]));
+        assert_eq!(types(&png), vec!["IHDR", "tEXt", "ruSt", "IEND"]);
+        assert_eq!(png.chunk_by_type("ruSt").unwrap().data(), b"payload");
+    }
+
+    #[test]
+    fn test_truncated_file() {
+        let bytes = testing_png().as_bytes();
+        let (png, fixes) = repair(&bytes[..offset(3) + 10]);
+        assert_eq!(fixes.len(), 2);
+        assert!(matches!(fixes[0], Fix::Dropped { .. }));
+        assert_eq!(fixes[1], Fix::Iend);
+        assert_eq!(types(&png), vec!["IHDR", "tEXt", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_signature_and_trailer() {
+        let mut bytes = testing_png().as_bytes();
+        bytes[1] = b'X';
+        bytes.extend_from_slice(b"after");
+        let (png, fixes) = repair(&bytes);
+        assert_eq!(fixes, vec![Fix::Signature]);
+        assert_eq!(png.header, Png::STANDARD_HEADER);
+        assert_eq!(&png.trailer()[..], b"after");
+    }
+}
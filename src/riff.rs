@@ -0,0 +1,305 @@
+use anyhow::Result;
+use bytes::Bytes;
+use std::fmt;
+use std::io::Read;
+
+// A RIFF file, such as WebP or WAV, is "RIFF", the size of everything after
+// the size field as a little-endian u32, a four letter form type and then the
+// chunks. Each chunk is a FourCC, the size of its data as a little-endian u32
+// and the data, followed by a pad byte when the size is odd.
+pub const MAGIC: [u8; 4] = *b"RIFF";
+pub const WEBP: [u8; 4] = *b"WEBP";
+pub const WAVE: [u8; 4] = *b"WAVE";
+
+// Chunks defined by WebP, WAV or RIFF itself, which must not be used to hold
+// a message.
+pub const KNOWN_FOURCCS: [&[u8; 4]; 19] = [
+    b"VP8 ", b"VP8L", b"VP8X", b"ALPH", b"ANIM", b"ANMF", b"ICCP", b"EXIF", b"XMP ", b"fmt ",
+    b"data", b"fact", b"cue ", b"plst", b"smpl", b"inst", b"bext", b"LIST", b"JUNK",
+];
+
+pub fn is_known(fourcc: &[u8; 4]) -> bool {
+    KNOWN_FOURCCS.contains(&fourcc)
+}
+
+pub fn parse_fourcc(name: &str) -> Result<[u8; 4]> {
+    let fourcc: [u8; 4] = name
+        .as_bytes()
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("A FourCC is four characters, not {:?}", name))?;
+    if !fourcc.iter().all(|byte| (0x20..0x7f).contains(byte)) {
+        return Err(anyhow::anyhow!(
+            "A FourCC is made of printable ASCII, not {:?}",
+            name
+        ));
+    }
+    Ok(fourcc)
+}
+
+pub fn fourcc_name(fourcc: &[u8; 4]) -> String {
+    fourcc
+        .iter()
+        .map(|&byte| {
+            if (0x20..0x7f).contains(&byte) {
+                byte as char
+            } else {
+                '?'
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct RiffChunk {
+    pub fourcc: [u8; 4],
+    pub data: Bytes,
+}
+
+impl RiffChunk {
+    pub fn new(fourcc: [u8; 4], data: Bytes) -> Result<RiffChunk> {
+        if data.len() > u32::MAX as usize - 1 {
+            return Err(anyhow::anyhow!("A RIFF chunk holds at most 4 GiB"));
+        }
+        Ok(RiffChunk { fourcc, data })
+    }
+
+    // Size of the serialized chunk, pad byte included.
+    pub fn serialized_len(&self) -> usize {
+        8 + self.data.len() + self.data.len() % 2
+    }
+
+    // Append the serialized chunk to `buf`.
+    pub fn write_to(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.fourcc);
+        buf.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&self.data);
+        if self.data.len() % 2 == 1 {
+            buf.push(0);
+        }
+    }
+}
+
+impl fmt::Display for RiffChunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} bytes",
+            fourcc_name(&self.fourcc),
+            self.data.len()
+        )
+    }
+}
+
+// A RIFF file as a list of top-level chunks. The size field is recomputed on
+// every write, and whatever follows the size it announces is kept as a
+// trailer. A missing pad byte after the last chunk is tolerated and written
+// out on the next save.
+pub struct Riff {
+    pub form_type: [u8; 4],
+    chunks: Vec<RiffChunk>,
+    trailer: Bytes,
+}
+
+impl Riff {
+    pub fn from_path(path: &str) -> Result<Riff> {
+        let mut file = std::fs::File::open(path)?;
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)?;
+        Riff::from_bytes(Bytes::from(buffer))
+    }
+
+    // Chunk data is sliced out of `value` rather than copied.
+    pub fn from_bytes(value: Bytes) -> Result<Riff> {
+        if value.len() < 12 || value[..4] != MAGIC {
+            return Err(anyhow::anyhow!(
+                "The file does not start with a RIFF header"
+            ));
+        }
+        let size = u32::from_le_bytes(value[4..8].try_into()?) as usize;
+        if size < 4 {
+            return Err(anyhow::anyhow!(
+                "The RIFF size field is too small: {}",
+                size
+            ));
+        }
+        let end = 8 + size;
+        if end > value.len() {
+            return Err(anyhow::anyhow!(
+                "The RIFF size field says {} bytes but the file has {}",
+                size,
+                value.len() - 8
+            ));
+        }
+        let form_type = value[8..12].try_into()?;
+        let mut chunks = Vec::new();
+        let mut offset = 12;
+        while offset < end {
+            if end - offset < 8 {
+                return Err(anyhow::anyhow!(
+                    "The chunk at offset {} is truncated",
+                    offset
+                ));
+            }
+            let fourcc: [u8; 4] = value[offset..offset + 4].try_into()?;
+            let length = u32::from_le_bytes(value[offset + 4..offset + 8].try_into()?) as usize;
+            let data_end = offset + 8 + length;
+            if data_end > end {
+                return Err(anyhow::anyhow!(
+                    "The {} chunk at offset {} is truncated",
+                    fourcc_name(&fourcc),
+                    offset
+                ));
+            }
+            chunks.push(RiffChunk::new(fourcc, value.slice(offset + 8..data_end))?);
+            offset = (data_end + length % 2).min(end);
+        }
+        Ok(Riff {
+            form_type,
+            chunks,
+            trailer: value.slice(end..),
+        })
+    }
+
+    pub fn chunks(&self) -> &[RiffChunk] {
+        &self.chunks
+    }
+
+    pub fn chunk_by_fourcc(&self, fourcc: &[u8; 4]) -> Option<&RiffChunk> {
+        self.chunks.iter().find(|chunk| &chunk.fourcc == fourcc)
+    }
+
+    pub fn append_chunk(&mut self, chunk: RiffChunk) {
+        self.chunks.push(chunk);
+    }
+
+    pub fn delete_chunk(&mut self, fourcc: &[u8; 4]) -> Result<RiffChunk> {
+        let index = self
+            .chunks
+            .iter()
+            .position(|chunk| &chunk.fourcc == fourcc)
+            .ok_or_else(|| anyhow::anyhow!("No {} chunk found", fourcc_name(fourcc)))?;
+        Ok(self.chunks.remove(index))
+    }
+
+    // The value of the size field: the form type and every chunk.
+    pub fn size(&self) -> usize {
+        4 + self
+            .chunks
+            .iter()
+            .map(|chunk| chunk.serialized_len())
+            .sum::<usize>()
+    }
+
+    // The bytes following the RIFF data, kept verbatim so they survive a
+    // rewrite.
+    pub fn trailer(&self) -> &Bytes {
+        &self.trailer
+    }
+
+    pub fn set_trailer(&mut self, trailer: Bytes) {
+        self.trailer = trailer;
+    }
+
+    pub fn as_bytes(&self) -> Result<Vec<u8>> {
+        let size = self.size();
+        let size_field = u32::try_from(size)
+            .map_err(|_| anyhow::anyhow!("The RIFF data is larger than 4 GiB"))?;
+        let mut bytes = Vec::with_capacity(8 + size + self.trailer.len());
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&size_field.to_le_bytes());
+        bytes.extend_from_slice(&self.form_type);
+        for chunk in &self.chunks {
+            chunk.write_to(&mut bytes);
+        }
+        bytes.extend_from_slice(&self.trailer);
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A WAVE file with a format chunk, an odd-sized data chunk and its pad
+    // byte, followed by a trailer.
+    fn testing_wav() -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&32u32.to_le_bytes());
+        bytes.extend_from_slice(&WAVE);
+        bytes.extend_from_slice(b"fmt ");
+        bytes.extend_from_slice(&8u32.to_le_bytes());
+        bytes.extend_from_slice(&[1, 0, 1, 0, 0x40, 0x1f, 0, 0]);
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&3u32.to_le_bytes());
+        bytes.extend_from_slice(&[1, 2, 3, 0]);
+        bytes.extend_from_slice(b"appended data");
+        bytes
+    }
+
+    #[test]
+    fn test_parse_chunks() {
+        let riff = Riff::from_bytes(Bytes::from(testing_wav())).unwrap();
+        assert_eq!(riff.form_type, WAVE);
+        let names: Vec<String> = riff
+            .chunks()
+            .iter()
+            .map(|x| fourcc_name(&x.fourcc))
+            .collect();
+        assert_eq!(names, ["fmt ", "data"]);
+        assert_eq!(&riff.chunk_by_fourcc(b"data").unwrap().data[..], [1, 2, 3]);
+        assert_eq!(riff.size(), 32);
+        assert_eq!(&riff.trailer()[..], b"appended data");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = testing_wav();
+        let riff = Riff::from_bytes(Bytes::from(bytes.clone())).unwrap();
+        assert_eq!(riff.as_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_append_and_delete_chunk() {
+        let mut riff = Riff::from_bytes(Bytes::from(testing_wav())).unwrap();
+        riff.append_chunk(RiffChunk::new(*b"ruSt", Bytes::from_static(b"odd")).unwrap());
+        let bytes = riff.as_bytes().unwrap();
+        assert_eq!(u32::from_le_bytes(bytes[4..8].try_into().unwrap()), 32 + 12);
+        let mut riff = Riff::from_bytes(Bytes::from(bytes)).unwrap();
+        assert_eq!(&riff.chunk_by_fourcc(b"ruSt").unwrap().data[..], b"odd");
+        assert_eq!(&riff.trailer()[..], b"appended data");
+        riff.delete_chunk(b"ruSt").unwrap();
+        assert_eq!(riff.as_bytes().unwrap(), testing_wav());
+        assert!(riff.delete_chunk(b"ruSt").is_err());
+    }
+
+    #[test]
+    fn test_missing_last_pad_byte() {
+        let mut bytes = testing_wav();
+        bytes.truncate(39);
+        bytes[4..8].copy_from_slice(&31u32.to_le_bytes());
+        let riff = Riff::from_bytes(Bytes::from(bytes)).unwrap();
+        assert_eq!(&riff.chunk_by_fourcc(b"data").unwrap().data[..], [1, 2, 3]);
+        assert_eq!(riff.as_bytes().unwrap(), testing_wav()[..40]);
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        assert!(Riff::from_bytes(Bytes::from_static(b"\x89PNG\r\n\x1a\n....")).is_err());
+        let mut too_long = testing_wav();
+        too_long[4..8].copy_from_slice(&1000u32.to_le_bytes());
+        assert!(Riff::from_bytes(Bytes::from(too_long)).is_err());
+        let mut truncated_chunk = testing_wav();
+        truncated_chunk[32..36].copy_from_slice(&100u32.to_le_bytes());
+        assert!(Riff::from_bytes(Bytes::from(truncated_chunk)).is_err());
+    }
+
+    #[test]
+    fn test_fourcc() {
+        assert_eq!(&parse_fourcc("ruSt").unwrap(), b"ruSt");
+        assert_eq!(&parse_fourcc("XMP ").unwrap(), b"XMP ");
+        assert!(parse_fourcc("toolong").is_err());
+        assert!(parse_fourcc("a\tbc").is_err());
+        assert!(is_known(b"VP8 "));
+        assert!(!is_known(b"ruSt"));
+    }
+}
@@ -0,0 +1,260 @@
+use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png, scan};
+use anyhow::Result;
+use std::str::FromStr;
+
+// Removal of identifying metadata before an image is published. A policy is
+// a list of chunk types plus two special entries: "private" for every private
+// chunk and "trailer" for whatever follows IEND, including chunks written
+// there by older versions of encode. Critical chunks are never removed.
+
+pub const PRIVATE: &str = "private";
+pub const TRAILER: &str = "trailer";
+
+// What a deny policy removes when no list is given.
+pub const DEFAULT_DENY: [&str; 8] = [
+    "tEXt", "zTXt", "iTXt", "eXIf", "tIME", "iCCP", PRIVATE, TRAILER,
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Policy {
+    // Keep critical chunks and the listed entries, remove everything else.
+    Allow(Vec<String>),
+    // Remove the listed entries, keep everything else.
+    Deny(Vec<String>),
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy::Deny(DEFAULT_DENY.iter().map(|entry| entry.to_string()).collect())
+    }
+}
+
+impl Policy {
+    // Check the entries up front so a typo doesn't silently keep metadata.
+    pub fn validate(&self) -> Result<()> {
+        let (Policy::Allow(entries) | Policy::Deny(entries)) = self;
+        for entry in entries {
+            if entry != PRIVATE && entry != TRAILER {
+                ChunkType::from_str(entry)
+                    .map_err(|_| anyhow::anyhow!("Invalid chunk type in policy: {}", entry))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn lists(&self, entry: &str) -> bool {
+        let (Policy::Allow(entries) | Policy::Deny(entries)) = self;
+        entries.iter().any(|listed| listed == entry)
+    }
+
+    fn removes(&self, entry: &str) -> bool {
+        match self {
+            Policy::Allow(_) => !self.lists(entry),
+            Policy::Deny(_) => self.lists(entry),
+        }
+    }
+
+    fn removes_chunk(&self, chunk_type: &ChunkType) -> bool {
+        if chunk_type.is_critical() {
+            return false;
+        }
+        let name = chunk_type.to_string();
+        match self {
+            // A private chunk stays if it is allowed by name or as a class.
+            Policy::Allow(_) if is_private(chunk_type) => {
+                !self.lists(&name) && !self.lists(PRIVATE)
+            }
+            Policy::Deny(_) if is_private(chunk_type) => self.lists(&name) || self.lists(PRIVATE),
+            _ => self.removes(&name),
+        }
+    }
+
+    fn removes_trailer(&self) -> bool {
+        self.removes(TRAILER)
+    }
+}
+
+// acTL, fcTL and fdAT have the private bit set but are registered, so they
+// don't belong to the private class and an APNG keeps its frames.
+fn is_private(chunk_type: &ChunkType) -> bool {
+    !chunk_type.is_public() && !scan::is_known(chunk_type)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Removed {
+    pub chunk_type: ChunkType,
+    pub count: usize,
+    // Data bytes, without the 12 bytes of framing per chunk.
+    pub bytes: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SanitizeReport {
+    // Removed chunks by type, in the order they first appeared.
+    pub chunks: Vec<Removed>,
+    // Bytes removed after IEND, chunks there included.
+    pub trailer: usize,
+}
+
+impl SanitizeReport {
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty() && self.trailer == 0
+    }
+
+    fn record(&mut self, chunk: &Chunk) {
+        let chunk_type = *chunk.chunk_type();
+        match self
+            .chunks
+            .iter_mut()
+            .find(|removed| removed.chunk_type == chunk_type)
+        {
+            Some(removed) => {
+                removed.count += 1;
+                removed.bytes += chunk.data().len();
+            }
+            None => self.chunks.push(Removed {
+                chunk_type,
+                count: 1,
+                bytes: chunk.data().len(),
+            }),
+        }
+    }
+}
+
+pub fn sanitize(png: &mut Png, policy: &Policy) -> Result<SanitizeReport> {
+    policy.validate()?;
+    let iend = ChunkType::from_str("IEND")?;
+    let mut report = SanitizeReport::default();
+    let mut kept = Vec::new();
+    let mut after_iend = Vec::new();
+    let mut seen_iend = false;
+    for chunk in png.chunks() {
+        if seen_iend {
+            after_iend.push(chunk.clone());
+        } else if policy.removes_chunk(chunk.chunk_type()) {
+            report.record(chunk);
+        } else {
+            kept.push(chunk.clone());
+        }
+        seen_iend |= *chunk.chunk_type() == iend;
+    }
+    if policy.removes_trailer() {
+        report.trailer = after_iend
+            .iter()
+            .map(|chunk| chunk.as_bytes().len())
+            .sum::<usize>()
+            + png.strip_trailer().len();
+    } else {
+        kept.extend(after_iend);
+    }
+    png.set_chunks(kept);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    fn testing_png() -> Png {
+        let mut png = Png::from_chunks(vec![
+            chunk("IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+            chunk("tEXt", b"Author\0me"),
+            chunk("tIME", &[7, 234, 1, 1, 0, 0, 0]),
+            chunk("gAMA", &[0, 0, 177, 143]),
+            chunk("ruSt", b"secret"),
+            chunk("IDAT", &[0; 10]),
+            chunk("tEXt", b"Comment\0hi"),
+            chunk("IEND", &[]),
+        ]);
+        png.set_trailer(Bytes::from_static(b"appended"));
+        png
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_default_policy() {
+        let mut png = testing_png();
+        let report = sanitize(&mut png, &Policy::default()).unwrap();
+        assert_eq!(types(&png), vec!["IHDR", "gAMA", "IDAT", "IEND"]);
+        assert_eq!(report.chunks.len(), 3);
+        assert_eq!(report.chunks[0].count, 2);
+        assert_eq!(report.chunks[0].bytes, 9 + 10);
+        assert_eq!(report.trailer, 8);
+        assert!(png.trailer().is_empty());
+    }
+
+    #[test]
+    fn test_allow_policy() {
+        let mut png = testing_png();
+        let policy = Policy::Allow(vec!["tIME".to_string(), TRAILER.to_string()]);
+        let report = sanitize(&mut png, &policy).unwrap();
+        assert_eq!(types(&png), vec!["IHDR", "tIME", "IDAT", "IEND"]);
+        assert_eq!(report.trailer, 0);
+        assert_eq!(&png.trailer()[..], b"appended");
+    }
+
+    #[test]
+    fn test_deny_policy() {
+        let mut png = testing_png();
+        let policy = Policy::Deny(vec!["gAMA".to_string(), "IDAT".to_string()]);
+        let report = sanitize(&mut png, &policy).unwrap();
+        // Critical chunks stay even when listed.
+        assert_eq!(
+            types(&png),
+            vec!["IHDR", "tEXt", "tIME", "ruSt", "IDAT", "tEXt", "IEND"]
+        );
+        assert_eq!(report.chunks.len(), 1);
+        assert_eq!(report.trailer, 0);
+    }
+
+    #[test]
+    fn test_chunks_after_iend() {
+        let mut png = testing_png();
+        let mut chunks = png.chunks().to_vec();
+        chunks.push(chunk("ruSt", b"legacy"));
+        png.set_chunks(chunks);
+        let report = sanitize(&mut png, &Policy::default()).unwrap();
+        assert_eq!(report.trailer, 12 + 6 + 8);
+        assert_eq!(types(&png).last().unwrap(), "IEND");
+    }
+
+    #[test]
+    fn test_animation_is_kept() {
+        let mut png = Png::from_chunks(vec![
+            chunk("IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+            chunk("acTL", &[0, 0, 0, 2, 0, 0, 0, 0]),
+            chunk("fcTL", &[0; 26]),
+            chunk("IDAT", &[0; 10]),
+            chunk("fcTL", &[0; 26]),
+            chunk("fdAT", &[0; 14]),
+            chunk("ruSt", b"secret"),
+            chunk("IEND", &[]),
+        ]);
+        let report = sanitize(&mut png, &Policy::default()).unwrap();
+        assert_eq!(
+            types(&png),
+            vec!["IHDR", "acTL", "fcTL", "IDAT", "fcTL", "fdAT", "IEND"]
+        );
+        assert_eq!(report.chunks.len(), 1);
+        let policy = Policy::Allow(vec!["acTL".into(), "fcTL".into(), "fdAT".into()]);
+        sanitize(&mut png, &policy).unwrap();
+        assert_eq!(png.chunks().len(), 7);
+    }
+
+    #[test]
+    fn test_invalid_policy() {
+        let policy = Policy::Deny(vec!["toolong".to_string()]);
+        assert!(sanitize(&mut testing_png(), &policy).is_err());
+    }
+}
@@ -0,0 +1,531 @@
+use crate::{chunk::chunk_crc, chunk_type::ChunkType, format::Format, mapped_png::MappedPng};
+use anyhow::Result;
+use bytes::Bytes;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+// Triage of PNG files for anything a plain encoder wouldn't write. Files are
+// read with the lenient header scan of `MappedPng` rather than `Png::try_from`
+// so that a bad CRC is a finding instead of a failure to read the file.
+
+// Every chunk type registered in the PNG specification and its extensions.
+pub const KNOWN_CHUNK_TYPES: [&str; 34] = [
+    "IHDR", "PLTE", "IDAT", "IEND", "tRNS", "cHRM", "gAMA", "iCCP", "sBIT", "sRGB", "cICP", "mDCV",
+    "cLLI", "tEXt", "zTXt", "iTXt", "bKGD", "hIST", "pHYs", "sPLT", "eXIf", "tIME", "acTL", "fcTL",
+    "fdAT", "oFFs", "pCAL", "sCAL", "gIFg", "gIFx", "gIFt", "sTER", "fRAc", "dSIG",
+];
+
+// Chunks that hold compressed or image data, where high entropy is expected.
+const COMPRESSED_CHUNK_TYPES: [&str; 5] = ["IDAT", "fdAT", "zTXt", "iTXt", "iCCP"];
+
+// Entropy isn't meaningful for a handful of bytes.
+const MIN_ENTROPY_LENGTH: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScanOptions {
+    // Ancillary chunks larger than this are flagged.
+    pub max_ancillary: usize,
+    // Bits per byte above which an uncompressed chunk is flagged.
+    pub max_entropy: f64,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            max_ancillary: 256 * 1024,
+            max_entropy: 7.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Finding {
+    PrivateChunk {
+        offset: usize,
+        chunk_type: ChunkType,
+    },
+    UnknownChunk {
+        offset: usize,
+        chunk_type: ChunkType,
+    },
+    DataAfterIend {
+        offset: usize,
+        length: usize,
+    },
+    OversizedChunk {
+        offset: usize,
+        chunk_type: ChunkType,
+        length: u32,
+    },
+    HighEntropy {
+        offset: usize,
+        chunk_type: ChunkType,
+        entropy: f64,
+    },
+    CrcMismatch {
+        offset: usize,
+        chunk_type: ChunkType,
+        stored: u32,
+        computed: u32,
+    },
+}
+
+impl Finding {
+    // A stable name for reports.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Finding::PrivateChunk { .. } => "private-chunk",
+            Finding::UnknownChunk { .. } => "unknown-chunk",
+            Finding::DataAfterIend { .. } => "data-after-iend",
+            Finding::OversizedChunk { .. } => "oversized-chunk",
+            Finding::HighEntropy { .. } => "high-entropy",
+            Finding::CrcMismatch { .. } => "crc-mismatch",
+        }
+    }
+
+    pub fn offset(&self) -> usize {
+        match *self {
+            Finding::PrivateChunk { offset, .. }
+            | Finding::UnknownChunk { offset, .. }
+            | Finding::DataAfterIend { offset, .. }
+            | Finding::OversizedChunk { offset, .. }
+            | Finding::HighEntropy { offset, .. }
+            | Finding::CrcMismatch { offset, .. } => offset,
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Finding::PrivateChunk { chunk_type, .. } => write!(f, "private chunk {}", chunk_type),
+            Finding::UnknownChunk { chunk_type, .. } => {
+                write!(f, "unregistered public chunk {}", chunk_type)
+            }
+            Finding::DataAfterIend { length, .. } => write!(f, "{} bytes after IEND", length),
+            Finding::OversizedChunk {
+                chunk_type, length, ..
+            } => write!(f, "{} chunk of {} bytes", chunk_type, length),
+            Finding::HighEntropy {
+                chunk_type,
+                entropy,
+                ..
+            } => write!(
+                f,
+                "{} chunk with {:.2} bits of entropy per byte",
+                chunk_type, entropy
+            ),
+            Finding::CrcMismatch {
+                chunk_type,
+                stored,
+                computed,
+                ..
+            } => write!(
+                f,
+                "{} chunk has crc {:08x}, expected {:08x}",
+                chunk_type, stored, computed
+            ),
+        }
+    }
+}
+
+// Shannon entropy in bits per byte.
+pub fn entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &byte in data {
+        counts[byte as usize] += 1;
+    }
+    let len = data.len() as f64;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+pub fn is_known(chunk_type: &ChunkType) -> bool {
+    KNOWN_CHUNK_TYPES
+        .iter()
+        .any(|known| known.as_bytes() == chunk_type.bytes())
+}
+
+fn is_compressed(chunk_type: &ChunkType) -> bool {
+    COMPRESSED_CHUNK_TYPES
+        .iter()
+        .any(|known| known.as_bytes() == chunk_type.bytes())
+}
+
+// Everything suspicious about a single file.
+pub fn scan(bytes: Bytes, options: &ScanOptions) -> Result<Vec<Finding>> {
+    scan_png(&MappedPng::from_bytes(bytes)?, options)
+}
+
+pub fn scan_png(img: &MappedPng, options: &ScanOptions) -> Result<Vec<Finding>> {
+    let mut findings = Vec::new();
+    let mut after_iend = None;
+    for header in img.headers() {
+        let offset = header.offset;
+        let chunk_type = header.chunk_type;
+        if after_iend.is_some() {
+            // Chunks after IEND are reported with the rest of the trailer.
+            break;
+        }
+        let chunk = img.chunk(header);
+        // APNG's chunks are registered but named like private ones.
+        if !is_known(&chunk_type) {
+            findings.push(if chunk_type.is_public() {
+                Finding::UnknownChunk { offset, chunk_type }
+            } else {
+                Finding::PrivateChunk { offset, chunk_type }
+            });
+        }
+        if !chunk_type.is_critical() && header.length as usize > options.max_ancillary {
+            findings.push(Finding::OversizedChunk {
+                offset,
+                chunk_type,
+                length: header.length,
+            });
+        }
+        if !is_compressed(&chunk_type) && chunk.data().len() >= MIN_ENTROPY_LENGTH {
+            let entropy = entropy(chunk.data());
+            if entropy > options.max_entropy {
+                findings.push(Finding::HighEntropy {
+                    offset,
+                    chunk_type,
+                    entropy,
+                });
+            }
+        }
+        let computed = chunk_crc(&chunk_type, chunk.data());
+        if computed != chunk.crc() {
+            findings.push(Finding::CrcMismatch {
+                offset,
+                chunk_type,
+                stored: chunk.crc(),
+                computed,
+            });
+        }
+        if chunk_type.bytes() == *b"IEND" {
+            after_iend = Some(offset + 12 + header.length as usize);
+        }
+    }
+    let len = img.as_bytes().len();
+    let start = after_iend.unwrap_or(img.trailer_offset());
+    if start < len {
+        findings.push(Finding::DataAfterIend {
+            offset: start,
+            length: len - start,
+        });
+    }
+    Ok(findings)
+}
+
+pub struct FileReport {
+    pub path: PathBuf,
+    // The findings, or why the file couldn't be read as a PNG.
+    pub findings: Result<Vec<Finding>, String>,
+}
+
+// The entries of a directory, minus symlinks. Links aren't followed, so a
+// link cycle can't recurse forever.
+fn dir_entries(path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+        if !std::fs::symlink_metadata(&path)?.file_type().is_symlink() {
+            entries.push(path);
+        }
+    }
+    Ok(entries)
+}
+
+fn scan_file(path: &Path, options: &ScanOptions) -> Option<Result<Vec<Finding>, String>> {
+    // Only the signature is read before deciding, so large uploads of other
+    // kinds aren't loaded.
+    match Format::of_path(path) {
+        Ok(Some(Format::Png)) => {}
+        Ok(_) => return None,
+        Err(err) => return Some(Err(err.to_string())),
+    }
+    Some(
+        MappedPng::open(path)
+            .and_then(|img| scan_png(&img, options))
+            .map_err(|err| err.to_string()),
+    )
+}
+
+// Scan every file under `root` that starts with the PNG signature, in path
+// order. Other files are skipped whatever their extension. A directory or
+// file that can't be read is reported and the walk goes on.
+pub fn scan_dir(root: &Path, options: &ScanOptions) -> Result<Vec<FileReport>> {
+    let mut reports = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(path) = pending.pop() {
+        if path.is_dir() {
+            match dir_entries(&path) {
+                Ok(entries) => pending.extend(entries),
+                Err(err) => reports.push(FileReport {
+                    path,
+                    findings: Err(err.to_string()),
+                }),
+            }
+        } else if let Some(findings) = scan_file(&path, options) {
+            reports.push(FileReport { path, findings });
+        }
+    }
+    reports.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(reports)
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+// A JSON document with every report and a summary.
+pub fn to_json(reports: &[FileReport]) -> String {
+    let files: Vec<String> = reports
+        .iter()
+        .map(|report| {
+            let path = json_string(&report.path.to_string_lossy());
+            match &report.findings {
+                Ok(findings) => {
+                    let findings: Vec<String> = findings
+                        .iter()
+                        .map(|finding| {
+                            format!(
+                                "{{\"kind\":{},\"offset\":{},\"message\":{}}}",
+                                json_string(finding.kind()),
+                                finding.offset(),
+                                json_string(&finding.to_string())
+                            )
+                        })
+                        .collect();
+                    format!(
+                        "{{\"path\":{},\"findings\":[{}]}}",
+                        path,
+                        findings.join(",")
+                    )
+                }
+                Err(err) => format!("{{\"path\":{},\"error\":{}}}", path, json_string(err)),
+            }
+        })
+        .collect();
+    let summary = Summary::new(reports);
+    format!(
+        "{{\"files\":[{}],\"summary\":{{\"scanned\":{},\"flagged\":{},\"unreadable\":{},\"findings\":{}}}}}",
+        files.join(","),
+        summary.scanned,
+        summary.flagged,
+        summary.unreadable,
+        summary.findings
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    pub scanned: usize,
+    pub flagged: usize,
+    pub unreadable: usize,
+    pub findings: usize,
+}
+
+impl Summary {
+    pub fn new(reports: &[FileReport]) -> Summary {
+        let mut summary = Summary {
+            scanned: reports.len(),
+            flagged: 0,
+            unreadable: 0,
+            findings: 0,
+        };
+        for report in reports {
+            match &report.findings {
+                Ok(findings) if findings.is_empty() => {}
+                Ok(findings) => {
+                    summary.flagged += 1;
+                    summary.findings += findings.len();
+                }
+                Err(_) => summary.unreadable += 1,
+            }
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::png::Png;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: Vec<u8>) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+    }
+
+    fn testing_png(extra: Vec<Chunk>) -> Vec<u8> {
+        let mut chunks = vec![
+            chunk("IHDR", vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+            chunk("tEXt", b"Comment\0hello".to_vec()),
+        ];
+        chunks.extend(extra);
+        chunks.push(chunk("IDAT", vec![0; 10]));
+        chunks.push(chunk("IEND", Vec::new()));
+        Png::from_chunks(chunks).as_bytes()
+    }
+
+    fn kinds(bytes: Vec<u8>) -> Vec<&'static str> {
+        scan(Bytes::from(bytes), &ScanOptions::default())
+            .unwrap()
+            .iter()
+            .map(|finding| finding.kind())
+            .collect()
+    }
+
+    #[test]
+    fn test_clean_file() {
+        assert!(kinds(testing_png(Vec::new())).is_empty());
+    }
+
+    #[test]
+    fn test_chunk_types() {
+        let bytes = testing_png(vec![
+            chunk("ruSt", vec![1]),
+            chunk("aBCd", vec![2]),
+            chunk("acTL", vec![0, 0, 0, 1, 0, 0, 0, 0]),
+        ]);
+        assert_eq!(kinds(bytes), vec!["private-chunk", "unknown-chunk"]);
+    }
+
+    #[test]
+    fn test_chunk_type_not_utf8() {
+        let odd = ChunkType::try_from([0xff, 0xfe, 0xfd, 0xfc]).unwrap();
+        let bytes = testing_png(vec![Chunk::new(odd, vec![1])]);
+        let findings = scan(Bytes::from(bytes), &ScanOptions::default()).unwrap();
+        assert_eq!(findings.len(), 1);
+        assert!(findings[0].to_string().contains("0xfffefdfc"));
+    }
+
+    #[test]
+    fn test_data_after_iend() {
+        let mut bytes = testing_png(Vec::new());
+        let end = bytes.len();
+        chunk("ruSt", b"legacy".to_vec()).write_to(&mut bytes);
+        bytes.extend_from_slice(b"more");
+        let findings = scan(Bytes::from(bytes), &ScanOptions::default()).unwrap();
+        assert_eq!(
+            findings,
+            vec![Finding::DataAfterIend {
+                offset: end,
+                length: 12 + 6 + 4
+            }]
+        );
+    }
+
+    #[test]
+    fn test_oversized_and_entropy() {
+        let random: Vec<u8> = (0..4096u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        let options = ScanOptions {
+            max_ancillary: 1024,
+            ..Default::default()
+        };
+        let bytes = testing_png(vec![chunk("eXIf", random.clone()), chunk("zTXt", random)]);
+        let findings = scan(Bytes::from(bytes), &options).unwrap();
+        let kinds: Vec<_> = findings.iter().map(|finding| finding.kind()).collect();
+        assert_eq!(
+            kinds,
+            vec!["oversized-chunk", "high-entropy", "oversized-chunk"]
+        );
+        assert!(entropy(&[7; 100]) == 0.0);
+        assert!((entropy(&(0..=255).collect::<Vec<u8>>()) - 8.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_crc_mismatch() {
+        let mut bytes = testing_png(Vec::new());
+        // The last byte of the tEXt CRC.
+        let index = 8 + 25 + 12 + 13 - 1;
+        bytes[index] ^= 0xff;
+        assert_eq!(kinds(bytes), vec!["crc-mismatch"]);
+    }
+
+    #[test]
+    fn test_scan_dir_and_json() {
+        let root = std::env::temp_dir().join(format!(
+            "encodeme_{}_test_scan_dir_and_json",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("nested")).unwrap();
+        std::fs::write(root.join("clean.png"), testing_png(Vec::new())).unwrap();
+        std::fs::write(
+            root.join("nested").join("upload.bin"),
+            testing_png(vec![chunk("ruSt", vec![1])]),
+        )
+        .unwrap();
+        std::fs::write(root.join("notes.txt"), b"not a png").unwrap();
+        let mut broken = testing_png(Vec::new());
+        broken.truncate(40);
+        std::fs::write(root.join("broken.png"), broken).unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&root, root.join("nested").join("loop")).unwrap();
+
+        let reports = scan_dir(&root, &ScanOptions::default()).unwrap();
+        assert_eq!(
+            Summary::new(&reports),
+            Summary {
+                scanned: 3,
+                flagged: 1,
+                unreadable: 1,
+                findings: 1
+            }
+        );
+        let json = to_json(&reports);
+        assert!(json.contains("\"kind\":\"private-chunk\""));
+        assert!(json.ends_with(
+            "\"summary\":{\"scanned\":3,\"flagged\":1,\"unreadable\":1,\"findings\":1}}"
+        ));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unreadable_dir_is_reported() {
+        use std::os::unix::fs::PermissionsExt;
+        let root = std::env::temp_dir().join(format!(
+            "encodeme_{}_test_unreadable_dir_is_reported",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let locked = root.join("locked");
+        std::fs::create_dir_all(&locked).unwrap();
+        std::fs::write(root.join("clean.png"), testing_png(Vec::new())).unwrap();
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+        // Permissions don't stop root, in which case there's nothing to test.
+        let unreadable = std::fs::read_dir(&locked).is_err();
+        let reports = scan_dir(&root, &ScanOptions::default()).unwrap();
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(reports[0].path, root.join("clean.png"));
+        assert!(reports[0].findings.is_ok());
+        if unreadable {
+            assert_eq!(reports.len(), 2);
+            assert_eq!(reports[1].path, locked);
+            assert!(reports[1].findings.is_err());
+        }
+    }
+}
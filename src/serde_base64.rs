@@ -0,0 +1,21 @@
+// Serde helpers that write byte buffers as base64 strings, for use with
+// `#[serde(with = "crate::serde_base64")]`. Decoding works for anything
+// built from a `Vec<u8>`, such as `Bytes` or a fixed-size array.
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{de, Deserialize, Deserializer, Serializer};
+
+pub fn serialize<S: Serializer, T: AsRef<[u8]>>(
+    bytes: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&STANDARD.encode(bytes.as_ref()))
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>, T: TryFrom<Vec<u8>>>(
+    deserializer: D,
+) -> Result<T, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    let bytes = STANDARD.decode(text).map_err(de::Error::custom)?;
+    let len = bytes.len();
+    T::try_from(bytes).map_err(|_| de::Error::invalid_length(len, &"a buffer of the right size"))
+}
@@ -0,0 +1,397 @@
+use crate::pixels::Image;
+
+// Standard detectors for LSB replacement, run on each channel of the decoded
+// samples. All of them look for the traces of flipping the least significant
+// bit: values 2k and 2k+1 becoming equally common (chi-square), groups of
+// samples becoming less smooth in one direction only (RS) and the balance of
+// sample pairs shifting (SPA). LSB matching leaves far fewer of these traces.
+// For a palette image (color type 3) the samples are palette indices, so the
+// results depend on how the palette is ordered rather than on the colors.
+
+// Checkpoints for the sequential chi-square attack, in percent of the channel.
+const CHI_SQUARE_STEPS: usize = 100;
+// The chi-square probability above which a prefix counts as embedded.
+const CHI_SQUARE_THRESHOLD: f64 = 0.5;
+// Pairs of values seen fewer times than this are left out of the chi-square
+// statistic, as the approximation breaks down for small counts.
+const MIN_EXPECTED: f64 = 5.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelReport {
+    pub channel: usize,
+    // Probability that the channel carries an embedded message according to
+    // the chi-square test over the whole channel.
+    pub chi_square_p: Option<f64>,
+    // How far from the start the chi-square probability stays above 0.5, as
+    // a fraction of the channel. Only meaningful for sequential embedding.
+    pub chi_square_rate: Option<f64>,
+    // Estimated fraction of samples carrying message bits.
+    pub rs_rate: Option<f64>,
+    pub spa_rate: Option<f64>,
+}
+
+// The samples of one channel as rows.
+fn channel_rows(image: &Image, channel: usize) -> Vec<Vec<i32>> {
+    let channels = image.channels();
+    image
+        .samples
+        .chunks_exact(image.header.width as usize * channels)
+        .map(|row| {
+            row.iter()
+                .skip(channel)
+                .step_by(channels)
+                .map(|&sample| sample as i32)
+                .collect()
+        })
+        .collect()
+}
+
+// ln(Gamma(x)) with the Lanczos approximation.
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.180_091_729_471_46,
+        -86.505_320_329_416_77,
+        24.014_098_240_830_91,
+        -1.231_739_572_450_155,
+        0.120_865_097_386_617_9e-2,
+        -0.539_523_938_495_3e-5,
+    ];
+    let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+    let mut series = 1.000_000_000_190_015;
+    for (i, coefficient) in COEFFICIENTS.iter().enumerate() {
+        series += coefficient / (x + 1.0 + i as f64);
+    }
+    -tmp + (2.506_628_274_631_000_5 * series / x).ln()
+}
+
+// The regularized upper incomplete gamma function Q(a, x).
+fn gamma_q(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 1.0;
+    }
+    let prefix = (-x + a * x.ln() - ln_gamma(a)).exp();
+    if x < a + 1.0 {
+        // Series for P(a, x).
+        let (mut term, mut sum, mut n) = (1.0 / a, 1.0 / a, a);
+        for _ in 0..1000 {
+            n += 1.0;
+            term *= x / n;
+            sum += term;
+            if term.abs() < sum.abs() * 1e-15 {
+                break;
+            }
+        }
+        1.0 - sum * prefix
+    } else {
+        // Continued fraction for Q(a, x), by the modified Lentz method.
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..1000 {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = b + an / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < 1e-15 {
+                break;
+            }
+        }
+        prefix * h
+    }
+}
+
+// Westfeld and Pfitzmann's test on a histogram: the probability that values
+// 2k and 2k+1 are as evenly split as they would be after embedding.
+fn chi_square_p(histogram: &[u64]) -> Option<f64> {
+    let mut statistic = 0.0;
+    let mut categories = 0;
+    for pair in histogram.chunks_exact(2) {
+        let expected = (pair[0] + pair[1]) as f64 / 2.0;
+        if expected < MIN_EXPECTED {
+            continue;
+        }
+        statistic += (pair[0] as f64 - expected).powi(2) / expected;
+        categories += 1;
+    }
+    if categories < 2 {
+        return None;
+    }
+    Some(gamma_q((categories - 1) as f64 / 2.0, statistic / 2.0))
+}
+
+// The p value of the whole channel, and how much of it, from the start,
+// keeps a p value above the threshold when the test is run on growing
+// prefixes. The latter finds messages written in scan order.
+pub fn chi_square(image: &Image, channel: usize) -> (Option<f64>, Option<f64>) {
+    let values: Vec<i32> = channel_rows(image, channel).concat();
+    let mut histogram = vec![0u64; image.header.max_value() as usize + 2];
+    let mut rate = 0.0;
+    let mut embedded = true;
+    let mut next = 0;
+    for step in 1..=CHI_SQUARE_STEPS {
+        let end = values.len() * step / CHI_SQUARE_STEPS;
+        for &value in &values[next..end] {
+            histogram[value as usize] += 1;
+        }
+        next = end;
+        match chi_square_p(&histogram) {
+            Some(p) if embedded && p > CHI_SQUARE_THRESHOLD => {
+                rate = step as f64 / CHI_SQUARE_STEPS as f64
+            }
+            Some(_) => embedded = false,
+            None => {}
+        }
+    }
+    let p = chi_square_p(&histogram);
+    (p, p.map(|_| rate))
+}
+
+// Smoothness of a group: the sum of differences between neighbours.
+fn smoothness(group: &[i32]) -> i32 {
+    group.windows(2).map(|pair| (pair[1] - pair[0]).abs()).sum()
+}
+
+// Flip the least significant bit (F1) or shift by one the other way (F-1).
+fn flip(value: i32, direction: i32) -> i32 {
+    match direction {
+        1 => value ^ 1,
+        -1 => ((value + 1) ^ 1) - 1,
+        _ => value,
+    }
+}
+
+// The share of regular and singular groups of four neighbouring samples
+// under the mask [0, 1, 1, 0] with the given flipping direction.
+fn regular_singular(rows: &[Vec<i32>], direction: i32) -> (f64, f64) {
+    const MASK: [i32; 4] = [0, 1, 1, 0];
+    let (mut regular, mut singular, mut groups) = (0, 0, 0);
+    for row in rows {
+        for group in row.chunks_exact(MASK.len()) {
+            let flipped: Vec<i32> = group
+                .iter()
+                .zip(MASK)
+                .map(|(&value, mask)| flip(value, mask * direction))
+                .collect();
+            let before = smoothness(group);
+            let after = smoothness(&flipped);
+            if after > before {
+                regular += 1;
+            } else if after < before {
+                singular += 1;
+            }
+            groups += 1;
+        }
+    }
+    if groups == 0 {
+        return (0.0, 0.0);
+    }
+    (
+        regular as f64 / groups as f64,
+        singular as f64 / groups as f64,
+    )
+}
+
+// Fridrich, Goljan and Du's RS analysis.
+pub fn rs(image: &Image, channel: usize) -> Option<f64> {
+    let rows = channel_rows(image, channel);
+    let flipped: Vec<Vec<i32>> = rows
+        .iter()
+        .map(|row| row.iter().map(|&value| value ^ 1).collect())
+        .collect();
+    let (r_m, s_m) = regular_singular(&rows, 1);
+    let (r_neg_m, s_neg_m) = regular_singular(&rows, -1);
+    let (r_m_flipped, s_m_flipped) = regular_singular(&flipped, 1);
+    let (r_neg_m_flipped, s_neg_m_flipped) = regular_singular(&flipped, -1);
+    let d0 = r_m - s_m;
+    let d1 = r_m_flipped - s_m_flipped;
+    let d_neg0 = r_neg_m - s_neg_m;
+    let d_neg1 = r_neg_m_flipped - s_neg_m_flipped;
+    let a = 2.0 * (d1 + d0);
+    let b = d_neg0 - d_neg1 - d1 - 3.0 * d0;
+    let c = d0 - d_neg0;
+    let x = smaller_root(a, b, c)?;
+    Some((x / (x - 0.5)).clamp(0.0, 1.0))
+}
+
+// The root of ax² + bx + c closest to zero.
+fn smaller_root(a: f64, b: f64, c: f64) -> Option<f64> {
+    if a.abs() < 1e-12 {
+        if b.abs() < 1e-12 {
+            return None;
+        }
+        return Some(-c / b);
+    }
+    // Near full embedding the estimate can make the roots complex; the real
+    // part is the best guess left.
+    let sqrt = (b * b - 4.0 * a * c).max(0.0).sqrt();
+    let (x1, x2) = ((-b + sqrt) / (2.0 * a), (-b - sqrt) / (2.0 * a));
+    Some(if x1.abs() < x2.abs() { x1 } else { x2 })
+}
+
+// Dumitrescu, Wu and Wang's sample pair analysis on horizontal neighbours.
+pub fn spa(image: &Image, channel: usize) -> Option<f64> {
+    let (mut x, mut y, mut k, mut pairs) = (0u64, 0u64, 0u64, 0u64);
+    for row in channel_rows(image, channel) {
+        for pair in row.windows(2) {
+            let (u, v) = (pair[0], pair[1]);
+            if (v % 2 == 0 && u < v) || (v % 2 == 1 && u > v) {
+                x += 1;
+            }
+            if (v % 2 == 0 && u > v) || (v % 2 == 1 && u < v) {
+                y += 1;
+            }
+            if u >> 1 == v >> 1 {
+                k += 1;
+            }
+            pairs += 1;
+        }
+    }
+    if k == 0 {
+        return None;
+    }
+    let (x, y, k, pairs) = (x as f64, y as f64, k as f64, pairs as f64);
+    let a = 2.0 * k;
+    let b = 2.0 * (2.0 * x - pairs);
+    let c = y - x;
+    let sqrt = (b * b - 4.0 * a * c).max(0.0).sqrt();
+    let beta = ((-b + sqrt) / (2.0 * a)).min((-b - sqrt) / (2.0 * a));
+    Some((2.0 * beta).clamp(0.0, 1.0))
+}
+
+// Run every detector on every channel.
+pub fn analyze(image: &Image) -> Vec<ChannelReport> {
+    (0..image.channels())
+        .map(|channel| {
+            // A flat channel, like an opaque alpha channel, says nothing.
+            let mut values = image.samples.iter().skip(channel).step_by(image.channels());
+            let first = values.next();
+            if values.all(|value| Some(value) == first) {
+                return ChannelReport {
+                    channel,
+                    chi_square_p: None,
+                    chi_square_rate: None,
+                    rs_rate: None,
+                    spa_rate: None,
+                };
+            }
+            let (chi_square_p, chi_square_rate) = chi_square(image, channel);
+            ChannelReport {
+                channel,
+                chi_square_p,
+                chi_square_rate,
+                rs_rate: rs(image, channel),
+                spa_rate: spa(image, channel),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixels::ImageHeader;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    // A smooth grayscale image with a little noise, like a photo.
+    fn testing_image(rng: &mut ChaCha20Rng) -> Image {
+        let (width, height) = (256, 256);
+        let mut samples = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let value = 128.0
+                    + 60.0 * (x as f64 / 23.0).sin() * (y as f64 / 31.0).cos()
+                    + rng.gen_range(-1.0..1.0);
+                samples.push(value.round() as u16);
+            }
+        }
+        Image {
+            header: ImageHeader {
+                width,
+                height,
+                bit_depth: 8,
+                color_type: 0,
+                interlace: 0,
+            },
+            samples,
+        }
+    }
+
+    // Replace the low bit of a share of the samples with random bits.
+    fn embed(image: &Image, rate: f64, rng: &mut ChaCha20Rng) -> Image {
+        let mut image = image.clone();
+        for sample in image.samples.iter_mut() {
+            if rng.gen_bool(rate) {
+                *sample = (*sample & !1) | rng.gen_range(0..2);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn test_gamma_q() {
+        // Chi-square survival function with 2 degrees of freedom is exp(-x/2).
+        for x in [0.5, 2.0, 10.0] {
+            assert!((gamma_q(1.0, x / 2.0) - (-x / 2.0).exp()).abs() < 1e-9);
+        }
+        assert!((ln_gamma(5.0) - 24f64.ln()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_clean_image() {
+        let mut rng = ChaCha20Rng::seed_from_u64(1);
+        let image = testing_image(&mut rng);
+        let report = analyze(&image);
+        assert_eq!(report.len(), 1);
+        assert!(report[0].chi_square_p.unwrap() < 0.01);
+        assert!(report[0].rs_rate.unwrap() < 0.05);
+        assert!(report[0].spa_rate.unwrap() < 0.05);
+    }
+
+    #[test]
+    fn test_rate_estimates() {
+        let mut rng = ChaCha20Rng::seed_from_u64(2);
+        let image = testing_image(&mut rng);
+        for rate in [0.25, 0.5] {
+            let stego = embed(&image, rate, &mut rng);
+            let rs = rs(&stego, 0).unwrap();
+            let spa = spa(&stego, 0).unwrap();
+            assert!((rs - rate).abs() < 0.1, "rs {} for {}", rs, rate);
+            assert!((spa - rate).abs() < 0.1, "spa {} for {}", spa, rate);
+        }
+    }
+
+    #[test]
+    fn test_sequential_embedding() {
+        let mut rng = ChaCha20Rng::seed_from_u64(4);
+        let mut stego = testing_image(&mut rng);
+        let half = stego.samples.len() / 2;
+        for sample in &mut stego.samples[..half] {
+            *sample = (*sample & !1) | rng.gen_range(0..2);
+        }
+        let (_, rate) = chi_square(&stego, 0);
+        assert!(rate.unwrap() >= 0.5);
+    }
+
+    #[test]
+    fn test_full_embedding() {
+        let mut rng = ChaCha20Rng::seed_from_u64(3);
+        let stego = embed(&testing_image(&mut rng), 1.0, &mut rng);
+        let (p, rate) = chi_square(&stego, 0);
+        assert!(p.unwrap() > 0.9);
+        assert_eq!(rate, Some(1.0));
+        assert!(rs(&stego, 0).unwrap() > 0.8);
+        assert!(spa(&stego, 0).unwrap() > 0.8);
+    }
+}
@@ -0,0 +1,100 @@
+use crate::{chunk::Chunk, chunk::MAX_LENGTH, chunk_type::ChunkType, png::Png};
+use anyhow::Result;
+use bytes::Bytes;
+use std::str::FromStr;
+
+// A message stored as the text of a tEXt chunk, which viewers show as image
+// metadata. The chunk data is the keyword, a NUL separator and the text, and
+// the text itself can't hold a NUL.
+pub const KEYWORD: &str = "encodeme";
+pub const CAPACITY: usize = MAX_LENGTH - KEYWORD.len() - 1;
+
+fn text_type() -> ChunkType {
+    ChunkType::from_str("tEXt").unwrap()
+}
+
+fn is_payload_chunk(chunk: &Chunk) -> bool {
+    chunk.chunk_type == text_type()
+        && chunk.data().len() > KEYWORD.len()
+        && chunk.data().starts_with(KEYWORD.as_bytes())
+        && chunk.data()[KEYWORD.len()] == 0
+}
+
+pub fn embed(png: &mut Png, payload: &[u8]) -> Result<()> {
+    if payload.contains(&0) {
+        return Err(anyhow::anyhow!(
+            "A tEXt chunk can't hold a message with a NUL byte"
+        ));
+    }
+    if png.chunks().iter().any(is_payload_chunk) {
+        return Err(anyhow::anyhow!(
+            "The image already holds a message in a {} tEXt chunk",
+            KEYWORD
+        ));
+    }
+    let data = [KEYWORD.as_bytes(), &[0], payload].concat();
+    png.append_chunk(Chunk::new(text_type(), data));
+    Ok(())
+}
+
+pub fn extract(chunks: &[Chunk]) -> Option<Bytes> {
+    chunks
+        .iter()
+        .find(|chunk| is_payload_chunk(chunk))
+        .map(|chunk| chunk.data.slice(KEYWORD.len() + 1..))
+}
+
+pub fn remove(png: &mut Png) -> Result<Bytes> {
+    let payload =
+        extract(png.chunks()).ok_or_else(|| anyhow::anyhow!("No {} tEXt chunk found", KEYWORD))?;
+    let chunks = png
+        .chunks()
+        .iter()
+        .filter(|chunk| !is_payload_chunk(chunk))
+        .cloned()
+        .collect();
+    png.set_chunks(chunks);
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            Chunk::new(
+                ChunkType::from_str("tEXt").unwrap(),
+                b"Comment\0Just a comment".to_vec(),
+            ),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ])
+    }
+
+    #[test]
+    fn test_embed_and_extract() {
+        let mut png = testing_png();
+        embed(&mut png, b"My secret message").unwrap();
+        let png = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        assert_eq!(png.chunks().len(), 3);
+        assert_eq!(png.chunks()[1].data(), b"encodeme\0My secret message");
+        assert_eq!(&extract(png.chunks()).unwrap()[..], b"My secret message");
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut png = testing_png();
+        embed(&mut png, b"My secret message").unwrap();
+        assert!(embed(&mut png, b"Another message").is_err());
+        assert_eq!(&remove(&mut png).unwrap()[..], b"My secret message");
+        assert_eq!(png.as_bytes(), testing_png().as_bytes());
+        assert!(remove(&mut png).is_err());
+    }
+
+    #[test]
+    fn test_nul_is_rejected() {
+        let mut png = testing_png();
+        assert!(embed(&mut png, b"My\0secret").is_err());
+        assert!(extract(png.chunks()).is_none());
+    }
+}
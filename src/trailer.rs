@@ -0,0 +1,127 @@
+use crate::png::Png;
+use anyhow::Result;
+use bytes::Bytes;
+
+// A payload stored after IEND is framed by a footer so it can be found at the
+// end of the file without a chunk to point at it:
+//
+//     payload | length (u32, BE) | crc32 of payload (u32, BE) | "ENCM"
+//
+// Anything already in the trailer stays in front of the payload.
+pub const MAGIC: [u8; 4] = *b"ENCM";
+pub const FOOTER_LEN: usize = 12;
+
+pub fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(payload.len() + FOOTER_LEN);
+    bytes.extend_from_slice(payload);
+    bytes.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(&crc32fast::hash(payload).to_be_bytes());
+    bytes.extend_from_slice(&MAGIC);
+    bytes
+}
+
+// Locate a framed payload at the end of `trailer`. Returns the offset where the
+// payload starts, or None if the trailer doesn't end with a valid frame.
+pub fn find(trailer: &[u8]) -> Option<usize> {
+    if trailer.len() < FOOTER_LEN || trailer[trailer.len() - 4..] != MAGIC {
+        return None;
+    }
+    let footer = trailer.len() - FOOTER_LEN;
+    let length = u32::from_be_bytes(trailer[footer..footer + 4].try_into().unwrap()) as usize;
+    let crc = u32::from_be_bytes(trailer[footer + 4..footer + 8].try_into().unwrap());
+    let start = footer.checked_sub(length)?;
+    if crc32fast::hash(&trailer[start..footer]) != crc {
+        return None;
+    }
+    Some(start)
+}
+
+pub fn extract(trailer: &Bytes) -> Option<Bytes> {
+    let start = find(trailer)?;
+    Some(trailer.slice(start..trailer.len() - FOOTER_LEN))
+}
+
+// Without IEND the bytes after the last chunk would be read back as more
+// chunks, so anything appended to the file needs one to sit behind.
+pub fn check_iend(png: &Png) -> Result<()> {
+    match png.chunks().last() {
+        Some(chunk) if chunk.chunk_type.to_string() == "IEND" => Ok(()),
+        _ => Err(anyhow::anyhow!(
+            "The PNG doesn't end with an IEND chunk, so nothing can be stored after it"
+        )),
+    }
+}
+
+pub fn embed(png: &mut Png, payload: &[u8]) -> Result<()> {
+    check_iend(png)?;
+    let mut trailer = png.trailer().to_vec();
+    trailer.extend(frame(payload));
+    png.set_trailer(Bytes::from(trailer));
+    Ok(())
+}
+
+// Remove the framed payload from the trailer, keeping whatever preceded it.
+pub fn remove(png: &mut Png) -> Result<Bytes> {
+    let trailer = png.trailer().clone();
+    let start = find(&trailer).ok_or_else(|| anyhow::anyhow!("No payload found after IEND"))?;
+    png.set_trailer(trailer.slice(..start));
+    Ok(trailer.slice(start..trailer.len() - FOOTER_LEN))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            Chunk::new(
+                ChunkType::from_str("FrSt").unwrap(),
+                b"I am the first chunk".to_vec(),
+            ),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ])
+    }
+
+    #[test]
+    fn test_embed_and_extract() {
+        let mut png = testing_png();
+        embed(&mut png, b"My secret message").unwrap();
+        let png = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        assert_eq!(png.chunks().len(), 2);
+        assert_eq!(&extract(png.trailer()).unwrap()[..], b"My secret message");
+    }
+
+    #[test]
+    fn test_existing_trailer_is_kept() {
+        let mut png = testing_png();
+        png.set_trailer(Bytes::from_static(b"appended data"));
+        embed(&mut png, b"My secret message").unwrap();
+        assert_eq!(&remove(&mut png).unwrap()[..], b"My secret message");
+        assert_eq!(&png.trailer()[..], b"appended data");
+    }
+
+    #[test]
+    fn test_no_payload() {
+        let mut png = testing_png();
+        png.set_trailer(Bytes::from_static(b"appended data"));
+        assert!(extract(png.trailer()).is_none());
+        assert!(remove(&mut png).is_err());
+    }
+
+    #[test]
+    fn test_no_iend() {
+        let mut png = Png::from_chunks(testing_png().chunks()[..1].to_vec());
+        assert!(embed(&mut png, b"My secret message").is_err());
+        assert!(png.trailer().is_empty());
+    }
+
+    #[test]
+    fn test_corrupted_payload() {
+        let mut framed = frame(b"My secret message");
+        framed[0] ^= 1;
+        assert!(find(&framed).is_none());
+    }
+}
@@ -0,0 +1,268 @@
+use crate::{chunk::Chunk, chunk_type::ChunkType, pixels::Image, png::Png};
+use anyhow::Result;
+use std::fmt;
+
+// Copying ancillary chunks from one image to another, following the rules
+// the PNG specification sets for editors. A chunk whose safe-to-copy bit is
+// clear describes the image data, so it is only copied when the target shows
+// the same image as the source, or when forced. Every chunk is placed in the
+// same region relative to PLTE and IDAT as it had in the source.
+
+// Ancillary chunks that may appear at most once.
+const SINGLE: [&str; 19] = [
+    "cHRM", "gAMA", "iCCP", "sBIT", "sRGB", "cICP", "mDCV", "cLLI", "tRNS", "bKGD", "hIST", "pHYs",
+    "eXIf", "tIME", "oFFs", "pCAL", "sCAL", "sTER", "acTL",
+];
+
+// Animation chunks refer to the frames of their own file.
+const ANIMATION: [&str; 3] = ["acTL", "fcTL", "fdAT"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Region {
+    BeforePlte,
+    BeforeIdat,
+    AfterIdat,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransplantOptions {
+    // Copy chunks that are not safe to copy even if the images differ.
+    pub force: bool,
+    // Only copy chunks of these types; all ancillary chunks when empty.
+    pub types: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Copied,
+    Unsafe,
+    AlreadyPresent,
+    Animation,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Copied => write!(f, "copied"),
+            Outcome::Unsafe => write!(f, "skipped, not safe to copy to a different image"),
+            Outcome::AlreadyPresent => write!(f, "skipped, the target already has one"),
+            Outcome::Animation => write!(f, "skipped, animation chunks can't be transplanted"),
+        }
+    }
+}
+
+fn is_one_of(chunk_type: &ChunkType, types: &[&str]) -> bool {
+    types
+        .iter()
+        .any(|name| name.as_bytes() == chunk_type.bytes())
+}
+
+fn region(png: &Png, index: usize) -> Region {
+    let position = |name: &[u8; 4]| {
+        png.chunks()
+            .iter()
+            .position(|chunk| chunk.chunk_type().bytes() == *name)
+    };
+    match (position(b"PLTE"), position(b"IDAT")) {
+        (Some(plte), _) if index < plte => Region::BeforePlte,
+        (_, Some(idat)) if index < idat => Region::BeforeIdat,
+        (None, None) => Region::BeforeIdat,
+        _ => Region::AfterIdat,
+    }
+}
+
+// Where a chunk for `region` goes in the target: after any chunks already
+// inserted there, so their order is kept.
+fn insert_position(png: &Png, region: Region) -> usize {
+    let chunks = png.chunks();
+    let position = |name: &[u8; 4]| {
+        chunks
+            .iter()
+            .position(|chunk| chunk.chunk_type().bytes() == *name)
+    };
+    let before_idat = position(b"IDAT")
+        .or(position(b"IEND"))
+        .unwrap_or(chunks.len());
+    match region {
+        Region::BeforePlte => position(b"PLTE").unwrap_or(before_idat),
+        Region::BeforeIdat => before_idat,
+        Region::AfterIdat => position(b"IEND").unwrap_or(chunks.len()),
+    }
+}
+
+fn critical_data<'a>(png: &'a Png, name: &[u8; 4]) -> Vec<&'a [u8]> {
+    png.chunks()
+        .iter()
+        .filter(|chunk| chunk.chunk_type().bytes() == *name)
+        .map(|chunk| chunk.data())
+        .collect()
+}
+
+// Whether the two files show the same image: same header and palette, and
+// the same image data or at least the same decoded samples.
+pub fn same_image(a: &Png, b: &Png) -> bool {
+    if critical_data(a, b"IHDR") != critical_data(b, b"IHDR")
+        || critical_data(a, b"PLTE") != critical_data(b, b"PLTE")
+    {
+        return false;
+    }
+    if critical_data(a, b"IDAT").concat() == critical_data(b, b"IDAT").concat() {
+        return true;
+    }
+    match (Image::from_png(a), Image::from_png(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+// Copy the ancillary chunks of `source` into `target`, returning what
+// happened to each of them in source order.
+pub fn transplant(
+    source: &Png,
+    target: &mut Png,
+    options: &TransplantOptions,
+) -> Result<Vec<(ChunkType, Outcome)>> {
+    for name in &options.types {
+        name.parse::<ChunkType>()
+            .map_err(|_| anyhow::anyhow!("Invalid chunk type: {}", name))?;
+    }
+    let same_image = same_image(source, target);
+    let mut outcomes = Vec::new();
+    let mut seen_iend = false;
+    for (index, chunk) in source.chunks().iter().enumerate() {
+        let chunk_type = *chunk.chunk_type();
+        // Chunks after IEND belong to the trailer, not the image.
+        seen_iend |= chunk_type.bytes() == *b"IEND";
+        if seen_iend || chunk_type.is_critical() {
+            continue;
+        }
+        let name = chunk_type.to_string();
+        if !options.types.is_empty() && !options.types.contains(&name) {
+            continue;
+        }
+        let outcome = if is_one_of(&chunk_type, &ANIMATION) {
+            Outcome::Animation
+        } else if !chunk_type.is_safe_to_copy() && !same_image && !options.force {
+            Outcome::Unsafe
+        } else if is_one_of(&chunk_type, &SINGLE)
+            && target
+                .chunks()
+                .iter()
+                .any(|x| *x.chunk_type() == chunk_type)
+        {
+            Outcome::AlreadyPresent
+        } else {
+            let position = insert_position(target, region(source, index));
+            let mut chunks = target.chunks().to_vec();
+            chunks.insert(position, Chunk::from_data(chunk_type, chunk.data.clone()));
+            target.set_chunks(chunks);
+            Outcome::Copied
+        };
+        outcomes.push((chunk_type, outcome));
+    }
+    Ok(outcomes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    fn ihdr(color_type: u8) -> Chunk {
+        chunk("IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, color_type, 0, 0, 0])
+    }
+
+    fn source() -> Png {
+        Png::from_chunks(vec![
+            ihdr(3),
+            chunk("gAMA", &[0, 0, 177, 143]),
+            chunk("PLTE", &[0, 0, 0]),
+            chunk("tRNS", &[0]),
+            chunk("pHYs", &[0, 0, 11, 19, 0, 0, 11, 19, 1]),
+            chunk("IDAT", &[1, 2, 3]),
+            chunk("tEXt", b"Title\0source"),
+            chunk("ruSt", b"payload"),
+            chunk("IEND", &[]),
+        ])
+    }
+
+    fn target() -> Png {
+        Png::from_chunks(vec![
+            ihdr(3),
+            chunk("PLTE", &[255, 255, 255]),
+            chunk("IDAT", &[4, 5, 6]),
+            chunk("IEND", &[]),
+        ])
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_safe_chunks_only() {
+        let mut target = target();
+        let outcomes = transplant(&source(), &mut target, &Default::default()).unwrap();
+        assert_eq!(
+            types(&target),
+            vec!["IHDR", "PLTE", "pHYs", "IDAT", "tEXt", "ruSt", "IEND"]
+        );
+        let unsafe_count = outcomes
+            .iter()
+            .filter(|(_, outcome)| *outcome == Outcome::Unsafe)
+            .count();
+        assert_eq!(unsafe_count, 2);
+    }
+
+    #[test]
+    fn test_forced_keeps_regions() {
+        let mut target = target();
+        let options = TransplantOptions {
+            force: true,
+            ..Default::default()
+        };
+        transplant(&source(), &mut target, &options).unwrap();
+        assert_eq!(
+            types(&target),
+            vec!["IHDR", "gAMA", "PLTE", "tRNS", "pHYs", "IDAT", "tEXt", "ruSt", "IEND"]
+        );
+    }
+
+    #[test]
+    fn test_same_image_copies_everything() {
+        let mut target = Png::from_chunks(
+            source()
+                .chunks()
+                .iter()
+                .filter(|chunk| chunk.chunk_type().is_critical())
+                .cloned()
+                .collect(),
+        );
+        let outcomes = transplant(&source(), &mut target, &Default::default()).unwrap();
+        assert!(outcomes
+            .iter()
+            .all(|(_, outcome)| *outcome == Outcome::Copied));
+        assert_eq!(types(&target), types(&source()));
+    }
+
+    #[test]
+    fn test_selected_types_and_duplicates() {
+        let mut target = target();
+        target.insert_chunk(2, chunk("pHYs", &[0; 9])).unwrap();
+        let options = TransplantOptions {
+            types: vec!["pHYs".to_string(), "tEXt".to_string()],
+            ..Default::default()
+        };
+        let outcomes = transplant(&source(), &mut target, &options).unwrap();
+        assert_eq!(outcomes.len(), 2);
+        assert_eq!(outcomes[0].1, Outcome::AlreadyPresent);
+        assert_eq!(outcomes[1].1, Outcome::Copied);
+    }
+}